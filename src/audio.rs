@@ -0,0 +1,140 @@
+use crate::sound::{Sound,C8_PATTERN_SIZE};
+
+pub const C8_DEFAULT_TONE:f32 = 440.0;
+pub const C8_DEFAULT_VOLUME:f32 = 0.25;
+///Seconds the volume takes to ramp between silent and full, so the beep starts and stops without clicking
+pub const C8_AUDIO_RAMP:f32 = 0.005;
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+}
+
+pub const C8_WAVEFORMS:[(&str,Waveform);3] = [
+    ("square",Waveform::Square),
+    ("triangle",Waveform::Triangle),
+    ("sine",Waveform::Sine),
+];
+
+impl Waveform {
+    pub fn from_name(name:&str)->Option<Self> {
+        C8_WAVEFORMS.iter().find(|(n,_)|*n == name).map(|(_,w)|*w)
+    }
+    ///One period, phase runs from 0 to 1 and the result from -1 to 1
+    #[inline]
+    pub fn sample(&self,phase:f32)->f32 {
+        match self {
+            Waveform::Square=>if phase < 0.5 {1.0} else {-1.0},
+            Waveform::Triangle=>4.0*(phase-0.5).abs()-1.0,
+            Waveform::Sine=>(phase*std::f32::consts::TAU).sin(),
+        }
+    }
+}
+
+///How the beeper sounds
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub struct AudioSettings {
+    ///Tone in hz, unused while an XO-CHIP pattern is loaded
+    pub frequency:f32,
+    pub waveform:Waveform,
+    ///0 to 1
+    pub volume:f32,
+    pub muted:bool,
+}
+
+impl Default for AudioSettings {
+    #[inline]
+    fn default()->Self {
+        Self{frequency:C8_DEFAULT_TONE,waveform:Waveform::Square,volume:C8_DEFAULT_VOLUME,muted:false}
+    }
+}
+
+///Where the frontend sends the beeper state once per frame
+pub trait AudioSink {
+    fn update(&mut self,sound:&Sound);
+    ///Stops the beep, for when the machine is paused
+    fn silence(&mut self) {
+        self.update(&Sound::pack())
+    }
+}
+
+///A sink for frontends without sound
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    #[inline]
+    fn update(&mut self,_sound:&Sound) {}
+}
+
+///Turns the beeper state into samples, the audio backend pulls them with fill
+#[derive(Clone)]
+pub struct Synth {
+    settings:AudioSettings,
+    sample_rate:f32,
+    playing:bool,
+    pattern:Option<[u8;C8_PATTERN_SIZE]>,
+    ///XO-CHIP pattern bits per second
+    rate:f64,
+    ///Position within the tone's period, 0 to 1
+    phase:f32,
+    ///Bit of the pattern being played
+    position:f64,
+    gain:f32,
+}
+
+impl Synth {
+    pub fn new(sample_rate:u32,settings:AudioSettings)->Self {
+        Self{
+            settings,
+            sample_rate:sample_rate.max(1) as f32,
+            playing:false,
+            pattern:None,
+            rate:0.0,
+            phase:0.0,
+            position:0.0,
+            gain:0.0,
+        }
+    }
+    #[inline]
+    pub fn settings(&self)->&AudioSettings {
+        &self.settings
+    }
+    #[inline]
+    pub fn settings_mut(&mut self)->&mut AudioSettings {
+        &mut self.settings
+    }
+    #[inline]
+    pub fn sample_rate(&self)->u32 {
+        self.sample_rate as u32
+    }
+    ///Writes the next samples, each from -volume to volume
+    pub fn fill(&mut self,out:&mut [f32]) {
+        let volume = if self.settings.muted {0.0} else {self.settings.volume.clamp(0.0,1.0)};
+        let target = if self.playing {volume} else {0.0};
+        let ramp = volume.max(f32::EPSILON)/(self.sample_rate*C8_AUDIO_RAMP);
+        let bits = (C8_PATTERN_SIZE*8) as f64;
+        for sample in out.iter_mut() {
+            self.gain = if self.gain < target {(self.gain+ramp).min(target)} else {(self.gain-ramp).max(target)};
+            let value = match &self.pattern {
+                Some(pattern)=>{
+                    let bit = self.position as usize;
+                    if pattern[bit/8]&(0x80>>(bit%8)) > 0 {1.0} else {-1.0}
+                }
+                None=>self.settings.waveform.sample(self.phase)
+            };
+            *sample = value*self.gain;
+            self.phase = (self.phase+self.settings.frequency/self.sample_rate).fract();
+            self.position = (self.position+self.rate/self.sample_rate as f64)%bits;
+        }
+    }
+}
+
+impl AudioSink for Synth {
+    fn update(&mut self,sound:&Sound) {
+        self.playing = sound.is_playing();
+        self.pattern = sound.pattern().copied();
+        self.rate = sound.playback_rate();
+    }
+}
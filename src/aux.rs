@@ -1,4 +1,5 @@
 use crate::{graphics::{PixMap},sound::Sound,keyboard::*};
+use crate::savestate::Snapshot;
 
 pub struct Auxillary {
     graphics_pack:PixMap,
@@ -9,8 +10,8 @@ pub struct Auxillary {
 impl Auxillary {
     pub fn pack(graphics_pack:PixMap,sound_pack:Sound) ->Self{
         Self{
-            graphics_pack:graphics_pack,
-            sound_pack:sound_pack,
+            graphics_pack,
+            sound_pack,
             keyboard:Keyboard::new(),
         }
     }
@@ -35,4 +36,21 @@ impl Auxillary {
     pub(crate) fn sound(&self)->Sound {
         self.sound_pack
     }
-}
\ No newline at end of file
+    #[inline]
+    pub(crate) fn sound_mut(&mut self)->&mut Sound {
+        &mut self.sound_pack
+    }
+}
+
+impl Snapshot for Auxillary {
+    fn save(&self,out:&mut Vec<u8>) {
+        self.graphics_pack.save(out);
+        self.sound_pack.save(out);
+        self.keyboard.save(out);
+    }
+    fn load(&mut self,input:&mut &[u8])->std::io::Result<()> {
+        self.graphics_pack.load(input)?;
+        self.sound_pack.load(input)?;
+        self.keyboard.load(input)
+    }
+}
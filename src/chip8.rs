@@ -1,153 +1,330 @@
 use crate::cpu::*;
-use std::sync::{Arc,Mutex};
 use crate::errors::*;
-use crate::keyboard::{Keyboard,Key};
-use crate::graphics::PixMap;
-use crate::sound::Sound;
+use crate::keyboard::{Keyboard,Key,KeyEvent};
+use crate::graphics::{PixMap,C8_LORES_WIDTH,C8_LORES_HEIGHT,C8_HIRES_WIDTH,C8_HIRES_HEIGHT};
+use crate::sound::{Sound,C8_PATTERN_SIZE};
 use crate::aux::Auxillary;
-use crate::fonts::{FontPack,C8_CLASSIC_FONT};
-pub const C8_TIMER_RATE:std::time::Duration = std::time::Duration::from_micros(16700);
-pub const C8_CPU_RATE:  std::time::Duration = std::time::Duration::from_micros(1000);
-
-macro_rules! x_in_xkk {
-    ($inst:expr) => {
-        (($inst>>8)&0xF) as u8
-    };
-}
-macro_rules! kk_in_xkk {
-    ($inst:expr) => {
-        ($inst&0xFF) as u8
-    };
-}
-macro_rules! x_in_xy {
-    ($inst:expr) => {
-        x_in_xkk!($inst)
-    };
-}
-macro_rules! y_in_xy {
-    ($inst:expr) => {
-        (($inst>>4)&0xF) as u8
-    };
-}
-macro_rules! last_nibble {
-    ($inst:expr) => {
-        ($inst&0xF) as u8
-    };
-}
-
-
+use crate::quirks::Quirks;
+use crate::platform::{Profile,C8_DEFAULT_IPF};
+use crate::fonts::{FontPack,C8_BIG_FONT_START,C8_FONT_PACK_SIZE};
+use crate::instruction::Instruction;
+use crate::savestate::*;
+use std::collections::VecDeque;
+///One 60hz frame, the timers tick once per frame
+pub const C8_TIMER_RATE:std::time::Duration = std::time::Duration::from_nanos(16_666_667);
+///Executed instruction addresses kept for crash reports
+pub const C8_PC_HISTORY:usize = 32;
 
+///Which family of opcodes the interpreter accepts
+#[derive(Copy,Clone,PartialEq,PartialOrd,Debug)]
+pub enum InstructionSet {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
 
+impl InstructionSet {
+    ///The largest resolution the set's instructions can switch to
+    #[inline]
+    pub fn display(&self)->(usize,usize) {
+        if *self >= InstructionSet::SuperChip {
+            (C8_HIRES_WIDTH,C8_HIRES_HEIGHT)
+        } else {
+            (C8_LORES_WIDTH,C8_LORES_HEIGHT)
+        }
+    }
+}
 
 pub struct Chip8{
     pub cpu:DefaultCpu,
     state:CpuState,
-    cosmic:bool,
-    needs_key:bool,
-    last_exec:std::time::Instant,
-    cpu_rate:std::time::Duration,
-    timer_rate:std::time::Duration
+    quirks:Quirks,
+    set:InstructionSet,
+    program_start:u16,
+    instructions_per_frame:u32,
+    ///Where FX29 and FX30 find the fonts
+    font_base:u16,
+    ///FX0A blocks the CPU here until it has a key
+    key_wait:KeyWait,
+    ///Set by a DXYN under the display-wait quirk, ends the current frame
+    waiting_vblank:bool,
+    ///Instructions run so far in the current frame
+    frame_cycles:u32,
+    history:VecDeque<u16>,
+    ///Instructions run since the machine was created, not part of save states
+    executed:u64,
+    ///Frames run, key events are queued against it
+    frames:u64,
+}
+
+///Where an FX0A is in waiting for a key, the CPU runs nothing else meanwhile
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum KeyWait {
+    Idle,
+    ///Waiting for any key to go down, the key goes into VX
+    Press(u8),
+    ///The key is down, waiting for it to come back up while the beeper sounds
+    Release(u8,Key),
+}
+
+impl Snapshot for KeyWait {
+    fn save(&self,out:&mut Vec<u8>) {
+        match *self {
+            KeyWait::Idle=>out.push(0),
+            KeyWait::Press(x)=>out.extend_from_slice(&[1,x]),
+            KeyWait::Release(x,key)=>out.extend_from_slice(&[2,x,key.into_byte()]),
+        }
+    }
+    fn load(&mut self,input:&mut &[u8])->std::io::Result<()> {
+        *self = match get_u8(input)? {
+            0=>KeyWait::Idle,
+            1=>KeyWait::Press(get_u8(input)?&0xF),
+            2=>{
+                let x = get_u8(input)?&0xF;
+                let key = Key::from_byte(get_u8(input)?).ok_or_else(||invalid("bad key"))?;
+                KeyWait::Release(x,key)
+            }
+            _=>return Err(invalid("bad key wait"))
+        };
+        Ok(())
+    }
 }
 
-#[derive(Clone)]
+///What one Chip8::frame_step did
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct FrameStep {
+    ///An instruction completed, false while FX0A is still waiting for its key
+    pub ran:bool,
+    ///The frame ended and the timers ticked
+    pub ended:bool,
+}
+
+///Whether the interpreter is running, and its two 60hz timers
+#[derive(Clone,Copy,Default,Debug)]
 pub struct CpuState {
-    executing:Arc<Mutex<bool>>,
-    delay_timer:Arc<Mutex<u8>>,
-    sound_timer:Arc<Mutex<u8>>,
-} 
+    executing:bool,
+    delay_timer:u8,
+    sound_timer:u8,
+}
+
+impl Snapshot for CpuState {
+    fn save(&self,out:&mut Vec<u8>) {
+        put_bool(out,self.executing);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+    }
+    fn load(&mut self,input:&mut &[u8])->std::io::Result<()> {
+        self.executing = get_bool(input)?;
+        self.delay_timer = get_u8(input)?;
+        self.sound_timer = get_u8(input)?;
+        Ok(())
+    }
+}
 
 impl CpuState {
     #[inline]
     pub fn new()->CpuState{
-        Self{
-            executing:Arc::new(Mutex::new(false)),
-            delay_timer:Arc::new(Mutex::new(0)),
-            sound_timer:Arc::new(Mutex::new(0)),
-        }
+        Self::default()
     }
-
-    pub fn stop(&self)->bool{
-        match self.executing.lock(){
-            Ok(mut a)=>{*a=false;true},
-            _=>false
-        }
+    #[inline]
+    pub fn stop(&mut self){
+        self.executing=false
     }
-    pub fn start(&self)->bool{
-        match self.executing.lock(){
-            Ok(mut a)=>{*a=true;true},
-            _=>false
-        }
+    #[inline]
+    pub fn start(&mut self){
+        self.executing=true
     }
-    pub fn is_running(&self)->Option<bool>{
-        match self.executing.lock(){
-            Ok(a)=>Some(*a),
-            _=>None
-        }
+    #[inline]
+    pub fn is_running(&self)->bool{
+        self.executing
+    }
+    #[inline]
+    pub fn delay_timer(&self)->u8{
+        self.delay_timer
+    }
+    #[inline]
+    pub fn sound_timer(&self)->u8{
+        self.sound_timer
     }
 }
 
 impl Chip8 {
     #[inline]
-    pub fn new(cpu:DefaultCpu,cosmic:bool)->Self{
+    pub fn new(cpu:DefaultCpu,quirks:Quirks,set:InstructionSet)->Self{
         Self{
-            cpu:cpu,
+            cpu,
             state:CpuState::new(),
-            cosmic:cosmic,
-            needs_key:false,
-            last_exec:std::time::Instant::now(),
-            cpu_rate:C8_CPU_RATE,
-            timer_rate:C8_TIMER_RATE,   
+            quirks,
+            set,
+            program_start:CHIP8_PROGRAM_START,
+            instructions_per_frame:C8_DEFAULT_IPF,
+            font_base:0x0,
+            key_wait:KeyWait::Idle,
+            waiting_vblank:false,
+            frame_cycles:0,
+            history:VecDeque::with_capacity(C8_PC_HISTORY),
+            executed:0,
+            frames:0,
         }
     }
-    #[inline]
-    pub fn create(cosmic:bool)->Chip8 {
-        Chip8::new(
-            DefaultCpu::new(MemoryStick::open(), 
+    ///Fails if the profile's fonts aren't valid hex or don't fit in memory at its font base
+    pub fn create(profile:Profile)->Result<Chip8> {
+        let mut fonts = FontPack::with_font(profile.font)?.with_base(profile.font_base);
+        fonts.load_big_font(profile.big_font)?;
+        Ok(Chip8::new(
+            DefaultCpu::new(MemoryStick::alloced(profile.memory_size), 
                             Auxillary::pack(PixMap::pack(), Sound::pack())),
-                            cosmic).with_font(C8_CLASSIC_FONT)
+                            profile.quirks,profile.set)
+            .with_font_pack(fonts)?
+            .with_program_start(profile.program_start)
+            .with_instructions_per_frame(profile.instructions_per_frame))
+    }
+    #[inline]
+    pub fn with_program_start(mut self,addr:u16)->Self{
+        self.program_start = addr;
+        self.cpu.jump(addr);
+        self
+    }
+    #[inline]
+    pub fn with_instructions_per_frame(mut self,n:u32)->Self{
+        self.set_instructions_per_frame(n);
+        self
+    }
+    #[inline]
+    pub fn program_start(&self)->u16{
+        self.program_start
+    }
+    #[inline]
+    pub fn instructions_per_frame(&self)->u32{
+        self.instructions_per_frame
+    }
+    ///Instructions run by every run_frame
+    #[inline]
+    pub fn set_instructions_per_frame(&mut self,n:u32){
+        self.instructions_per_frame = n.max(1);
+    }
+    #[inline]
+    pub fn quirks(&self)->&Quirks{
+        &self.quirks
+    }
+    #[inline]
+    pub fn quirks_mut(&mut self)->&mut Quirks{
+        &mut self.quirks
+    }
+    #[inline]
+    pub fn instruction_set(&self)->InstructionSet{
+        self.set
+    }
+    #[inline]
+    fn schip(&self)->bool{
+        self.set>=InstructionSet::SuperChip
+    }
+    #[inline]
+    fn xochip(&self)->bool{
+        self.set>=InstructionSet::XoChip
+    }
+    ///Skips the next instruction, stepping over all 4 bytes of an XO-CHIP F000 NNNN
+    #[inline]
+    fn skip(&mut self){
+        if self.xochip() && self.cpu.instruction() == 0xF000 {
+            self.cpu.inc_instruction()
+        }
+        self.cpu.inc_instruction()
     }
     #[inline]
     pub fn keyboard(&self)->&Keyboard{
-        return self.cpu.aux().keyboard()
+        self.cpu.aux().keyboard()
     }
     #[inline]
     pub fn keyboard_mut(&mut self)->&mut Keyboard{
-        return self.cpu.aux_mut().keyboard_mut()
+        self.cpu.aux_mut().keyboard_mut()
+    }
+    ///Frames run so far, the next one to run is this number
+    #[inline]
+    pub fn frame(&self)->u64{
+        self.frames
+    }
+    ///Queues a key press or release for the start of the next frame
+    #[inline]
+    pub fn queue_input(&mut self,event:KeyEvent){
+        let frame = self.frames;
+        self.keyboard_mut().queue(frame,event)
+    }
+    ///Queues a key press or release for the start of frame, for replaying recorded input
+    #[inline]
+    pub fn queue_input_at(&mut self,frame:u64,event:KeyEvent){
+        self.keyboard_mut().queue(frame,event)
     }
     
     #[inline] 
-    pub fn with_font_pack(mut self,font:FontPack)->Self{
-        self.using_font_pack(font);
-        self
+    pub fn with_font_pack(mut self,font:FontPack)->Result<Self>{
+        self.using_font_pack(font)?;
+        Ok(self)
     }
     #[inline] 
-    pub fn with_font(mut self,font:&str)->Self{
-        self.using_font(font);
-        self
+    pub fn with_font(mut self,font:&str)->Result<Self>{
+        self.using_font(font)?;
+        Ok(self)
+    }
+    ///Copies both fonts to the pack's base, which FX29 and FX30 then use
+    pub fn using_font_pack(&mut self,font:FontPack)->Result<()> {
+        let (base,len) = (font.base(),C8_FONT_PACK_SIZE);
+        if base as usize+len as usize > self.cpu.memory().len() {
+            return Err(ErrorKind::MemoryOutOfRange{addr:base,len}.into());
+        }
+        self.cpu.memory_mut().fill(base,font.bytes());
+        self.font_base = base;
+        Ok(())
     }
     #[inline]
-    pub fn using_font_pack(&mut self,font:FontPack) {
-        self.cpu.memory_mut().fill(0x0,font.fonts.arena())
+    pub fn using_font(&mut self,font:&str)->Result<()> {
+        let base = self.font_base;
+        load_font(self.cpu.memory_mut(),base,font)
     }
     #[inline]
-    pub fn using_font(&mut self,font:&str) {
-        load_font(self.cpu.memory_mut(),font)
+    pub fn set_char_font(&mut self,ch:u8,font:&str)->Result<()> {
+        let base = self.font_base;
+        load_charfont(self.cpu.memory_mut(),base,ch,font)
     }
     #[inline]
-    pub fn set_char_font(&mut self,ch:u8,font:&str) {
-        load_charfont(self.cpu.memory_mut(),ch,font)
+    pub fn font_base(&self)->u16{
+        self.font_base
     }
     #[inline]
-    pub fn load_program(&mut self,hex:&str) {
-        self.cpu.memory_mut().load_hex(0x200, hex);
+    pub fn load_program(&mut self,hex:&str)->Result<()> {
+        self.cpu.memory_mut().load_hex(self.program_start, hex).map(|_|())
     }
     #[inline]
     pub fn load_program_binary(&mut self,hex:&[u8]) {
-        self.cpu.memory_mut().load_binary(0x200, hex);
+        self.cpu.memory_mut().load_binary(self.program_start, hex);
+    }
+    ///Loads a rom at the program start, refusing one that runs past the end of memory or over the fonts
+    pub fn load_rom(&mut self,rom:&[u8])->Result<()> {
+        let space = self.cpu.memory().len().saturating_sub(self.program_start as usize);
+        if rom.len() > space {
+            return Err(ErrorKind::RomTooLarge{size:rom.len(),space}.into());
+        }
+        let (start,end) = (self.program_start as usize,self.program_start as usize+rom.len());
+        let fonts = self.font_base as usize..self.font_base as usize+C8_FONT_PACK_SIZE as usize;
+        if fonts.start < end && start < fonts.end {
+            return Err(ErrorKind::RomOverlapsFonts{font_base:self.font_base,start:self.program_start,end}.into());
+        }
+        self.load_program_binary(rom);
+        Ok(())
+    }
+    ///Addresses of the last C8_PC_HISTORY instructions, oldest first
+    #[inline]
+    pub fn pc_history(&self)->impl Iterator<Item=&u16>{
+        self.history.iter()
+    }
+    ///Counts every instruction run, frontends use it to show the speed
+    #[inline]
+    pub fn instructions_executed(&self)->u64{
+        self.executed
     }
     #[inline]
     pub fn cpu_state(&self)->CpuState{
-        self.state.clone()
+        self.state
     }
     #[inline]
     pub fn graphics(&self)->&PixMap{
@@ -157,419 +334,854 @@ impl Chip8 {
     pub fn graphics_mut(&mut self)->&mut PixMap{
         self.cpu.aux_mut().graphics_mut()
     }
-    fn delay_timer(status:Arc<Mutex<bool>>,timer:Arc<Mutex<u8>>,wait_time:std::time::Duration) {
-        loop {
-            let now = std::time::Instant::now();
-            let ok = match status.lock(){
-                Ok(a)=>*a,
-                _=>false
-            };
-            if !ok{
-                break;
-            }
-            match timer.lock() {
-                Ok(mut time)=>if *time>0{
-                    *time-=1
-                },
-                _=>{}
-            }
-            let end = std::time::Instant::now()-now;
-            if end < wait_time {
-                std::thread::sleep(wait_time)
-            }
+    #[inline]
+    pub fn sound(&self)->Sound{
+        self.cpu.aux().sound()
+    }
+    #[inline]
+    pub fn delay_timer(&self)->u8{
+        self.state.delay_timer
+    }
+    #[inline]
+    pub fn sound_timer(&self)->u8{
+        self.state.sound_timer
+    }
+    #[inline]
+    pub fn set_delay_timer(&mut self,n:u8){
+        self.state.delay_timer = n
+    }
+    #[inline]
+    pub fn set_sound_timer(&mut self,n:u8){
+        self.state.sound_timer = n
+    }
+    ///The 60hz tick: counts both timers down and keeps the beeper on while the sound timer is set,
+    ///or while the key an FX0A is waiting on is held
+    pub fn tick_timers(&mut self){
+        if self.state.delay_timer > 0 {
+            self.state.delay_timer-=1
+        }
+        let beep = self.state.sound_timer > 0 || matches!(self.key_wait,KeyWait::Release(..));
+        if self.state.sound_timer > 0 {
+            self.state.sound_timer-=1;
+        }
+        if beep {
+            self.cpu.aux_mut().sound_mut().safeplay()
+        } else {
+            self.cpu.aux_mut().sound_mut().safepause()
         }
     }
-    fn sound_timer(status:Arc<Mutex<bool>>,timer:Arc<Mutex<u8>>,mut sound_pack:Sound,wait_time:std::time::Duration) {
-        loop {
-            let now = std::time::Instant::now();
-            let ok = match status.lock(){
-                Ok(a)=>*a,
-                _=>false
-            };
-            if !ok{
-                break;
-            }
-            match timer.lock() {
-                Ok(mut time)=>if *time>0{
-                    *time-=1;
-                    sound_pack.safeplay()
-                    
-                } else {
-                    sound_pack.safepause()
-                    
-                },
-                _=>{
-                    sound_pack.safepause()
-                    
+    #[inline]
+    pub fn key_wait(&self)->KeyWait{
+        self.key_wait
+    }
+    ///True while an FX0A holds the CPU
+    #[inline]
+    pub fn is_waiting_for_key(&self)->bool{
+        self.key_wait != KeyWait::Idle
+    }
+    ///Moves an FX0A wait along with the keypad, true once it has its key in VX.
+    ///Like the VIP the key counts on release, with the key-press quirk as soon as it's down
+    fn wait_for_key(&mut self)->bool{
+        let pressed = self.cpu.aux().keyboard().all_pressed().next().map(|event|event.key);
+        self.key_wait = match self.key_wait {
+            KeyWait::Idle=>return true,
+            KeyWait::Press(x)=>match pressed {
+                Some(key) if self.quirks.key_press=>{
+                    *self.cpu.register_mut(x) = key.into_byte();
+                    KeyWait::Idle
                 }
+                Some(key)=>KeyWait::Release(x,key),
+                None=>KeyWait::Press(x)
             }
-            let end = std::time::Instant::now()-now;
-            if end < wait_time {
-                std::thread::sleep(wait_time)
-            }
-      }
-    }
-    fn open_sound(&self,clock_speed:std::time::Duration)->std::thread::JoinHandle<()> {
-        let _st:Arc<Mutex<u8>> = self.state.sound_timer.clone();
-        let _et:Arc<Mutex<bool>> = self.state.executing.clone();
-        let sound = self.cpu.aux().sound();
-        std::thread::spawn(move||{
-            Self::sound_timer(_et, _st, sound, clock_speed);    
-        })
-    }
-    fn open_delay(&self,clock_speed:std::time::Duration)->std::thread::JoinHandle<()> {
-        let _st = self.state.delay_timer.clone();
-        let _et = self.state.executing.clone();
-        std::thread::spawn(move||{
-            Self::delay_timer(_et, _st, clock_speed);
-        })
-    }
-    fn wait_for_key_handler(&mut self,instruction:u16)->bool{
-        let key = self.cpu.aux().keyboard().all_pressed().next();
-        match key {
-            Some(key)=>{
-                *self.cpu.register_mut(x_in_xy!(instruction))=key.key.into_byte();
-                self.needs_key=false;
-                true
-            }
-            _=>{
-                self.needs_key=true;
-                false
+            KeyWait::Release(x,key) if self.cpu.aux().keyboard().is_released(key)=>{
+                *self.cpu.register_mut(x) = key.into_byte();
+                KeyWait::Idle
             }
-        }
+            wait=>wait
+        };
+        self.key_wait == KeyWait::Idle
     }
-    pub fn execute_step(&mut self)->Error {
-        match self.state.is_running(){
-            Some(is_r)=>{
-                if is_r{
-                    match self.execute_instruction() {
-                        Ok(_)=>{},
-                        Err(e)=>return e
+    ///DXYN, plus the SCHIP 16x16 DXY0 form
+    fn draw_sprite(&mut self,x:u8,y:u8,n:u8){
+        //with the display-wait quirk only one sprite is drawn per 60hz frame
+        self.waiting_vblank = self.quirks.display_wait;
+        let (width,height) = {
+            let graphics = self.cpu.aux().graphics();
+            (graphics.width() as u16,graphics.height() as u16)
+        };
+        let big = n == 0 && self.schip();
+        let (cols,rows) = if big {(16,16)} else {(8,n as u16)};
+        let x = *self.cpu.register(x) as u16 % width;
+        let y = *self.cpu.register(y) as u16 % height;
+        let mut i = self.cpu.register_i();
+        let mut set = false;
+        let planes = self.cpu.aux().graphics().planes();
+        for plane in [1u8,2] {
+            if planes&plane == 0 {
+                continue;
+            }
+            for row in 0..rows {
+                if y+row >= height && !self.quirks.sprite_wrap {
+                    break;
+                }
+                let sprite = if big {
+                    self.cpu.memory().u16(i+row*2)
+                } else {
+                    (*self.cpu.memory().derefrence(i+row) as u16)<<8
+                };
+                for col in 0..cols {
+                    if x+col >= width && !self.quirks.sprite_wrap {
+                        break;
                     }
-                    let end = std::time::Instant::now()-self.last_exec;
-                    if self.cpu_rate > end {
-                        std::thread::sleep(self.cpu_rate-end);
+                    let bit = ((sprite<<col)>>15) as u8;
+                    let (px,py) = ((x+col)%width,(y+row)%height);
+                    if self.cpu.aux_mut().graphics_mut().decide_plane(px as u8,py as u8,bit,plane) {
+                        set = true;
                     }
-                    self.last_exec = std::time::Instant::now();
                 }
-                Error::None
             }
-            _=>return Error::ExecutionLocked
+            //with both planes selected the second plane's rows follow the first's
+            i+=rows*(cols/8);
         }
-       
+        if set{
+            *self.cpu.register_mut(0xF)=1
+        } else {
+            *self.cpu.register_mut(0xF)=0
+        }
+    }
+    ///Snapshot of the whole machine, see load_state
+    pub fn save_state(&self)->Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(C8_SAVESTATE_MAGIC);
+        put_u16(&mut out,C8_SAVESTATE_VERSION);
+        out.push(self.set as u8);
+        put_u16(&mut out,self.program_start);
+        put_u32(&mut out,self.instructions_per_frame);
+        put_u16(&mut out,self.font_base);
+        self.key_wait.save(&mut out);
+        put_bool(&mut out,self.waiting_vblank);
+        put_u64(&mut out,self.frames);
+        self.state.save(&mut out);
+        self.quirks.save(&mut out);
+        self.cpu.save(&mut out);
+        out
     }
-    pub fn start(&mut self)->Result<(std::thread::JoinHandle<()>,std::thread::JoinHandle<()>)> {
-        if !self.state.start() {
-            return Err(Error::ExecutionLocked);
+    ///Restores a snapshot from save_state, leaving the machine untouched if it can't be read
+    pub fn load_state(&mut self,state:&[u8])->Result<()> {
+        let backup = self.save_state();
+        let mut input = state;
+        if self.read_state(&mut input).is_err() || !input.is_empty() {
+            let _ = self.read_state(&mut backup.as_slice());
+            return Err(ErrorKind::InvalidSaveState.into());
         }
-        self.cpu.jump(0x200);
-        let sound = self.open_sound(self.timer_rate.clone());
-        let delay = self.open_delay(self.timer_rate.clone());
-        Ok((sound,delay))
+        Ok(())
     }
-    pub fn close(&mut self,sound:std::thread::JoinHandle<()>,delay:std::thread::JoinHandle<()>) {
+    fn read_state(&mut self,input:&mut &[u8])->std::io::Result<()> {
+        let mut magic = [0u8;4];
+        get_bytes(input,&mut magic)?;
+        if &magic != C8_SAVESTATE_MAGIC || get_u16(input)? != C8_SAVESTATE_VERSION {
+            return Err(invalid("not a save state for this version"));
+        }
+        self.set = match get_u8(input)? {
+            0=>InstructionSet::Chip8,
+            1=>InstructionSet::SuperChip,
+            2=>InstructionSet::XoChip,
+            _=>return Err(invalid("bad instruction set"))
+        };
+        self.program_start = get_u16(input)?;
+        self.instructions_per_frame = get_u32(input)?.max(1);
+        self.font_base = get_u16(input)?;
+        self.key_wait.load(input)?;
+        self.waiting_vblank = get_bool(input)?;
+        self.frames = get_u64(input)?;
+        self.frame_cycles = 0;
+        self.state.load(input)?;
+        self.quirks.load(input)?;
+        self.cpu.load(input)
+    }
+    ///Runs a single instruction, timers only move in run_frame or tick_timers
+    pub fn execute_step(&mut self)->Result<()> {
+        if self.state.is_running() {
+            self.execute_instruction()?;
+        }
+        Ok(())
+    }
+    ///One 60hz frame: the queued input due, up to instructions_per_frame instructions, then a timer tick
+    pub fn run_frame(&mut self)->Result<()> {
+        if !self.state.is_running() {
+            self.begin_frame();
+            self.end_frame();
+            return Ok(());
+        }
+        while !self.frame_step()?.ended {}
+        Ok(())
+    }
+    ///Runs the next instruction of the current frame, ending the frame and ticking the timers when it's over
+    pub fn frame_step(&mut self)->Result<FrameStep> {
+        if self.frame_cycles == 0 {
+            self.begin_frame();
+        }
+        let ran = self.execute_instruction()?;
+        self.frame_cycles+=1;
+        //a blocked FX0A sits out the rest of the frame, input only changes between frames
+        let ended = self.waiting_vblank || self.is_waiting_for_key() || self.frame_cycles >= self.instructions_per_frame;
+        if ended {
+            self.end_frame();
+        }
+        Ok(FrameStep{ran,ended})
+    }
+    fn begin_frame(&mut self) {
+        let frame = self.frames;
+        self.keyboard_mut().apply(frame);
+    }
+    ///Releases only show for the frame they happen in
+    fn end_frame(&mut self) {
+        self.frame_cycles = 0;
+        self.waiting_vblank = false;
+        self.frames+=1;
+        self.keyboard_mut().reset();
+        self.tick_timers();
+    }
+    pub fn start(&mut self) {
+        self.state.start();
+        self.cpu.jump(self.program_start);
+    }
+    pub fn close(&mut self) {
         self.state.stop();
-        let _ = sound.join();
-        let _ = delay.join();
+        self.cpu.aux_mut().sound_mut().safepause();
     }
+    ///Picks instructions per frame from the time one instruction should take
     pub fn set_speed(&mut self,speed:std::time::Duration){
-        self.cpu_rate=speed;
+        let n = C8_TIMER_RATE.as_nanos()/speed.as_nanos().max(1);
+        self.set_instructions_per_frame(n as u32);
+    }
+    pub fn slowdown(&mut self,n:u32){
+        let ipf = self.instructions_per_frame.saturating_sub(n);
+        self.set_instructions_per_frame(ipf);
+    }
+    pub fn speedup(&mut self,n:u32){
+        let ipf = self.instructions_per_frame.saturating_add(n);
+        self.set_instructions_per_frame(ipf);
+    }
+    ///Decodes the instruction at addr for this machine's set, with any operand word
+    pub fn decode_at(&self,addr:u16)->Result<Instruction> {
+        let memory = self.cpu.memory();
+        Instruction::decode(memory.u16(addr),self.set).map(|i|i.with_operand(memory.u16(addr.wrapping_add(2))))
     }
-    pub fn slowdown(&mut self,n:u64){
-        self.cpu_rate+=std::time::Duration::from_micros(10*n);
+    ///The memory an instruction reads or writes through I, as an address and a length
+    pub fn memory_span(&self,instruction:Instruction)->Option<(u16,u16)> {
+        let span = |x:u8,y:u8|if x <= y {y-x+1} else {x-y+1} as u16;
+        let len = match instruction {
+            Instruction::Drw{n,..}=>{
+                let planes = if self.set >= InstructionSet::XoChip {self.graphics().planes().count_ones()} else {1};
+                let rows = if n == 0 && self.schip() {32} else {n as u16};
+                rows*planes as u16
+            }
+            Instruction::LoadRegs(x)|Instruction::StoreRegs(x)=>x as u16+1,
+            Instruction::LoadRange{x,y}|Instruction::SaveRange{x,y}=>span(x,y),
+            Instruction::Bcd(_)=>3,
+            Instruction::Audio=>C8_PATTERN_SIZE as u16,
+            _=>return None
+        };
+        match len {
+            0=>None,
+            _=>Some((self.cpu.register_i(),len))
+        }
     }
-    pub fn speedup(&mut self,n:u64){
-        let t1 =std::time::Duration::from_micros(10*n);
-        if self.cpu_rate > t1 {
-            self.cpu_rate-=t1
+    ///Main instruction executor, errors carry the PC, opcode and call stack.
+    ///False when nothing completed: FX0A keeps PC on itself until it has its key, then finishes here
+    pub fn execute_instruction(&mut self)->Result<bool> {
+        if self.is_waiting_for_key() {
+            let done = self.wait_for_key();
+            if done {
+                self.cpu.inc_instruction();
+            }
+            return Ok(done);
         }
-        
+        let pc = self.cpu.program_counter();
+        let opcode = self.cpu.instruction();
+        if self.history.len() == C8_PC_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(pc);
+        self.executed+=1;
+        self.execute(opcode).map_err(|kind|Error::new(kind).at(pc,opcode,self.cpu.memory().callstack()))?;
+        Ok(!self.is_waiting_for_key())
     }
-    ///Main instruction decoder
-    pub fn execute_instruction(&mut self)->Result<()> {
-        let instruction = self.cpu.instruction();
+    fn execute(&mut self,opcode:u16)->std::result::Result<(),ErrorKind> {
         self.cpu.inc_instruction();
-        //println!("{:04X}: {:04X}",self.cpu.program_counter(),instruction);
-        let _ = match instruction {
-            0x00E0=>self.cpu.aux_mut().graphics_mut().clear(),
-            0x00EE=>match self.cpu.ret(){
+        let instruction = match Instruction::decode(opcode,self.set) {
+            Ok(instruction)=>instruction.with_operand(self.cpu.instruction()),
+            //the newer 00CN and 00FB-00FF do nothing on older machines
+            Err(_) if opcode>>12 == 0x0=>return Ok(()),
+            Err(error)=>return Err(error.kind)
+        };
+        if let Some((addr,len)) = self.memory_span(instruction) {
+            if addr as usize+len as usize > self.cpu.memory().len() {
+                return Err(ErrorKind::MemoryOutOfRange{addr,len});
+            }
+        }
+        match instruction {
+            Instruction::Sys(addr)=>self.cpu.jump(addr),
+            Instruction::Cls=>self.cpu.aux_mut().graphics_mut().clear(),
+            Instruction::Ret=>match self.cpu.ret(){
                 true=>{},
-                false=>return Err(Error::StackUnderflow)
-            }
-            0x00FD=>{},
-            _=>match (instruction>>12)&0xF {
-                0x0|0x1=>self.cpu.jump(instruction),
-                0x2=>self.cpu.call(instruction),
-                0x3=>{
-                    if *self.cpu.register(x_in_xkk!(instruction)) == kk_in_xkk!(instruction) {
-                        self.cpu.inc_instruction()
-                    }
+                false=>return Err(ErrorKind::StackUnderflow)
+            }
+            Instruction::ScrollDown(n)=>self.cpu.aux_mut().graphics_mut().scroll_down(n as usize),
+            Instruction::ScrollUp(n)=>self.cpu.aux_mut().graphics_mut().scroll_up(n as usize),
+            Instruction::ScrollRight=>self.cpu.aux_mut().graphics_mut().scroll_right(4),
+            Instruction::ScrollLeft=>self.cpu.aux_mut().graphics_mut().scroll_left(4),
+            //exit: park the interpreter on this instruction
+            Instruction::Exit=>self.cpu.dec_instruction(),
+            Instruction::Low=>self.cpu.aux_mut().graphics_mut().set_hires(false),
+            Instruction::High=>self.cpu.aux_mut().graphics_mut().set_hires(true),
+            Instruction::Jp(addr)=>self.cpu.jump(addr),
+            Instruction::Call(addr)=>{
+                let limit = match self.quirks.stack_depth {
+                    0=>CHIP8_MAX_STACK_DEPTH,
+                    depth=>depth.min(CHIP8_MAX_STACK_DEPTH)
+                };
+                if self.cpu.memory().stack_depth() >= limit || !self.cpu.call(addr) {
+                    return Err(ErrorKind::StackOverflow{limit});
                 }
-                0x4=>{
-                    if *self.cpu.register(x_in_xkk!(instruction)) != kk_in_xkk!(instruction) {
-                        self.cpu.inc_instruction()
-                    }
+            }
+            Instruction::SeByte{x,kk}=>{
+                if *self.cpu.register(x) == kk {
+                    self.skip()
                 }
-                0x5=>{
-                    if last_nibble!(instruction) != 0 {
-                        return Err(Error::InvalidInstruction);
-                    } else {
-                        if *self.cpu.register(x_in_xy!(instruction)) == *self.cpu.register(y_in_xy!(instruction)) {
-                            self.cpu.inc_instruction()
-                        }
-                    }
+            }
+            Instruction::SneByte{x,kk}=>{
+                if *self.cpu.register(x) != kk {
+                    self.skip()
                 }
-                0x6=>{
-                    *self.cpu.register_mut(x_in_xkk!(instruction))=kk_in_xkk!(instruction);
+            }
+            Instruction::SeReg{x,y}=>{
+                if *self.cpu.register(x) == *self.cpu.register(y) {
+                    self.skip()
                 }
-                0x7=>{
-                    *self.cpu.register_mut(x_in_xkk!(instruction))+=kk_in_xkk!(instruction);
+            }
+            Instruction::SaveRange{x,y}=>self.cpu.save_range(x,y),
+            Instruction::LoadRange{x,y}=>self.cpu.load_range(x,y),
+            Instruction::LdByte{x,kk}=>{
+                *self.cpu.register_mut(x)=kk;
+            }
+            Instruction::AddByte{x,kk}=>{
+                *self.cpu.register_mut(x) = self.cpu.register(x).wrapping_add(kk);
+            }
+            Instruction::LdReg{x,y}=>{
+                let byte = *self.cpu.register(y);
+                *self.cpu.register_mut(x)=byte;
+            }
+            Instruction::Or{x,y}=>{
+                let byte = *self.cpu.register(y);
+                *self.cpu.register_mut(x)|=byte;
+                if self.quirks.vf_reset {
+                    *self.cpu.register_mut(0xF)=0
                 }
-                0x8=>match last_nibble!(instruction){
-                    0x0=>{
-                        let byte = *self.cpu.register(y_in_xy!(instruction));
-                        *self.cpu.register_mut(x_in_xy!(instruction))=byte;
-                    }
-                    0x1=>{
-                        let byte = *self.cpu.register(y_in_xy!(instruction));
-                        *self.cpu.register_mut(x_in_xy!(instruction))|=byte; 
-                    }
-                    0x2=>{
-                        let byte = *self.cpu.register(y_in_xy!(instruction));
-                        *self.cpu.register_mut(x_in_xy!(instruction))&=byte;
-                    }
-                    0x3=>{
-                        let byte = *self.cpu.register(y_in_xy!(instruction));
-                        *self.cpu.register_mut(x_in_xy!(instruction))^=byte;
-                    }
-                    0x4=>{
-                        let res:u16 = *self.cpu.register(x_in_xy!(instruction)) as u16 +*self.cpu.register(y_in_xy!(instruction)) as u16;
-                        *self.cpu.register_mut(x_in_xy!(instruction))=(res&0xFF) as u8;
-                        if res&0xFF00 > 0 {
-                            *self.cpu.register_mut(0xF)=1
-                        } else {
-                            *self.cpu.register_mut(0xF)=0
-                        }
-                    }
-                    0x5=>{
-                        let x = x_in_xy!(instruction);
-                        let y = y_in_xy!(instruction);
-                        if *self.cpu.register(x) > *self.cpu.register(y) {
-                            *self.cpu.register_mut(0xF) =1
-                        } else {
-                            *self.cpu.register_mut(0xF) =0
-                        }
-                        let res:u16 = *self.cpu.register(x) as u16 -*self.cpu.register(y) as u16;
-                        *self.cpu.register_mut(x_in_xy!(instruction))=(res&0xFF) as u8;
-                    }
-                    0x6=>{
-                        let x = x_in_xy!(instruction);
-                        let y = y_in_xy!(instruction);
-                        if self.cosmic {
-                            *self.cpu.register_mut(x)=*self.cpu.register(y);
-                        }
-                        if *self.cpu.register(x)&0x1 > 0 {
-                            *self.cpu.register_mut(0xF) =1
-                        } else {
-                            *self.cpu.register_mut(0xF) =0
-                        }
-                        *self.cpu.register_mut(x)>>=1
-                    }
-                    0x7=>{
-                        let x = x_in_xy!(instruction);
-                        let y = y_in_xy!(instruction);
-                        if *self.cpu.register(y) > *self.cpu.register(x) {
-                            *self.cpu.register_mut(0xF) =1
-                        } else {
-                            *self.cpu.register_mut(0xF) =0
-                        }
-                        let res:u16 = *self.cpu.register(y) as u16 -*self.cpu.register(x) as u16;
-                        *self.cpu.register_mut(x_in_xy!(instruction))=(res&0xFF) as u8;
-                    }
-                    0xE=>{
-                        let x = x_in_xy!(instruction);
-                        let y = y_in_xy!(instruction);
-                        if self.cosmic {
-                            *self.cpu.register_mut(x)=*self.cpu.register(y);
-                        }
-                        if *self.cpu.register(x)&0x80 > 0 {
-                            *self.cpu.register_mut(0xF) =1
-                        } else {
-                            *self.cpu.register_mut(0xF) =0
-                        }
-                        *self.cpu.register_mut(x)<<=1
-                    }
-                    _=>{
-                        return Err(Error::InvalidInstruction);
-                    }
+            }
+            Instruction::And{x,y}=>{
+                let byte = *self.cpu.register(y);
+                *self.cpu.register_mut(x)&=byte;
+                if self.quirks.vf_reset {
+                    *self.cpu.register_mut(0xF)=0
                 }
-                0x9=>{
-                    if last_nibble!(instruction) != 0 {
-                        return Err(Error::InvalidInstruction);
-                    } else {
-                        let x = x_in_xy!(instruction);
-                        let y = y_in_xy!(instruction);
-                        if *self.cpu.register(x) != *self.cpu.register(y) {
-                            self.cpu.inc_instruction()
-                        }
-                    }
+            }
+            Instruction::Xor{x,y}=>{
+                let byte = *self.cpu.register(y);
+                *self.cpu.register_mut(x)^=byte;
+                if self.quirks.vf_reset {
+                    *self.cpu.register_mut(0xF)=0
                 }
-                0xA=>{
-                    //println!("OI: {:04X}, OV: {:04X}",self.cpu.registerI(),self.cpu.memory().derefrence(self.cpu.registerI()));
-                    self.cpu.set_register_i(instruction&0xFFF);
-                    //println!("I: {:04X}, V: {:04X}",self.cpu.registerI(),self.cpu.memory().derefrence(self.cpu.registerI()))
+            }
+            Instruction::AddReg{x,y}=>{
+                let res:u16 = *self.cpu.register(x) as u16 +*self.cpu.register(y) as u16;
+                *self.cpu.register_mut(x)=(res&0xFF) as u8;
+                if res&0xFF00 > 0 {
+                    *self.cpu.register_mut(0xF)=1
+                } else {
+                    *self.cpu.register_mut(0xF)=0
                 }
-                0xB=>{
-                    if self.cosmic {
-                        self.cpu.jump(instruction);
-                        self.cpu.jumpn(*self.cpu.register(0x0) as u16);
-                    } else {
-                        self.cpu.jump(instruction);
-                        self.cpu.jumpn(*self.cpu.register(x_in_xy!(instruction))as u16);
-                    }
+            }
+            //the flag is written last so it wins when x is VF
+            Instruction::Sub{x,y}=>{
+                let (vx,vy) = (*self.cpu.register(x),*self.cpu.register(y));
+                *self.cpu.register_mut(x) = vx.wrapping_sub(vy);
+                *self.cpu.register_mut(0xF) = (vx >= vy) as u8;
+            }
+            Instruction::Shr{x,y}=>{
+                let v = *self.cpu.register(if self.quirks.shift_vy {y} else {x});
+                *self.cpu.register_mut(x) = v>>1;
+                *self.cpu.register_mut(0xF) = v&0x1;
+            }
+            Instruction::Subn{x,y}=>{
+                let (vx,vy) = (*self.cpu.register(x),*self.cpu.register(y));
+                *self.cpu.register_mut(x) = vy.wrapping_sub(vx);
+                *self.cpu.register_mut(0xF) = (vy >= vx) as u8;
+            }
+            Instruction::Shl{x,y}=>{
+                let v = *self.cpu.register(if self.quirks.shift_vy {y} else {x});
+                *self.cpu.register_mut(x) = v<<1;
+                *self.cpu.register_mut(0xF) = v>>7;
+            }
+            Instruction::SneReg{x,y}=>{
+                if *self.cpu.register(x) != *self.cpu.register(y) {
+                    self.skip()
                 }
-                0xC=>{
-                    *self.cpu.register_mut(x_in_xkk!(instruction))= self.cpu.random()&kk_in_xkk!(instruction);
+            }
+            Instruction::LdI(addr)=>self.cpu.set_register_i(addr),
+            Instruction::JpV0(addr)=>{
+                self.cpu.jump(addr);
+                if !self.quirks.jump_vx {
+                    self.cpu.jumpn(*self.cpu.register(0x0) as u16);
+                } else {
+                    self.cpu.jumpn(*self.cpu.register((addr>>8) as u8) as u16);
                 }
-                0xD=>{
-                    let height = last_nibble!(instruction);
-                    let x = *self.cpu.register(x_in_xy!(instruction))&63;
-                    let mut y = *self.cpu.register(y_in_xy!(instruction))&31;
-                    let mut set = false;
-                    let i = self.cpu.register_i();
-                    let max = i + (height as u16);
-                    for index in i..max {
-                        let mut sprite = *self.cpu.memory().derefrence(index);
-                        for xi in x..64 {
-                            if self.cpu.aux_mut().graphics_mut().decide(xi,y,sprite&0x80) {
-                                set = true;
-                            }
-                            sprite<<=1
-                        }
-                        y+=1;
-                        if y >=32 {
-                            break;
-                        }
-                    }
-                    if set{
+            }
+            Instruction::Rnd{x,kk}=>{
+                *self.cpu.register_mut(x)= self.cpu.random()&kk;
+            }
+            Instruction::Drw{x,y,n}=>self.draw_sprite(x,y,n),
+            Instruction::Skp(x)=>{
+                let byte = *self.cpu.register(x)&0xF;
+                if self.cpu.aux().keyboard().is_pressed(Key::from_byte(byte).unwrap()) {
+                    self.skip()
+                }
+            }
+            Instruction::Sknp(x)=>{
+                let byte = *self.cpu.register(x)&0xF;
+                if !self.cpu.aux().keyboard().is_pressed(Key::from_byte(byte).unwrap()) {
+                    self.skip()
+                }
+            }
+            Instruction::LdILong(addr)=>{
+                self.cpu.set_register_i(addr);
+                self.cpu.inc_instruction()
+            }
+            Instruction::Plane(n)=>self.cpu.aux_mut().graphics_mut().select_planes(n),
+            Instruction::Audio=>{
+                let mut pattern = [0u8;C8_PATTERN_SIZE];
+                let i = self.cpu.register_i();
+                for (n,byte) in pattern.iter_mut().enumerate() {
+                    *byte = *self.cpu.memory().derefrence(i+n as u16);
+                }
+                self.cpu.aux_mut().sound_mut().load_pattern(pattern)
+            }
+            Instruction::LdVxDt(x)=>{
+                *self.cpu.register_mut(x) = self.state.delay_timer;
+            }
+            Instruction::LdKey(x)=>{
+                self.key_wait = KeyWait::Press(x);
+                if !self.wait_for_key() {
+                    self.cpu.dec_instruction()
+                }
+            }
+            Instruction::LdDtVx(x)=>{
+                self.state.delay_timer = *self.cpu.register(x);
+            }
+            Instruction::LdStVx(x)=>{
+                self.state.sound_timer = *self.cpu.register(x);
+            }
+            Instruction::AddI(x)=>{
+                let byte = *self.cpu.register(x) as u16 + self.cpu.register_i();
+                if self.quirks.index_overflow {
+                    if byte > 0xFFF {
                         *self.cpu.register_mut(0xF)=1
                     } else {
                         *self.cpu.register_mut(0xF)=0
                     }
                 }
-                0xE=>{
-                    match instruction&0xFF {
-                        0x9E=>{
-                           let byte = *self.cpu.register(x_in_xy!(instruction))&0xF;
-                           if self.cpu.aux().keyboard().is_pressed(Key::from_byte(byte).unwrap()) {
-                               self.cpu.inc_instruction()
-                           }
-                        }
-                        0xA1=>{
-                            let byte = *self.cpu.register(x_in_xy!(instruction))&0xF;
-                            if !self.cpu.aux().keyboard().is_pressed(Key::from_byte(byte).unwrap()) {
-                                self.cpu.inc_instruction()
-                            }
-                        }
-                        _=>{
-                            return Err(Error::InvalidInstruction);
-                        }
-                    }
-                }
-                0xF=> match instruction&0xFF{
-                    0x07=>{
-                        let wv = match self.state.delay_timer.lock() {
-                            Ok(v)=>*v,
-                            _=>{
-                                return Err(Error::DelayTimerLocked);
-                            }
-                        };
-                        *self.cpu.register_mut(x_in_xy!(instruction)) = wv;
-                    }
-                    0x0A=>{
-                       if !self.wait_for_key_handler(instruction){
-                           println!("Waitin");
-                           self.cpu.dec_instruction()
-                       }
-                    }
-                    0x15=>{
-                        let byte = *self.cpu.register(x_in_xkk!(instruction));
-                        match self.state.delay_timer.lock(){
-                            Ok(mut dl)=>{
-                                *dl = byte;
-                            }
-                            _=>{
-                                return Err(Error::DelayTimerLocked);
-                            }
-                        }
-                    }
-                    0x18=>{
-                        let byte = *self.cpu.register(x_in_xkk!(instruction));
-                        match self.state.sound_timer.lock(){
-                            Ok(mut dl)=>{
-                                *dl = byte;
-                            }
-                            _=>{
-                                return Err(Error::SoundTimerLocked);
-                            }
-                        }
-                    }
-                    0x1E=>{
-                        let byte = *self.cpu.register(x_in_xkk!(instruction)) as u16 + self.cpu.register_i();
-                        if !self.cosmic {
-                            if byte > 0xFFF {
-                                *self.cpu.register_mut(0xF)=1
-                            } else {
-                                *self.cpu.register_mut(0xF)=0
-                            }
-                        }
-                        self.cpu.set_register_i(byte);
-                    }
-                    0x29=>{
-                        let byte = (*self.cpu.register(x_in_xkk!(instruction))&0xF) as u16;
-                        self.cpu.set_register_i(byte*5)
-                    }
-                    0x33=>{
-                        let val = self.cpu.register(x_in_xkk!(instruction));
-                        let i = self.cpu.register_i();
-                        let temp_alloc = format!("{:03}",val);
-                        let string = temp_alloc.as_bytes();
-                        *self.cpu.memory_mut().derefrence_mut(i) =   string[0]-'0' as u8;
-                        *self.cpu.memory_mut().derefrence_mut(i+1) = string[1]-'0' as u8;
-                        *self.cpu.memory_mut().derefrence_mut(i+2) = string[2]-'0' as u8;
-                    }
-                    0x65=>{
-                    
-                        let x = x_in_xy!(instruction);
-                        let addr = self.cpu.register_i();
-                        self.cpu.fill_registers(addr, x);                  
-                        if self.cosmic {
-                            self.cpu.set_register_i(addr+(x as u16))
-                        }
-                    }
-                    0x55=>{
-                        let x = x_in_xy!(instruction);
-                        let addr = self.cpu.register_i();
-                        self.cpu.load_registers(addr, x);                   
-                        if self.cosmic {
-                            self.cpu.set_register_i(addr+(x as u16))
-                        }
-                    }
-                    _=>{
-                        return Err(Error::InvalidInstruction);
-                    }
+                self.cpu.set_register_i(byte);
+            }
+            Instruction::LdFont(x)=>{
+                let byte = (*self.cpu.register(x)&0xF) as u16;
+                self.cpu.set_register_i(self.font_base+byte*5)
+            }
+            Instruction::LdBigFont(x)=>{
+                let byte = (*self.cpu.register(x)&0xF) as u16;
+                self.cpu.set_register_i(self.font_base+C8_BIG_FONT_START+byte*10)
+            }
+            Instruction::Bcd(x)=>{
+                let val = self.cpu.register(x);
+                let i = self.cpu.register_i();
+                let temp_alloc = format!("{:03}",val);
+                let string = temp_alloc.as_bytes();
+                *self.cpu.memory_mut().derefrence_mut(i) =   string[0]-b'0';
+                *self.cpu.memory_mut().derefrence_mut(i+1) = string[1]-b'0';
+                *self.cpu.memory_mut().derefrence_mut(i+2) = string[2]-b'0';
+            }
+            Instruction::Pitch(x)=>{
+                let byte = *self.cpu.register(x);
+                self.cpu.aux_mut().sound_mut().set_pitch(byte)
+            }
+            Instruction::StoreRegs(x)=>{
+                let addr = self.cpu.register_i();
+                self.cpu.load_registers(addr, x);
+                if self.quirks.memory_increment {
+                    self.cpu.set_register_i(addr+(x as u16)+1)
                 }
-                _=>{
-                    return Err(Error::InvalidInstruction);
+            }
+            Instruction::LoadRegs(x)=>{
+                let addr = self.cpu.register_i();
+                self.cpu.fill_registers(addr, x);
+                if self.quirks.memory_increment {
+                    self.cpu.set_register_i(addr+(x as u16)+1)
                 }
             }
-        };
-        Ok(())     
+            Instruction::StoreFlags(x)=>self.cpu.save_flags(x),
+            Instruction::LoadFlags(x)=>self.cpu.restore_flags(x),
+        }
+        Ok(())
     }
   
 }
 
+#[cfg(test)]
+impl Chip8 {
+    ///Waits on FX0A for 5, pressed on frame 3 and released on frame 5, then stores it at #300:
+    ///LD I, #300  LD V0, K  LD [I], V0  JP #206
+    pub(crate) fn waiting_for_key()->Self {
+        use crate::keyboard::KeyEventKind;
+        let mut chip8 = Chip8::create(Profile::default()).unwrap();
+        chip8.load_rom(&[0xA3,0x00,0xF0,0x0A,0xF0,0x55,0x12,0x06]).unwrap();
+        chip8.start();
+        chip8.queue_input_at(3,KeyEvent::new(Key::Five,KeyEventKind::KeyPress));
+        chip8.queue_input_at(5,KeyEvent::new(Key::Five,KeyEventKind::KeyRelease));
+        chip8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+    use crate::keyboard::KeyEventKind;
+    use crate::sound::C8_DEFAULT_PITCH;
+
+    ///Hires XO-CHIP drawing random sprites on plane 2, with a call that sets the sound timer
+    const XO_ROM:[u8;37] = [
+        0x00,0xFF, 0xF2,0x01, 0xA2,0x20, 0xC0,0x7F, 0xD0,0x05, 0x22,0x10, 0x12,0x04, 0x00,0x00,
+        0x7A,0x01, 0xFA,0x18, 0x00,0xEE, 0,0,0,0,0,0,0,0,0,0,
+        0xFF,0x81,0x81,0x81,0xFF,
+    ];
+
+    ///Big-endian bytes of the opcodes
+    fn rom(ops:&[u16])->Vec<u8> {
+        ops.iter().flat_map(|op|op.to_be_bytes()).collect()
+    }
+
+    ///Loads ops on a machine built from profile and executes steps instructions
+    fn run(profile:Profile,ops:&[u16],steps:usize)->Chip8 {
+        let mut chip8 = Chip8::create(profile).unwrap();
+        chip8.load_rom(&rom(ops)).unwrap();
+        chip8.start();
+        for _ in 0..steps {
+            chip8.execute_instruction().unwrap();
+        }
+        chip8
+    }
+
+    ///Every lit pixel in the current resolution, row by row
+    fn lit(chip8:&Chip8)->Vec<(u8,u8)> {
+        let graphics = chip8.graphics();
+        let mut pixels = vec![];
+        for y in 0..graphics.height() as u8 {
+            for x in 0..graphics.width() as u8 {
+                if graphics.get(x,y) > 0 {
+                    pixels.push((x,y));
+                }
+            }
+        }
+        pixels
+    }
+
+    fn xochip(frames:u32)->Chip8 {
+        let mut chip8 = Chip8::create(Platform::XoChip.profile()).unwrap();
+        chip8.load_rom(&XO_ROM).unwrap();
+        chip8.start();
+        for _ in 0..frames {
+            chip8.run_frame().unwrap();
+        }
+        chip8
+    }
 
+    #[test]
+    fn save_states_round_trip() {
+        let mut original = xochip(5);
+        let state = original.save_state();
+        let mut restored = Chip8::create(Profile::default()).unwrap();
+        restored.load_state(&state).unwrap();
+        assert!(restored.save_state() == state);
+        for _ in 0..10 {
+            original.run_frame().unwrap();
+            restored.run_frame().unwrap();
+        }
+        assert!(restored.save_state() == original.save_state());
+    }
 
+    #[test]
+    fn bad_save_states_leave_the_machine_alone() {
+        let mut chip8 = xochip(5);
+        let state = chip8.save_state();
+        let mut other = Chip8::create(Profile::default()).unwrap();
+        let before = other.save_state();
+        assert!(other.load_state(&state[..state.len()-1]).is_err());
+        let mut newer = state.clone();
+        newer[5]+=1;
+        assert!(other.load_state(&newer).is_err());
+        assert!(other.save_state() == before);
+        assert!(chip8.load_state(b"C8SS").is_err());
+        assert!(chip8.save_state() == state);
+    }
 
+    #[test]
+    fn newer_0nnn_instructions_do_nothing_on_chip8() {
+        let mut chip8 = Chip8::create(Profile::default()).unwrap();
+        chip8.load_rom(&[0x00,0xFE,0x00,0xC4,0x60,0x01]).unwrap();
+        chip8.start();
+        for _ in 0..3 {
+            chip8.execute_instruction().unwrap();
+        }
+        assert_eq!(chip8.cpu.program_counter(),0x206);
+        assert_eq!(*chip8.cpu.register(0),1);
+    }
+
+    #[test]
+    fn fonts_can_go_anywhere_the_rom_doesnt() {
+        let mut profile = Profile{font_base:0xE00,..Profile::default()};
+        let mut chip8 = Chip8::create(profile).unwrap();
+        chip8.load_rom(&[0xF0,0x29]).unwrap();
+        assert_eq!(chip8.cpu.memory().byte(0xE00),0xF0);
+        assert_eq!(chip8.load_rom(&vec![0;0xC01]).unwrap_err().kind,
+                   ErrorKind::RomOverlapsFonts{font_base:0xE00,start:0x200,end:0xE01});
+        profile.font_base = 0x1A0;
+        let mut chip8 = Chip8::create(profile).unwrap();
+        assert!(chip8.load_rom(&[0x00,0xE0]).is_err());
+    }
+
+    #[test]
+    fn scrolls_in_lores_and_hires() {
+        //LD I, #21C  LD V0, 8  LD V1, 8  DRW V0, V1, 1  SCD 2  SCR  SCL
+        //HIGH  DRW V0, V1, 1  SCD 3  SCR  SCL  SCD 1  then the sprite
+        let ops = [0xA21C,0x6008,0x6108,0xD011,0x00C2,0x00FB,0x00FC,
+                   0x00FF,0xD011,0x00C3,0x00FB,0x00FC,0x00C1,0x0000,0x8000];
+        let profile = Platform::SChipModern.profile();
+        assert_eq!(lit(&run(profile,&ops,4)),[(8,8)]);
+        assert_eq!(lit(&run(profile,&ops,5)),[(8,10)]);
+        assert_eq!(lit(&run(profile,&ops,6)),[(12,10)]);
+        assert_eq!(lit(&run(profile,&ops,7)),[(8,10)]);
+        let hires = run(profile,&ops,8);
+        assert!(hires.graphics().is_hires() && lit(&hires).is_empty());
+        assert_eq!(lit(&run(profile,&ops,10)),[(8,11)]);
+        assert_eq!(lit(&run(profile,&ops,11)),[(12,11)]);
+        assert_eq!(lit(&run(profile,&ops,12)),[(8,11)]);
+        assert_eq!(lit(&run(profile,&ops,13)),[(8,12)]);
+    }
+
+    #[test]
+    fn scrolling_drops_pixels_off_the_edge() {
+        //LD I, #20C  DRW V0, V0, 1  SCL  SCD 15  SCD 15  SCD 2  then the sprite
+        let ops = [0xA20C,0xD001,0x00FC,0x00CF,0x00CF,0x00C2,0xF000];
+        let profile = Platform::SChipModern.profile();
+        assert_eq!(lit(&run(profile,&ops,2)),[(0,0),(1,0),(2,0),(3,0)]);
+        assert!(lit(&run(profile,&ops,3)).is_empty());
+        let ops = [0xA20C,0xD001,0x00CF,0x00CF,0x00C2,0x0000,0xF000];
+        assert_eq!(lit(&run(profile,&ops,4)),[(0,30),(1,30),(2,30),(3,30)]);
+        assert!(lit(&run(profile,&ops,5)).is_empty());
+    }
 
+    #[test]
+    fn draws_16x16_sprites_with_collisions() {
+        //HIGH  LD I, #20E  DRW V0, V0, 0  DRW V0, V0, 0  LD V0, 120  DRW V0, V1, 0  EXIT  then 32 bytes of sprite
+        let mut ops = vec![0x00FF,0xA20E,0xD000,0xD000,0x6078,0xD010,0x00FD];
+        ops.extend([0xFFFF;16]);
+        let profile = Platform::SChipModern.profile();
+        let first = run(profile,&ops,3);
+        assert_eq!((lit(&first).len(),*first.cpu.register(0xF)),(256,0));
+        assert_eq!(lit(&first).last(),Some(&(15,15)));
+        let second = run(profile,&ops,4);
+        assert_eq!((lit(&second).len(),*second.cpu.register(0xF)),(0,1));
+        //clipped at the right edge without the wrap quirk
+        let clipped = run(profile,&ops,6);
+        assert_eq!((lit(&clipped).len(),*clipped.cpu.register(0xF)),(8*16,0));
+        assert!(lit(&clipped).iter().all(|(x,_)|*x >= 120));
+    }
+
+    #[test]
+    fn big_font_and_flag_registers() {
+        //LD V5, 7  LD HF, V5  LD V0, #11  LD V1, #22  LD V2, #33  LD R, V2
+        //LD V0, 0  LD V1, 0  LD V2, 0  LD V1, R
+        let ops = [0x6507,0xF530,0x6011,0x6122,0x6233,0xF275,0x6000,0x6100,0x6200,0xF185];
+        let chip8 = run(Platform::SChipModern.profile(),&ops,10);
+        let i = chip8.cpu.register_i();
+        assert_eq!(i,C8_BIG_FONT_START+70);
+        let digit:Vec<u8> = (i..i+10).map(|addr|chip8.cpu.memory().byte(addr)).collect();
+        assert_eq!(digit,[0xFF,0xFF,0x03,0x06,0x0C,0x18,0x30,0x60,0x60,0x60]);
+        assert_eq!(chip8.cpu.flags()[..4],[0x11,0x22,0x33,0]);
+        assert_eq!(chip8.cpu.registery()[..3],[0x11,0x22,0]);
+    }
+
+    #[test]
+    fn long_i_load_and_skipping_over_it() {
+        //LD I, #1234  LD V0, 1  SE V0, 1  LD I, #ABCD  LD V1, 2  SNE V0, 1  LD I, #BEEF
+        let ops = [0xF000,0x1234,0x6001,0x3001,0xF000,0xABCD,0x6102,0x4001,0xF000,0xBEEF];
+        let profile = Platform::XoChip.profile();
+        let chip8 = run(profile,&ops,1);
+        assert_eq!((chip8.cpu.register_i(),chip8.cpu.program_counter()),(0x1234,0x204));
+        let chip8 = run(profile,&ops,4);
+        assert_eq!((chip8.cpu.register_i(),chip8.cpu.program_counter(),*chip8.cpu.register(1)),(0x1234,0x20E,2));
+        let chip8 = run(profile,&ops,6);
+        assert_eq!((chip8.cpu.register_i(),chip8.cpu.program_counter()),(0xBEEF,0x214));
+    }
+
+    #[test]
+    fn saves_and_loads_register_ranges() {
+        //LD I, #300  LD V1, 1  LD V2, 2  LD V3, 3  SAVE V1 - V3  LD I, #310  SAVE V3 - V1
+        //LD I, #300  LOAD V3 - V2
+        let ops = [0xA300,0x6101,0x6202,0x6303,0x5132,0xA310,0x5312,0xA300,0x5323];
+        let chip8 = run(Platform::XoChip.profile(),&ops,9);
+        let memory = chip8.cpu.memory();
+        assert_eq!([memory.byte(0x300),memory.byte(0x301),memory.byte(0x302),memory.byte(0x303)],[1,2,3,0]);
+        assert_eq!([memory.byte(0x310),memory.byte(0x311),memory.byte(0x312)],[3,2,1]);
+        assert_eq!(chip8.cpu.registery()[..4],[0,1,2,1]);
+        assert_eq!(chip8.cpu.register_i(),0x300);
+    }
+
+    #[test]
+    fn planes_mask_drawing_and_clearing() {
+        //PLANE 2  LD I, #214  DRW V0, V0, 1  PLANE 3  DRW V0, V0, 1  PLANE 1  CLS  PLANE 0  DRW V0, V0, 1
+        //then a row for each plane
+        let ops = [0xF201,0xA214,0xD001,0xF301,0xD001,0xF101,0x00E0,0xF001,0xD001,0x0000,0x8000];
+        let profile = Platform::XoChip.profile();
+        let chip8 = run(profile,&ops,3);
+        assert_eq!((chip8.graphics().planes(),chip8.graphics().get(0,0)),(2,2));
+        //plane 1 gets the first row and plane 2 the second, which is blank
+        let chip8 = run(profile,&ops,5);
+        assert_eq!((chip8.graphics().get(0,0),*chip8.cpu.register(0xF)),(3,0));
+        assert_eq!(lit(&chip8),[(0,0)]);
+        let chip8 = run(profile,&ops,7);
+        assert_eq!(chip8.graphics().get(0,0),2);
+        let chip8 = run(profile,&ops,9);
+        assert_eq!((chip8.graphics().get(0,0),*chip8.cpu.register(0xF)),(2,0));
+    }
+
+    #[test]
+    fn loads_audio_patterns_and_pitch() {
+        //LD I, #20A  AUDIO  LD V4, 112  PITCH V4  EXIT  then the pattern
+        let mut ops = vec![0xA20A,0xF002,0x6470,0xF43A,0x00FD];
+        ops.extend([0xF00F,0x0000,0xAAAA,0x5555,0x0102,0x0304,0x0506,0x0708]);
+        let chip8 = run(Platform::XoChip.profile(),&ops,1);
+        assert_eq!((chip8.sound().pattern(),chip8.sound().pitch()),(None,C8_DEFAULT_PITCH));
+        assert_eq!(chip8.sound().playback_rate(),4000.0);
+        let chip8 = run(Platform::XoChip.profile(),&ops,4);
+        let sound = chip8.sound();
+        assert_eq!(sound.pattern().unwrap()[..],rom(&ops[5..])[..]);
+        assert_eq!(sound.pitch(),112);
+        assert_eq!(sound.playback_rate(),8000.0);
+        let bits:Vec<bool> = (0..8).map(|n|sound.sample(n).unwrap()).collect();
+        assert_eq!(bits,[true,true,true,true,false,false,false,false]);
+        assert_eq!(sound.sample(128+4),Some(false));
+    }
+
+    ///The default profile with quirks, and again with edit applied on top
+    fn quirk_pair(edit:impl Fn(&mut Quirks))->(Profile,Profile) {
+        let off = Profile::default();
+        let mut on = off;
+        edit(&mut on.quirks);
+        (off,on)
+    }
+
+    #[test]
+    fn vf_reset_quirk() {
+        //LD VF, 1  LD V1, 3  OR V0, V1
+        let ops = [0x6F01,0x6103,0x8011];
+        let (off,on) = quirk_pair(|q|q.vf_reset = true);
+        assert_eq!(*run(off,&ops,3).cpu.register(0xF),1);
+        assert_eq!(*run(on,&ops,3).cpu.register(0xF),0);
+    }
+
+    #[test]
+    fn shift_vy_quirk() {
+        //LD V0, 4  LD V1, 3  SHR V0, V1
+        let ops = [0x6004,0x6103,0x8016];
+        let (off,on) = quirk_pair(|q|q.shift_vy = true);
+        let (off,on) = (run(off,&ops,3),run(on,&ops,3));
+        assert_eq!((*off.cpu.register(0),*off.cpu.register(0xF)),(2,0));
+        assert_eq!((*on.cpu.register(0),*on.cpu.register(0xF)),(1,1));
+    }
+
+    #[test]
+    fn jump_vx_quirk() {
+        //LD V0, 2  LD V3, 4  JP V0, #310
+        let ops = [0x6002,0x6304,0xB310];
+        let (on,off) = quirk_pair(|q|q.jump_vx = false);
+        assert!(on.quirks.jump_vx);
+        assert_eq!(run(on,&ops,3).cpu.program_counter(),0x314);
+        assert_eq!(run(off,&ops,3).cpu.program_counter(),0x312);
+    }
+
+    #[test]
+    fn index_overflow_quirk() {
+        //LD VF, 5  LD V0, 1  LD I, #FFF  ADD I, V0, where I wraps with the 4K of memory
+        let ops = [0x6F05,0x6001,0xAFFF,0xF01E];
+        let (on,off) = quirk_pair(|q|q.index_overflow = false);
+        let (on,off) = (run(on,&ops,4),run(off,&ops,4));
+        assert_eq!((on.cpu.register_i(),*on.cpu.register(0xF)),(0,1));
+        assert_eq!((off.cpu.register_i(),*off.cpu.register(0xF)),(0,5));
+    }
+
+    #[test]
+    fn memory_increment_quirk() {
+        //LD I, #300  LD [I], V2  LD V2, [I]
+        let ops = [0xA300,0xF255,0xF265];
+        let (off,on) = quirk_pair(|q|q.memory_increment = true);
+        assert_eq!(run(off,&ops,3).cpu.register_i(),0x300);
+        assert_eq!(run(on,&ops,2).cpu.register_i(),0x303);
+        assert_eq!(run(on,&ops,3).cpu.register_i(),0x306);
+    }
+
+    #[test]
+    fn sprite_wrap_quirk() {
+        //LD V0, 62  LD V1, 31  LD I, #20A  DRW V0, V1, 2  then the sprite
+        let ops = [0x603E,0x611F,0xA20A,0xD012,0x0000,0xF0F0];
+        let (off,on) = quirk_pair(|q|q.sprite_wrap = true);
+        assert_eq!(lit(&run(off,&ops,4)),[(62,31),(63,31)]);
+        assert_eq!(lit(&run(on,&ops,4)),[(0,0),(1,0),(62,0),(63,0),(0,31),(1,31),(62,31),(63,31)]);
+    }
+
+    #[test]
+    fn display_wait_quirk() {
+        //DRW V0, V0, 1  DRW V0, V0, 1  JP #204
+        let ops = [0xD001,0xD001,0x1204];
+        let (off,on) = quirk_pair(|q|q.display_wait = true);
+        let mut off = run(off,&ops,0);
+        let mut on = run(on,&ops,0);
+        assert!(!off.frame_step().unwrap().ended);
+        assert!(on.frame_step().unwrap().ended);
+        assert_eq!((on.frame(),on.cpu.program_counter()),(1,0x202));
+    }
+
+    #[test]
+    fn key_press_quirk() {
+        //LD V0, K  JP #202
+        let ops = [0xF00A,0x1202];
+        let (off,on) = quirk_pair(|q|q.key_press = true);
+        let mut off = run(off,&ops,0);
+        let mut on = run(on,&ops,0);
+        for chip8 in [&mut off,&mut on] {
+            chip8.queue_input(KeyEvent::new(Key::Seven,KeyEventKind::KeyPress));
+        }
+        assert!(on.frame_step().unwrap().ran);
+        assert_eq!((*on.cpu.register(0),on.cpu.program_counter()),(7,0x202));
+        assert!(!off.frame_step().unwrap().ran);
+        assert_eq!(off.key_wait(),KeyWait::Release(0,Key::Seven));
+    }
+
+    #[test]
+    fn stack_depth_quirk() {
+        //CALL #200
+        let (_,on) = quirk_pair(|q|q.stack_depth = 2);
+        let mut chip8 = run(on,&[0x2200],2);
+        assert_eq!(chip8.execute_instruction().unwrap_err().kind,ErrorKind::StackOverflow{limit:2});
+    }
+}
@@ -1,19 +1,20 @@
 use crate::aux::Auxillary;
 use byteorder::{BigEndian,ReadBytesExt};
 use oorandom as rand;
-pub const CHIP8_MEM_SIZE:usize = 0xFFF;
+use crate::savestate::*;
+use crate::errors::{ErrorKind,Result};
+pub const CHIP8_MEM_SIZE:usize = 0x1000;
+pub const XOCHIP_MEM_SIZE:usize = 0x10000;
 pub const CHIP8_REGISTER_COUNT:usize = 0xF+1;
 pub const CHIP8_PROGRAM_START:u16=0x200;
+pub const CHIP8_FLAG_COUNT:usize = 0xF+1;
+///Calls that can be nested whatever the stack depth quirk says
+pub const CHIP8_MAX_STACK_DEPTH:usize = 256;
 macro_rules! sized_rptr {
     ($ptr:expr) => {
         ($ptr&0xF) as usize
     };
 }
-macro_rules! sized_ptr {
-    ($ptr:expr) => {
-        ($ptr&0xFFF)
-    };
-}
 
 pub struct MemoryStick{
     memory:Vec<u8>,
@@ -26,59 +27,26 @@ impl MemoryStick {
     pub fn open()->Self{
         Self::alloced(CHIP8_MEM_SIZE)
     }
+    ///Allocates max bytes, max must be a power of two so addresses can wrap with a mask
     #[inline]
     pub fn alloced(max:usize)->Self{
         Self {
             memory:vec![0u8;max],
             callstack:vec![],
-            size:max as u16
+            size:(max-1) as u16
         }
     }
-    fn _load_hex(&mut self,addr:&mut u16,iaddr:&mut usize,hex:&str)->bool{
-        let mut ic:u8=0;
-        let mut first =false;
-        let mut done=false;
-        for ch in hex.chars().nth(*iaddr){
-            *iaddr+=1;
-            if ch >='0' && ch <='9'{
-                if !first {
-                    ic = ((ch as u8 -'0' as u8)&0xF)<<4;
-                    first=true;
-                } else {
-                    ic |= (ch as u8 -'0' as u8)&0xF;
-                    *self.derefrence_mut(*addr) = ic;
-                    done=true;
-                    break;
-                }
-            }
-            else if ch>='a' && ch<='z' {
-                if !first {
-                    ic = ((ch as u8 -'a' as u8)&0xF)<<4;
-                    first=true;
-                } else {
-                    ic |= (ch as u8 -'a' as u8)&0xF;
-                    *self.derefrence_mut(*addr) = ic;
-                    done=true;
-                    break;
-                }
-            }
-            else if ch >='A' && ch<='Z' {
-                if !first {
-                    ic = ((ch as u8 -'A' as u8)&0xF)<<4;
-                    first=true;
-                } else {
-                    ic |= (ch as u8 -'A' as u8)&0xF;
-                    *self.derefrence_mut(*addr) = ic;
-                    done=true;
-                    break;
-                }
-            }
-            
-        }
-        if done {
-            *addr+=1;
-        }
-        done
+    #[inline]
+    pub fn len(&self)->usize {
+        self.memory.len()
+    }
+    #[inline]
+    pub fn is_empty(&self)->bool {
+        self.memory.is_empty()
+    }
+    #[inline]
+    pub fn wrap(&self,addr:u16)->u16 {
+        addr&self.size
     }
     pub fn load_memory(&mut self,addr:u16,other:MemoryStick) {
         self.load_binary(addr, &other.memory)
@@ -89,7 +57,7 @@ impl MemoryStick {
             if !skip{
                 println!("{:04X}\t{:04X}",idx,self.u16(idx))
             }
-            skip=!skip
+            skip = !skip
         }
     }
     #[inline]
@@ -101,30 +69,24 @@ impl MemoryStick {
     pub fn u16(&self,addr:u16)->u16 {
         ((*self.derefrence(addr) as u16)<<8)|(*self.derefrence(addr+1) as u16)
     }
-    #[inline]
-    pub fn load_hex(&mut self,addr:u16,hex:&str){
-        let mut addr = addr;
-        let mut iaddr = 0;
-        while self._load_hex(&mut addr, &mut iaddr, hex){
-
-        }
-    }
-    pub fn loadn_hex(&mut self,addr:u16,hex:&str,n:u16){
-        let mut addr = addr;
-        let mut iaddr = 0;
-        let mut count =0;
-        while self._load_hex(&mut addr, &mut iaddr, hex){
-            count+=1;
-            if count >=n {
-                break;
-            }
+    ///Writes the bytes of a hex string from addr, returning how many there were
+    pub fn load_hex(&mut self,addr:u16,hex:&str)->Result<usize>{
+        let bytes = parse_hex(hex)?;
+        self.load_binary(addr,&bytes);
+        Ok(bytes.len())
+    }
+    ///Writes the first n bytes of a hex string from addr, failing if it has fewer
+    pub fn loadn_hex(&mut self,addr:u16,hex:&str,n:u16)->Result<()>{
+        let bytes = parse_hex(hex)?;
+        if bytes.len() < n as usize {
+            return Err(ErrorKind::HexTooShort{expected:n as usize,found:bytes.len()}.into());
         }
+        self.load_binary(addr,&bytes[..n as usize]);
+        Ok(())
     }
     pub fn load_binary(&mut self,addr:u16,binary:&[u8]){
-        let mut addr = addr;
-        for byte in binary{
-            *self.derefrence_mut(addr)=*byte;
-            addr+=1;
+        for (addr,byte) in (addr..).zip(binary.iter()){
+            *self.derefrence_mut(addr) = *byte;
         }
     }
     pub fn load_big_binary_instructions(&mut self,addr:u16,binary:&[u8]){
@@ -137,12 +99,10 @@ impl MemoryStick {
         }
     }
     pub fn load_little_binary_instructions(&mut self,addr:u16,binary:&[u8]){
-        let mut addr = addr;
         let mut cursor = std::io::Cursor::new(binary);
-        for byte in cursor.read_u16::<BigEndian>(){
+        if let Ok(byte) = cursor.read_u16::<BigEndian>(){
             *self.derefrence_mut(addr)=(byte>>8) as u8;
             *self.derefrence_mut(addr+1)=(byte&0xFF) as u8;
-            addr+=2;
         }
     }
     pub (crate)fn derefrence(&self,pointer:u16)->&u8 {
@@ -152,36 +112,110 @@ impl MemoryStick {
         self.memory.get_mut((pointer&self.size) as usize).unwrap()
     }
     pub fn fill(&mut self,base_ptr:u16,vals:&[u8]){
-        let mut n =0;
-        for val in vals{
-            self.memory[(((base_ptr+n)&self.size)) as usize]=*val;
-            n+=1;
+        for (n,val) in (0..).zip(vals.iter()){
+            self.memory[((base_ptr+n)&self.size) as usize] = *val;
         }
     }
-    pub fn push(&mut self,ptr:u16){
-        self.callstack.push(ptr)
+    ///False once CHIP8_MAX_STACK_DEPTH calls are on the stack
+    pub fn push(&mut self,ptr:u16)->bool{
+        if self.callstack.len() >= CHIP8_MAX_STACK_DEPTH {
+            return false;
+        }
+        self.callstack.push(ptr);
+        true
     }
     pub fn pop(&mut self)->Option<u16>{
         self.callstack.pop()
-    } 
+    }
+    #[inline]
+    pub fn stack_depth(&self)->usize{
+        self.callstack.len()
+    }
+    ///Return addresses, innermost call last
+    #[inline]
+    pub fn callstack(&self)->&[u16]{
+        &self.callstack
+    }
+    #[inline]
+    pub fn byte(&self,addr:u16)->u8{
+        *self.derefrence(addr)
+    }
+    #[inline]
+    pub fn set_byte(&mut self,addr:u16,byte:u8){
+        *self.derefrence_mut(addr) = byte
+    }
+}
+
+impl Snapshot for MemoryStick {
+    fn save(&self,out:&mut Vec<u8>) {
+        put_u32(out,self.memory.len() as u32);
+        out.extend_from_slice(&self.memory);
+        put_u16(out,self.callstack.len() as u16);
+        for addr in &self.callstack {
+            put_u16(out,*addr);
+        }
+    }
+    fn load(&mut self,input:&mut &[u8])->std::io::Result<()> {
+        let len = get_u32(input)? as usize;
+        if !len.is_power_of_two() || len > XOCHIP_MEM_SIZE {
+            return Err(invalid("bad memory size"));
+        }
+        let mut memory = vec![0u8;len];
+        get_bytes(input,&mut memory)?;
+        let depth = get_u16(input)? as usize;
+        if depth > CHIP8_MAX_STACK_DEPTH {
+            return Err(invalid("call stack too deep"));
+        }
+        let mut callstack = Vec::with_capacity(depth);
+        for _ in 0..depth {
+            callstack.push(get_u16(input)?);
+        }
+        self.memory = memory;
+        self.callstack = callstack;
+        self.size = (len-1) as u16;
+        Ok(())
+    }
 }
 
+///Bytes from pairs of hex digits, whitespace may separate the pairs
+pub fn parse_hex(hex:&str)->Result<Vec<u8>>{
+    let mut bytes = Vec::with_capacity(hex.len()/2);
+    let mut high:Option<(usize,u8)> = None;
+    for (offset,ch) in hex.char_indices() {
+        if ch.is_whitespace() && high.is_none() {
+            continue;
+        }
+        let digit = ch.to_digit(16).ok_or(ErrorKind::InvalidHex{offset})? as u8;
+        match high.take() {
+            Some((_,high))=>bytes.push(high<<4|digit),
+            None=>high = Some((offset,digit))
+        }
+    }
+    match high {
+        Some((offset,_))=>Err(ErrorKind::InvalidHex{offset}.into()),
+        None=>Ok(bytes)
+    }
+}
+///The 16 small characters, 5 bytes each, from base
 #[inline]
-pub fn load_font(memory:&mut MemoryStick,hex:&str){
-    memory.loadn_hex(0x0, hex,16*5)
+pub fn load_font(memory:&mut MemoryStick,base:u16,hex:&str)->Result<()>{
+    memory.loadn_hex(base,hex,16*5)
 }
 #[inline]
-pub fn load_charfont(memory:&mut MemoryStick,ch:u8,hex:&str){
-    memory.loadn_hex(((ch&0xF) as u16)*5, hex,5)
+pub fn load_charfont(memory:&mut MemoryStick,base:u16,ch:u8,hex:&str)->Result<()>{
+    memory.loadn_hex(base+((ch&0xF) as u16)*5,hex,5)
+}
+///The 16 big characters, 10 bytes each, after the small ones
+#[inline]
+pub fn load_big_font(memory:&mut MemoryStick,base:u16,hex:&str)->Result<()>{
+    memory.loadn_hex(base+crate::fonts::C8_BIG_FONT_START,hex,16*10)
 }
-
-
-
 
 pub struct DenseCpu {
     pc:u16,
     regi:u16,
     registers:[u8;CHIP8_REGISTER_COUNT],
+    flags:[u8;CHIP8_FLAG_COUNT],
     aux_pack: Auxillary,
     memory:MemoryStick,
     rgen:rand::Rand32
@@ -193,7 +227,8 @@ impl DenseCpu {
             pc:CHIP8_PROGRAM_START,
             regi:0,
             registers:[0u8;CHIP8_REGISTER_COUNT],
-            memory:memory,
+            flags:[0u8;CHIP8_FLAG_COUNT],
+            memory,
             aux_pack:aux,
             rgen:rand::Rand32::new(0xFCFB)
         }
@@ -205,7 +240,6 @@ impl DenseCpu {
     #[inline]
     pub fn fill_registers(&mut self,mut addr:u16,reg:u8) {
         for regi in 0..=reg {
-            //println!("{} regi reads {:04X} {:04X}",regi,*self.memory().derefrence(addr),addr);
             *self.register_mut(regi) = *self.memory().derefrence(addr);
             addr+=1
         }
@@ -218,6 +252,45 @@ impl DenseCpu {
             addr+=1;
         }
     }
+    ///XO-CHIP 5XY2, stores vx..=vy (in either direction) starting at I
+    pub fn save_range(&mut self,x:u8,y:u8) {
+        let addr = self.regi;
+        for (n,reg) in Self::range(x,y).enumerate() {
+            let b = *self.register(reg);
+            *self.memory_mut().derefrence_mut(addr+n as u16)=b;
+        }
+    }
+    ///XO-CHIP 5XY3, loads vx..=vy (in either direction) starting at I
+    pub fn load_range(&mut self,x:u8,y:u8) {
+        let addr = self.regi;
+        for (n,reg) in Self::range(x,y).enumerate() {
+            *self.register_mut(reg) = *self.memory().derefrence(addr+n as u16);
+        }
+    }
+    #[inline]
+    fn range(x:u8,y:u8)->impl Iterator<Item=u8> {
+        let (x,y) = (x&0xF,y&0xF);
+        let len = y.abs_diff(x);
+        (0..=len).map(move |n| if x<=y {x+n} else {x-n})
+    }
+    ///Copies v0..=vx into the SCHIP RPL user flags
+    #[inline]
+    pub fn save_flags(&mut self,reg:u8) {
+        for reg in 0..=sized_rptr!(reg) {
+            self.flags[reg] = self.registers[reg];
+        }
+    }
+    ///Copies the SCHIP RPL user flags back into v0..=vx
+    #[inline]
+    pub fn restore_flags(&mut self,reg:u8) {
+        for reg in 0..=sized_rptr!(reg) {
+            self.registers[reg] = self.flags[reg];
+        }
+    }
+    #[inline]
+    pub fn flags(&self) ->&[u8] {
+        &self.flags
+    }
     #[inline]
     pub fn registery(&self) ->&[u8] {
         &self.registers
@@ -232,7 +305,7 @@ impl DenseCpu {
     }
     #[inline]
     pub fn set_register_i(&mut self,i:u16){
-        self.regi = sized_ptr!(i)
+        self.regi = self.memory.wrap(i)
     }
     #[inline]
     pub fn deref_i(&self)->&u8 {
@@ -248,7 +321,7 @@ impl DenseCpu {
     }
     #[inline]
     pub fn instruction(&self)->u16 {
-        return self.memory().u16(self.pc)
+        self.memory().u16(self.pc)
     }
     #[inline]
     pub fn inc_instruction(&mut self) {
@@ -259,6 +332,10 @@ impl DenseCpu {
        self.jump(self.pc-2)
     }
     #[inline]
+    pub fn set_register(&mut self,ptr:u8,byte:u8){
+        *self.register_mut(ptr) = byte
+    }
+    #[inline]
     pub(crate) fn register_mut(&mut self,ptr:u8) ->&mut u8 {
         self.registers.get_mut(sized_rptr!(ptr)).unwrap()
     }
@@ -288,16 +365,18 @@ impl DenseCpu {
     }
     #[inline]
     pub fn jump(&mut self,addr:u16){
-        self.pc=sized_ptr!(addr)
+        self.pc=self.memory.wrap(addr)
     }
     #[inline]
     pub fn jumpn(&mut self,n:u16){
-        self.jump(self.pc+sized_ptr!(n))
+        self.jump(self.pc+n)
     }
     #[inline]
-    pub fn call(&mut self,addr:u16){
-        self.memory.push(sized_ptr!(self.pc));
-        self.jump(addr)
+    pub fn call(&mut self,addr:u16)->bool{
+        match self.memory.push(self.pc){
+            true=>{self.jump(addr);true},
+            false=>false
+        }
     }
     #[inline]
     pub fn ret(&mut self)->bool{
@@ -315,4 +394,71 @@ impl DenseCpu {
 
 }
 
-pub type DefaultCpu = DenseCpu;
\ No newline at end of file
+impl Snapshot for DenseCpu {
+    fn save(&self,out:&mut Vec<u8>) {
+        put_u16(out,self.pc);
+        put_u16(out,self.regi);
+        out.extend_from_slice(&self.registers);
+        out.extend_from_slice(&self.flags);
+        let (state,inc) = self.rgen.state();
+        put_u64(out,state);
+        put_u64(out,inc);
+        self.memory.save(out);
+        self.aux_pack.save(out);
+    }
+    fn load(&mut self,input:&mut &[u8])->std::io::Result<()> {
+        self.pc = get_u16(input)?;
+        self.regi = get_u16(input)?;
+        get_bytes(input,&mut self.registers)?;
+        get_bytes(input,&mut self.flags)?;
+        let state = get_u64(input)?;
+        let inc = get_u64(input)?;
+        self.rgen = rand::Rand32::from_state((state,inc));
+        self.memory.load(input)?;
+        self.aux_pack.load(input)?;
+        self.pc = self.memory.wrap(self.pc);
+        self.regi = self.memory.wrap(self.regi);
+        Ok(())
+    }
+}
+
+pub type DefaultCpu = DenseCpu;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fonts::C8_CLASSIC_FONT;
+
+    fn invalid_at(hex:&str)->ErrorKind {
+        parse_hex(hex).unwrap_err().kind
+    }
+
+    #[test]
+    fn parse_hex_reads_pairs() {
+        assert_eq!(parse_hex("AF").unwrap(),[0xAF]);
+        assert_eq!(parse_hex("af").unwrap(),[0xAF]);
+        assert_eq!(parse_hex(" 0a F0\n12\t").unwrap(),[0x0A,0xF0,0x12]);
+        assert!(parse_hex("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_hex_rejects_odd_digits_and_non_hex() {
+        assert_eq!(invalid_at("ABC"),ErrorKind::InvalidHex{offset:2});
+        assert_eq!(invalid_at("AF 1"),ErrorKind::InvalidHex{offset:3});
+        assert_eq!(invalid_at("AG"),ErrorKind::InvalidHex{offset:1});
+        assert_eq!(invalid_at("0x1F"),ErrorKind::InvalidHex{offset:1});
+        //whitespace only goes between pairs
+        assert_eq!(invalid_at("A F"),ErrorKind::InvalidHex{offset:1});
+    }
+
+    #[test]
+    fn loads_the_classic_font() {
+        let mut memory = MemoryStick::alloced(CHIP8_MEM_SIZE);
+        load_font(&mut memory,0x50,C8_CLASSIC_FONT).unwrap();
+        let bytes:Vec<u8> = (0x50..0x50+16*5).map(|addr|memory.byte(addr)).collect();
+        assert_eq!(bytes[..5],[0xF0,0x90,0x90,0x90,0xF0]);
+        assert_eq!(bytes[5..10],[0x20,0x60,0x20,0x20,0x70]);
+        assert_eq!(bytes[75..],[0xF0,0x80,0xF0,0x80,0x80]);
+        assert_eq!((memory.byte(0x4F),memory.byte(0xA0)),(0,0));
+        assert_eq!(load_font(&mut memory,0,"F0909090F0").unwrap_err().kind,ErrorKind::HexTooShort{expected:80,found:5});
+    }
+}
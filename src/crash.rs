@@ -0,0 +1,218 @@
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path,PathBuf};
+use crate::chip8::Chip8;
+use crate::debugger::registers;
+use crate::errors::{Error,Result as C8Result,SourceError};
+use crate::keyboard::{Key,KeyEvent,KeyEventKind};
+
+///Key presses and releases by the frame they reached the machine
+#[derive(Default,Clone)]
+pub struct InputLog {
+    events:Vec<(u64,KeyEvent)>,
+}
+
+impl InputLog {
+    #[inline]
+    pub fn new()->Self {
+        Self::default()
+    }
+    #[inline]
+    pub fn record(&mut self,frame:u64,event:KeyEvent) {
+        self.events.push((frame,event))
+    }
+    #[inline]
+    pub fn clear(&mut self) {
+        self.events.clear()
+    }
+    #[inline]
+    pub fn len(&self)->usize {
+        self.events.len()
+    }
+    #[inline]
+    pub fn is_empty(&self)->bool {
+        self.events.is_empty()
+    }
+    ///One tab separated line per event: frame, hex key and press or release
+    pub fn text(&self)->String {
+        let mut out = String::new();
+        for (frame,event) in &self.events {
+            let kind = match event.kind {
+                KeyEventKind::KeyPress=>"press",
+                _=>"release"
+            };
+            let _ = writeln!(out,"{}\t{:X}\t{}",frame,event.key.into_byte(),kind);
+        }
+        out
+    }
+    ///Reads back what text wrote
+    pub fn parse(file:&str,text:&str)->Result<Self,SourceError> {
+        let mut log = Self::new();
+        for (n,line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields:Vec<&str> = line.split_whitespace().collect();
+            let event = match fields[..] {
+                [frame,key,kind]=>{
+                    let frame = frame.parse::<u64>().ok();
+                    let key = u8::from_str_radix(key,16).ok().and_then(Key::from_byte);
+                    let kind = match kind {
+                        "press"=>Some(KeyEventKind::KeyPress),
+                        "release"=>Some(KeyEventKind::KeyRelease),
+                        _=>None
+                    };
+                    frame.zip(key).zip(kind).map(|((frame,key),kind)|(frame,KeyEvent::new(key,kind)))
+                }
+                _=>None
+            };
+            let (frame,event) = event.ok_or_else(||SourceError::new(file,n+1,"expected `frame key press|release`".to_string()))?;
+            log.record(frame,event);
+        }
+        Ok(log)
+    }
+    ///Queues every event for its frame, load the start state first so the frames line up
+    pub fn replay(&self,chip8:&mut Chip8) {
+        for (frame,event) in &self.events {
+            chip8.queue_input_at(*frame,*event);
+        }
+    }
+}
+
+///The error, registers, call stack and recently executed addresses
+pub fn crash_report(chip8:&Chip8,error:&Error)->String {
+    let mut out = format!("{}\n\n{}\n\nlast executed:\n",error,registers(chip8));
+    let history:Vec<String> = chip8.pc_history().map(|pc|format!("{:04X}",pc)).collect();
+    for line in history.chunks(12) {
+        let _ = writeln!(out,"{}",line.join(" "));
+    }
+    out
+}
+
+///Everything needed to reproduce a crash: replaying the input log from the start state
+///should end in the crash state
+pub struct CrashBundle<'a> {
+    pub report:String,
+    pub rom:&'a [u8],
+    pub start_state:&'a [u8],
+    pub crash_state:Vec<u8>,
+    pub inputs:&'a InputLog,
+}
+
+impl<'a> CrashBundle<'a> {
+    ///Writes the bundle into a new directory named after the game, returning it
+    pub fn write(&self,game:&Path)->io::Result<PathBuf> {
+        let stamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d|d.as_secs()).unwrap_or(0);
+        let mut name = game.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".crash-{}",stamp));
+        let dir = game.with_file_name(name);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("report.txt"),&self.report)?;
+        std::fs::write(dir.join("rom.ch8"),self.rom)?;
+        std::fs::write(dir.join("start.state"),self.start_state)?;
+        std::fs::write(dir.join("crash.state"),&self.crash_state)?;
+        std::fs::write(dir.join("input.log"),self.inputs.text())?;
+        Ok(dir)
+    }
+}
+
+///A bundle read back from the directory CrashBundle::write made
+pub struct SavedBundle {
+    pub rom:Vec<u8>,
+    pub start_state:Vec<u8>,
+    pub crash_state:Vec<u8>,
+    pub inputs:InputLog,
+}
+
+impl SavedBundle {
+    pub fn read(dir:&Path)->Result<Self,SourceError> {
+        let read = |name:&str|{
+            let path = dir.join(name);
+            std::fs::read(&path).map_err(|e|SourceError::new(&path.display().to_string(),0,e.to_string()))
+        };
+        let log = dir.join("input.log");
+        let text = String::from_utf8_lossy(&read("input.log")?).into_owned();
+        Ok(Self{
+            rom:read("rom.ch8")?,
+            start_state:read("start.state")?,
+            crash_state:read("crash.state")?,
+            inputs:InputLog::parse(&log.display().to_string(),&text)?,
+        })
+    }
+    ///Loads the start state and runs up to frames with the logged inputs, returning the crash if it
+    ///happens again. The machine is closed after a crash, as it was when crash.state was saved
+    pub fn replay(&self,chip8:&mut Chip8,frames:u64)->C8Result<Option<Error>> {
+        chip8.load_state(&self.start_state)?;
+        self.inputs.replay(chip8);
+        for _ in 0..frames {
+            if let Err(e) = chip8.run_frame() {
+                chip8.close();
+                return Ok(Some(e));
+            }
+        }
+        Ok(None)
+    }
+    ///Whether the machine is now exactly as it was when the bundle was written
+    #[inline]
+    pub fn matches_crash(&self,chip8:&Chip8)->bool {
+        chip8.save_state() == self.crash_state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::Key;
+    use crate::platform::Profile;
+
+    #[test]
+    fn replaying_a_bundle_reaches_the_same_crash() {
+        //waits for a key, then returns with nothing on the stack
+        let rom = [0x60,0x07,0xF1,0x0A,0x00,0xEE];
+        let mut chip8 = Chip8::create(Profile::default()).unwrap();
+        chip8.load_rom(&rom).unwrap();
+        chip8.start();
+        for _ in 0..3 {
+            chip8.run_frame().unwrap();
+        }
+        let start_state = chip8.save_state();
+        let mut inputs = InputLog::new();
+        let mut crash = None;
+        for frame in 0..20 {
+            let event = match frame {
+                5=>Some(KeyEvent::new(Key::from_byte(0xA).unwrap(),KeyEventKind::KeyPress)),
+                7=>Some(KeyEvent::new(Key::from_byte(0xA).unwrap(),KeyEventKind::KeyRelease)),
+                _=>None
+            };
+            if let Some(event) = event {
+                inputs.record(chip8.frame(),event);
+                chip8.queue_input(event);
+            }
+            if let Err(e) = chip8.run_frame() {
+                chip8.close();
+                crash = Some(e);
+                break
+            }
+        }
+        let crash = crash.expect("the rom should crash once the key is released");
+        let dir = std::env::temp_dir().join(format!("chip8-replay-{}",std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bundle = CrashBundle{
+            report:crash_report(&chip8,&crash),
+            rom:&rom,
+            start_state:&start_state,
+            crash_state:chip8.save_state(),
+            inputs:&inputs,
+        };
+        let written = bundle.write(&dir.join("game.ch8")).unwrap();
+        let saved = SavedBundle::read(&written);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let saved = saved.unwrap();
+        assert_eq!(saved.rom,rom);
+
+        let mut replay = Chip8::create(Profile::default()).unwrap();
+        assert_eq!(saved.replay(&mut replay,100).unwrap(),Some(crash));
+        assert_eq!(*replay.cpu.register(1),0xA);
+        assert!(saved.matches_crash(&replay));
+    }
+}
@@ -0,0 +1,601 @@
+use std::collections::{BTreeMap,BTreeSet};
+use std::io::{self,BufRead,Write};
+use std::path::{Path,PathBuf};
+use serde_json::{json,Value};
+use crate::chip8::Chip8;
+use crate::debugger::{Debugger,Stop,parse_number};
+use crate::encoder::ListingLine;
+use crate::octo::Octo;
+use crate::platform::{Platform,Profile};
+
+///The only thread the adapter reports
+pub const C8_DAP_THREAD:u64 = 1;
+///variablesReference of the register scope
+const C8_DAP_REGISTERS:u64 = 1;
+
+///Reads one Content-Length framed message, None at the end of the stream
+pub fn read_message(input:&mut impl BufRead)->io::Result<Option<Value>> {
+    let mut len = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name,value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let len = len.ok_or_else(||io::Error::new(io::ErrorKind::InvalidData,"missing Content-Length"))?;
+    let mut body = vec![0;len];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e|io::Error::new(io::ErrorKind::InvalidData,e))
+}
+
+pub fn write_message(out:&mut impl Write,message:&Value)->io::Result<()> {
+    let body = message.to_string();
+    write!(out,"Content-Length: {}\r\n\r\n{}",body.len(),body)?;
+    out.flush()
+}
+
+const C8_BASE64:&[u8;64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+///Standard padded base64, which is how readMemory hands over bytes
+pub fn base64(bytes:&[u8])->String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3)*4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32)<<16 | (*chunk.get(1).unwrap_or(&0) as u32)<<8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for k in 0..4 {
+            if k <= chunk.len() {
+                out.push(C8_BASE64[(n>>(18-k*6)) as usize&0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+///Maps source lines to addresses and back through an assembler listing
+#[derive(Default)]
+pub struct SourceMap {
+    lines:Vec<ListingLine>,
+    dir:PathBuf,
+}
+
+impl SourceMap {
+    ///File names in the listing are taken relative to dir, the listing's directory
+    pub fn from_listing(text:&str,dir:&Path)->Self {
+        Self{
+            lines:text.lines().filter_map(ListingLine::parse).collect(),
+            dir:dir.to_path_buf(),
+        }
+    }
+    pub fn load(path:&Path)->io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(Self::from_listing(&text,path.parent().unwrap_or_else(||Path::new("."))))
+    }
+    fn same_file(&self,listed:&str,path:&Path)->bool {
+        let listed = Path::new(listed);
+        if path.ends_with(listed) {
+            return true;
+        }
+        match (self.dir.join(listed).canonicalize(),path.canonicalize()) {
+            (Ok(a),Ok(b))=>a == b,
+            _=>false
+        }
+    }
+    ///The first statement at or after line, with the line it's actually on
+    pub fn address(&self,path:&Path,line:usize)->Option<(usize,u16)> {
+        self.lines.iter()
+            .filter(|l|l.line >= line && !l.bytes.is_empty() && self.same_file(&l.file,path))
+            .min_by_key(|l|l.line)
+            .map(|l|(l.line,l.addr))
+    }
+    ///The statement whose bytes cover addr
+    pub fn location(&self,addr:u16)->Option<&ListingLine> {
+        self.lines.iter().find(|l|l.addr <= addr && (addr as usize) < l.addr as usize+l.bytes.len())
+    }
+    fn path(&self,listed:&str)->PathBuf {
+        let path = self.dir.join(listed);
+        path.canonicalize().unwrap_or(path)
+    }
+}
+
+///A response body, and an event to send once the response is out, or why the request failed
+type Reply = Result<(Value,Option<(&'static str,&'static str)>),String>;
+
+///The stopped event reason for a Stop
+fn reason(stop:&Stop)->&'static str {
+    match stop {
+        Stop::Breakpoint(_)=>"breakpoint",
+        Stop::Watchpoint{..}=>"data breakpoint",
+        Stop::Step=>"step",
+        Stop::Error(_)=>"exception",
+    }
+}
+
+///Debug Adapter Protocol server, requests go in through handle and the machine
+///advances through run_frame, which runs frames with a Debugger
+pub struct DapServer<W:Write> {
+    out:W,
+    seq:u64,
+    profile:Profile,
+    chip8:Option<Chip8>,
+    map:SourceMap,
+    source_breakpoints:BTreeMap<PathBuf,Vec<u16>>,
+    instruction_breakpoints:BTreeSet<u16>,
+    stop_on_entry:bool,
+    debugger:Debugger,
+    done:bool,
+}
+
+impl<W:Write> DapServer<W> {
+    ///profile is used for programs launched without a platform of their own
+    pub fn new(profile:Profile,out:W)->Self {
+        Self{
+            out,
+            seq:0,
+            profile,
+            chip8:None,
+            map:SourceMap::default(),
+            source_breakpoints:BTreeMap::new(),
+            instruction_breakpoints:BTreeSet::new(),
+            stop_on_entry:false,
+            debugger:Debugger::new(),
+            done:false,
+        }
+    }
+    #[inline]
+    pub fn is_running(&self)->bool {
+        self.chip8.is_some() && !self.debugger.is_paused()
+    }
+    ///True after disconnect
+    #[inline]
+    pub fn is_done(&self)->bool {
+        self.done
+    }
+    #[inline]
+    pub fn chip8(&self)->Option<&Chip8> {
+        self.chip8.as_ref()
+    }
+    fn send(&mut self,mut message:Value)->io::Result<()> {
+        self.seq+=1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.out,&message)
+    }
+    fn event(&mut self,event:&str,body:Value)->io::Result<()> {
+        self.send(json!({"type":"event","event":event,"body":body}))
+    }
+    fn stopped(&mut self,reason:&str,text:Option<String>)->io::Result<()> {
+        self.debugger.pause();
+        let mut body = json!({"reason":reason,"threadId":C8_DAP_THREAD,"allThreadsStopped":true});
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.event("stopped",body)
+    }
+    fn is_breakpoint(&self,addr:u16)->bool {
+        self.debugger.breakpoints().any(|b|*b == addr)
+    }
+    ///Hands the debugger every source and instruction breakpoint
+    fn sync_breakpoints(&mut self) {
+        self.debugger.clear_breakpoints();
+        for addr in self.instruction_breakpoints.iter().chain(self.source_breakpoints.values().flatten()) {
+            self.debugger.add_breakpoint(*addr);
+        }
+    }
+    ///Answers one request, sending its response and any events it causes
+    pub fn handle(&mut self,request:&Value)->io::Result<()> {
+        let command = request["command"].as_str().unwrap_or("");
+        let args = &request["arguments"];
+        let (body,after) = match self.respond(command,args) {
+            Ok(ok)=>ok,
+            Err(message)=>{
+                return self.send(json!({"type":"response","request_seq":request["seq"],"success":false,
+                                        "command":command,"message":message}));
+            }
+        };
+        self.send(json!({"type":"response","request_seq":request["seq"],"success":true,"command":command,"body":body}))?;
+        match after {
+            Some(("stopped",reason))=>self.stopped(reason,None),
+            Some((event,_))=>self.event(event,json!({})),
+            None=>Ok(())
+        }
+    }
+    fn respond(&mut self,command:&str,args:&Value)->Reply {
+        let after = match command {
+            "initialize"=>return Ok((json!({
+                "supportsConfigurationDoneRequest":true,
+                "supportsInstructionBreakpoints":true,
+                "supportsDisassembleRequest":true,
+                "supportsReadMemoryRequest":true,
+                "supportsSetVariable":true,
+                "supportsTerminateRequest":true,
+            }),Some(("initialized","")))),
+            "launch"=>{
+                self.launch(args)?;
+                None
+            }
+            "configurationDone"=>{
+                let pc = self.machine()?.cpu.program_counter();
+                if self.stop_on_entry {
+                    Some(("stopped","entry"))
+                } else if self.is_breakpoint(pc) {
+                    Some(("stopped","breakpoint"))
+                } else {
+                    self.debugger.resume();
+                    None
+                }
+            }
+            "setBreakpoints"=>return Ok((self.set_breakpoints(args),None)),
+            "setInstructionBreakpoints"=>{
+                self.instruction_breakpoints.clear();
+                let mut verified = vec![];
+                for bp in args["breakpoints"].as_array().into_iter().flatten() {
+                    let addr = bp["instructionReference"].as_str().and_then(parse_number)
+                        .map(|a|a.wrapping_add(bp["offset"].as_i64().unwrap_or(0) as u16));
+                    verified.push(match addr {
+                        Some(addr)=>{
+                            self.instruction_breakpoints.insert(addr);
+                            json!({"verified":true,"instructionReference":format!("0x{:04X}",addr)})
+                        }
+                        None=>json!({"verified":false,"message":"not an address"})
+                    });
+                }
+                self.sync_breakpoints();
+                return Ok((json!({"breakpoints":verified}),None));
+            }
+            "threads"=>return Ok((json!({"threads":[{"id":C8_DAP_THREAD,"name":"chip8"}]}),None)),
+            "stackTrace"=>return self.stack_trace(),
+            "scopes"=>return Ok((json!({"scopes":[{"name":"Registers","variablesReference":C8_DAP_REGISTERS,"expensive":false}]}),None)),
+            "variables"=>return self.variables(args),
+            "setVariable"=>return self.set_variable(args),
+            "readMemory"=>return self.read_memory(args),
+            "disassemble"=>return self.disassemble(args),
+            "continue"=>{
+                self.machine()?;
+                self.debugger.resume();
+                return Ok((json!({"allThreadsContinued":true}),None));
+            }
+            "next"=>{
+                let chip8 = self.chip8.as_mut().ok_or("no program launched")?;
+                return stepped(self.debugger.next(chip8));
+            }
+            "stepIn"=>{
+                let chip8 = self.chip8.as_mut().ok_or("no program launched")?;
+                return stepped(Some(self.debugger.step(chip8)));
+            }
+            "stepOut"=>{
+                let chip8 = self.chip8.as_ref().ok_or("no program launched")?;
+                return stepped(self.debugger.finish(chip8));
+            }
+            "pause"=>Some(("stopped","pause")),
+            "disconnect"|"terminate"=>{
+                if let Some(chip8) = &mut self.chip8 {
+                    chip8.close();
+                }
+                self.done = true;
+                if command == "terminate" {
+                    Some(("terminated",""))
+                } else {
+                    None
+                }
+            }
+            other=>return Err(format!("unsupported request '{}'",other))
+        };
+        Ok((json!({}),after))
+    }
+    fn machine(&mut self)->Result<&mut Chip8,String> {
+        self.chip8.as_mut().ok_or_else(||"no program launched".to_string())
+    }
+    fn launch(&mut self,args:&Value)->Result<(),String> {
+        let program = Path::new(args["program"].as_str().ok_or("launch needs a program")?);
+        let profile = match args["platform"].as_str() {
+            Some(name)=>Profile::from(Platform::from_name(name).ok_or_else(||format!("unknown platform '{}'",name))?),
+            None=>self.profile
+        };
+        let rom = match program.extension() {
+            Some(ext) if ext == "8o"=>{
+                let text = std::fs::read_to_string(program).map_err(|e|format!("{}: {}",program.display(),e))?;
+                let compiled = Octo::new().with_target(profile.set).compile(&program.display().to_string(),&text)
+                    .map_err(|e|e.to_string())?;
+                self.instruction_breakpoints.extend(compiled.breakpoints.values());
+                compiled.bytes
+            }
+            _=>std::fs::read(program).map_err(|e|format!("{}: {}",program.display(),e))?
+        };
+        if let Some(listing) = args["listing"].as_str() {
+            self.map = SourceMap::load(Path::new(listing)).map_err(|e|format!("{}: {}",listing,e))?;
+        }
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        let mut chip8 = Chip8::create(profile).map_err(|e|e.to_string())?;
+        chip8.load_rom(&rom).map_err(|e|e.to_string())?;
+        chip8.start();
+        self.chip8 = Some(chip8);
+        self.sync_breakpoints();
+        self.debugger.pause();
+        Ok(())
+    }
+    fn set_breakpoints(&mut self,args:&Value)->Value {
+        let path = PathBuf::from(args["source"]["path"].as_str().unwrap_or(""));
+        let mut addrs = vec![];
+        let mut verified = vec![];
+        for bp in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = bp["line"].as_u64().unwrap_or(0) as usize;
+            verified.push(match self.map.address(&path,line) {
+                Some((line,addr))=>{
+                    addrs.push(addr);
+                    json!({"verified":true,"line":line,"instructionReference":format!("0x{:04X}",addr)})
+                }
+                None=>json!({"verified":false,"line":line,"message":"no code at or after this line in the listing"})
+            });
+        }
+        self.source_breakpoints.insert(path,addrs);
+        self.sync_breakpoints();
+        json!({"breakpoints":verified})
+    }
+    ///A stack frame for code at addr, named and placed by the listing when there is one
+    fn frame(&self,id:usize,addr:u16)->Value {
+        let mut frame = json!({"id":id,"name":format!("{:04X}",addr),"line":0,"column":0,
+                               "instructionPointerReference":format!("0x{:04X}",addr)});
+        if let Some(line) = self.map.location(addr) {
+            frame["name"] = json!(format!("{:04X} {}",addr,line.text));
+            frame["line"] = json!(line.line);
+            frame["column"] = json!(1);
+            frame["source"] = json!({"path":self.map.path(&line.file)});
+        }
+        frame
+    }
+    fn stack_trace(&mut self)->Reply {
+        let chip8 = self.machine()?;
+        let pc = chip8.cpu.program_counter();
+        //each return address follows its 2NNN, so the caller's frame points at the call
+        let calls:Vec<u16> = chip8.cpu.memory().callstack().iter().rev().map(|ret|ret.wrapping_sub(2)).collect();
+        let mut frames = vec![self.frame(0,pc)];
+        for (n,addr) in calls.iter().enumerate() {
+            frames.push(self.frame(n+1,*addr));
+        }
+        let total = frames.len();
+        Ok((json!({"stackFrames":frames,"totalFrames":total}),None))
+    }
+    fn variables(&mut self,args:&Value)->Reply {
+        if args["variablesReference"].as_u64() != Some(C8_DAP_REGISTERS) {
+            return Ok((json!({"variables":[]}),None));
+        }
+        let chip8 = self.machine()?;
+        let cpu = &chip8.cpu;
+        let mut vars:Vec<Value> = cpu.registery().iter().enumerate()
+            .map(|(n,v)|json!({"name":format!("V{:X}",n),"value":format!("0x{:02X}",v),"type":"u8","variablesReference":0}))
+            .collect();
+        let i = cpu.register_i();
+        let pc = cpu.program_counter();
+        vars.push(json!({"name":"I","value":format!("0x{:04X}",i),"type":"u16","variablesReference":0,
+                         "memoryReference":format!("0x{:04X}",i)}));
+        vars.push(json!({"name":"PC","value":format!("0x{:04X}",pc),"type":"u16","variablesReference":0,
+                         "memoryReference":format!("0x{:04X}",pc)}));
+        vars.push(json!({"name":"DT","value":format!("0x{:02X}",chip8.delay_timer()),"type":"u8","variablesReference":0}));
+        vars.push(json!({"name":"ST","value":format!("0x{:02X}",chip8.sound_timer()),"type":"u8","variablesReference":0}));
+        Ok((json!({"variables":vars}),None))
+    }
+    fn set_variable(&mut self,args:&Value)->Reply {
+        let name = args["name"].as_str().unwrap_or("").to_lowercase();
+        let text = args["value"].as_str().unwrap_or("");
+        let value = parse_number(text).ok_or_else(||format!("'{}' isn't a number",text))?;
+        let chip8 = self.machine()?;
+        let shown = match name.as_str() {
+            "i"=>{
+                chip8.cpu.set_register_i(value);
+                format!("0x{:04X}",value)
+            }
+            "pc"=>{
+                chip8.cpu.jump(value);
+                format!("0x{:04X}",value)
+            }
+            "dt"=>{
+                chip8.set_delay_timer(value as u8);
+                format!("0x{:02X}",value as u8)
+            }
+            "st"=>{
+                chip8.set_sound_timer(value as u8);
+                format!("0x{:02X}",value as u8)
+            }
+            reg if reg.len() == 2 && reg.starts_with('v')=>{
+                let x = u8::from_str_radix(&reg[1..],16).map_err(|_|format!("no register {}",reg))?;
+                chip8.cpu.set_register(x,value as u8);
+                format!("0x{:02X}",value as u8)
+            }
+            _=>return Err(format!("can't set '{}'",name))
+        };
+        Ok((json!({"value":shown}),None))
+    }
+    fn read_memory(&mut self,args:&Value)->Reply {
+        let reference = args["memoryReference"].as_str().unwrap_or("");
+        let base = parse_number(reference).ok_or_else(||format!("'{}' isn't an address",reference))?;
+        let start = base as i64+args["offset"].as_i64().unwrap_or(0);
+        let count = args["count"].as_u64().unwrap_or(0) as i64;
+        let memory = self.machine()?.cpu.memory();
+        let len = memory.len() as i64;
+        let first = start.clamp(0,len);
+        let last = (start+count).clamp(first,len);
+        let bytes:Vec<u8> = (first..last).map(|a|memory.byte(a as u16)).collect();
+        Ok((json!({"address":format!("0x{:04X}",first),"data":base64(&bytes),"unreadableBytes":count-(last-first)}),None))
+    }
+    fn disassemble(&mut self,args:&Value)->Reply {
+        let reference = args["memoryReference"].as_str().unwrap_or("");
+        let base = parse_number(reference).ok_or_else(||format!("'{}' isn't an address",reference))? as i64;
+        let start = base+args["offset"].as_i64().unwrap_or(0)+args["instructionOffset"].as_i64().unwrap_or(0)*2;
+        let count = args["instructionCount"].as_u64().unwrap_or(0);
+        let len = self.machine()?.cpu.memory().len() as i64;
+        let mut instructions = vec![];
+        for n in 0..count as i64 {
+            let addr = start+n*2;
+            if addr < 0 || addr+1 >= len {
+                instructions.push(json!({"address":format!("0x{:04X}",addr.max(0)),"instruction":"","presentationHint":"invalid"}));
+                continue;
+            }
+            let addr = addr as u16;
+            let chip8 = self.machine()?;
+            let opcode = chip8.cpu.memory().u16(addr);
+            let text = match chip8.decode_at(addr) {
+                Ok(instruction)=>instruction.to_string(),
+                Err(_)=>format!("db #{:02X}, #{:02X}",opcode>>8,opcode&0xFF)
+            };
+            let mut instruction = json!({"address":format!("0x{:04X}",addr),"instructionBytes":format!("{:04X}",opcode),"instruction":text});
+            if let Some(line) = self.map.location(addr) {
+                instruction["line"] = json!(line.line);
+                instruction["location"] = json!({"path":self.map.path(&line.file)});
+            }
+            instructions.push(instruction);
+        }
+        Ok((json!({"instructions":instructions}),None))
+    }
+    ///Runs the rest of a 60hz frame unless paused, reporting why execution stopped if it did
+    pub fn run_frame(&mut self)->io::Result<()> {
+        let chip8 = match &mut self.chip8 {
+            Some(chip8) if !self.debugger.is_paused()=>chip8,
+            _=>return Ok(())
+        };
+        match self.debugger.run_frame(chip8) {
+            Some(Stop::Error(error))=>self.stopped("exception",Some(error.to_string())),
+            Some(stop)=>self.stopped(reason(&stop),None),
+            None=>Ok(())
+        }
+    }
+}
+
+///The reply to a step request, a stopped event once it's done or nothing while it keeps running
+fn stepped(stop:Option<Stop>)->Reply {
+    match stop {
+        Some(Stop::Error(error))=>Err(error.to_string()),
+        Some(stop)=>Ok((json!({}),Some(("stopped",reason(&stop))))),
+        None=>Ok((json!({}),None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn framed(messages:&[Value])->Vec<u8> {
+        let mut out = vec![];
+        for message in messages {
+            write_message(&mut out,message).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn read_message_splits_frames() {
+        let mut bytes = framed(&[json!({"seq":1}),json!({"seq":2})]);
+        bytes.splice(0..0,b"Content-Type: application/json\r\n".iter().copied());
+        let mut input = Cursor::new(bytes);
+        assert_eq!(read_message(&mut input).unwrap(),Some(json!({"seq":1})));
+        assert_eq!(read_message(&mut input).unwrap(),Some(json!({"seq":2})));
+        assert_eq!(read_message(&mut input).unwrap(),None);
+    }
+
+    #[test]
+    fn read_message_rejects_bad_headers() {
+        let cases:[&[u8];4] = [
+            b"Content-Type: json\r\n\r\n{}",
+            b"Content-Length: two\r\n\r\n{}",
+            b"Content-Length: 2\r\n\r\n{",
+            b"Content-Length: 5\r\n\r\nnope!",
+        ];
+        for bytes in cases {
+            assert!(read_message(&mut Cursor::new(bytes)).is_err(),"{}",String::from_utf8_lossy(bytes));
+        }
+    }
+
+    #[test]
+    fn base64_pads() {
+        let cases = [("",""),("f","Zg=="),("fo","Zm8="),("foo","Zm9v"),("foob","Zm9vYg=="),("fooba","Zm9vYmE="),("foobar","Zm9vYmFy")];
+        for (bytes,text) in cases {
+            assert_eq!(base64(bytes.as_bytes()),text);
+        }
+        assert_eq!(base64(&[0xFF,0xFE,0x00]),"//4A");
+    }
+
+    #[test]
+    fn source_map_round_trips() {
+        let listing = "0200\t6005\tgame.8o:2\tv0 := 5\n0202\t\tgame.8o:3\t: loop\n0202\t7001\tgame.8o:4\tv0 += 1\n0204\t1202\tgame.8o:5\tjump loop\n";
+        let map = SourceMap::from_listing(listing,Path::new("."));
+        let path = Path::new("roms/game.8o");
+        for (line,addr) in [(2,0x200),(4,0x202),(5,0x204)] {
+            assert_eq!(map.address(path,line),Some((line,addr)));
+            assert_eq!(map.location(addr).map(|l|l.line),Some(line));
+            assert_eq!(map.location(addr+1).map(|l|l.line),Some(line));
+        }
+        //a label has no bytes, so it's the statement after it that breaks
+        assert_eq!(map.address(path,3),Some((4,0x202)));
+        assert_eq!(map.address(path,6),None);
+        assert_eq!(map.address(Path::new("other.8o"),2),None);
+        assert!(map.location(0x206).is_none());
+    }
+
+    #[test]
+    fn continue_stops_at_an_instruction_breakpoint() {
+        //CLS  LD V0, 5  ADD V0, 1  JP #204
+        let program = std::env::temp_dir().join(format!("chip8-dap-{}.ch8",std::process::id()));
+        std::fs::write(&program,[0x00,0xE0,0x60,0x05,0x70,0x01,0x12,0x04]).unwrap();
+        let requests = framed(&[
+            json!({"seq":1,"type":"request","command":"initialize","arguments":{}}),
+            json!({"seq":2,"type":"request","command":"launch","arguments":{"program":program}}),
+            json!({"seq":3,"type":"request","command":"setInstructionBreakpoints","arguments":{"breakpoints":[{"instructionReference":"0x206"}]}}),
+            json!({"seq":4,"type":"request","command":"configurationDone"}),
+            json!({"seq":5,"type":"request","command":"continue","arguments":{"threadId":C8_DAP_THREAD}}),
+        ]);
+        let mut out = vec![];
+        let mut server = DapServer::new(Profile::default(),&mut out);
+        let mut input = Cursor::new(requests);
+        while let Some(request) = read_message(&mut input).unwrap() {
+            server.handle(&request).unwrap();
+        }
+        std::fs::remove_file(&program).unwrap();
+        assert!(server.is_running());
+        server.run_frame().unwrap();
+        assert!(!server.is_running());
+        let chip8 = server.chip8().unwrap();
+        assert_eq!((chip8.cpu.program_counter(),*chip8.cpu.register(0)),(0x206,6));
+        drop(server);
+
+        let mut output = Cursor::new(out);
+        let mut messages = vec![];
+        while let Some(message) = read_message(&mut output).unwrap() {
+            messages.push(message);
+        }
+        let kinds:Vec<String> = messages.iter()
+            .map(|m|format!("{} {}",m["type"].as_str().unwrap(),m["command"].as_str().or_else(||m["event"].as_str()).unwrap()))
+            .collect();
+        assert_eq!(kinds,["response initialize","event initialized","response launch","response setInstructionBreakpoints",
+                          "response configurationDone","response continue","event stopped"]);
+        assert!(messages[..6].iter().all(|m|m["type"] == "event" || m["success"] == true));
+        assert_eq!(messages[3]["body"]["breakpoints"][0]["verified"],true);
+        assert_eq!(messages[6]["body"]["reason"],"breakpoint");
+        assert_eq!(messages.last().unwrap()["seq"],7);
+    }
+
+    #[test]
+    fn running_frames_tick_the_timers() {
+        //LD V0, 10  LD DT, V0  JP #204
+        let program = std::env::temp_dir().join(format!("chip8-dap-timer-{}.ch8",std::process::id()));
+        std::fs::write(&program,[0x60,0x0A,0xF0,0x15,0x12,0x04]).unwrap();
+        let mut server = DapServer::new(Profile::default(),io::sink());
+        server.handle(&json!({"seq":1,"command":"launch","arguments":{"program":program}})).unwrap();
+        std::fs::remove_file(&program).unwrap();
+        server.handle(&json!({"seq":2,"command":"configurationDone"})).unwrap();
+        for _ in 0..3 {
+            server.run_frame().unwrap();
+        }
+        let chip8 = server.chip8().unwrap();
+        assert_eq!((chip8.frame(),chip8.delay_timer()),(3,7));
+    }
+}
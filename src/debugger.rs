@@ -0,0 +1,431 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use crate::chip8::{Chip8,KeyWait};
+use crate::errors::{Error,ErrorKind};
+use crate::instruction::Instruction;
+
+///What a watchpoint fires on
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+impl WatchKind {
+    #[inline]
+    fn matches(&self,write:bool)->bool {
+        match self {
+            WatchKind::Read=>!write,
+            WatchKind::Write=>write,
+            WatchKind::Access=>true,
+        }
+    }
+}
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub struct Watchpoint {
+    pub addr:u16,
+    pub len:u16,
+    pub kind:WatchKind,
+}
+
+///Memory an instruction is about to touch through I
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub struct MemoryAccess {
+    pub addr:u16,
+    pub len:u16,
+    pub write:bool,
+}
+
+///Why execution paused
+#[derive(Clone,PartialEq,Debug)]
+pub enum Stop {
+    Breakpoint(u16),
+    Watchpoint{pc:u16,access:MemoryAccess},
+    ///A step, next or finish completed
+    Step,
+    Error(Error),
+}
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+enum Mode {
+    Paused,
+    Running,
+    ///Runs until the call at this depth returns to ret
+    Over{depth:usize,ret:u16},
+    ///Runs until the stack is shallower than depth
+    Finish{depth:usize},
+}
+
+///The memory the instruction at PC reads or writes, if any
+pub fn memory_access(chip8:&Chip8)->Option<MemoryAccess> {
+    let instruction = chip8.decode_at(chip8.cpu.program_counter()).ok()?;
+    let (addr,len) = chip8.memory_span(instruction)?;
+    let write = matches!(instruction,Instruction::StoreRegs(_)|Instruction::SaveRange{..}|Instruction::Bcd(_));
+    Some(MemoryAccess{addr,len,write})
+}
+
+///Breakpoints, watchpoints and stepping on top of a Chip8
+pub struct Debugger {
+    breakpoints:BTreeSet<u16>,
+    watchpoints:Vec<Watchpoint>,
+    mode:Mode,
+}
+
+impl Default for Debugger {
+    #[inline]
+    fn default()->Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    ///Starts paused
+    #[inline]
+    pub fn new()->Self {
+        Self{breakpoints:BTreeSet::new(),watchpoints:vec![],mode:Mode::Paused}
+    }
+    #[inline]
+    pub fn is_paused(&self)->bool {
+        self.mode == Mode::Paused
+    }
+    #[inline]
+    pub fn pause(&mut self) {
+        self.mode = Mode::Paused
+    }
+    #[inline]
+    pub fn resume(&mut self) {
+        self.mode = Mode::Running
+    }
+    #[inline]
+    pub fn breakpoints(&self)->impl Iterator<Item=&u16> {
+        self.breakpoints.iter()
+    }
+    #[inline]
+    pub fn add_breakpoint(&mut self,addr:u16)->bool {
+        self.breakpoints.insert(addr)
+    }
+    #[inline]
+    pub fn remove_breakpoint(&mut self,addr:u16)->bool {
+        self.breakpoints.remove(&addr)
+    }
+    #[inline]
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear()
+    }
+    #[inline]
+    pub fn watchpoints(&self)->&[Watchpoint] {
+        &self.watchpoints
+    }
+    #[inline]
+    pub fn add_watchpoint(&mut self,watchpoint:Watchpoint) {
+        self.watchpoints.push(watchpoint)
+    }
+    ///Removes every watchpoint starting at addr
+    pub fn remove_watchpoint(&mut self,addr:u16)->bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w|w.addr != addr);
+        len != self.watchpoints.len()
+    }
+    fn watched(&self,access:&MemoryAccess)->bool {
+        let (start,end) = (access.addr as u32,access.addr as u32+access.len as u32);
+        self.watchpoints.iter().any(|w|{
+            w.kind.matches(access.write) && (w.addr as u32) < end && start < w.addr as u32+w.len.max(1) as u32
+        })
+    }
+    ///Runs one instruction as part of the current frame, checking watchpoints.
+    ///Returns whether the frame ended, or why execution has to stop
+    fn execute(&mut self,chip8:&mut Chip8)->Result<bool,Stop> {
+        let pc = chip8.cpu.program_counter();
+        let access = memory_access(chip8).filter(|a|self.watched(a));
+        let step = chip8.frame_step().map_err(Stop::Error)?;
+        //a blocked FX0A didn't run, let alone touch memory
+        match access {
+            Some(access) if step.ran=>Err(Stop::Watchpoint{pc,access}),
+            _=>Ok(step.ended)
+        }
+    }
+    ///Runs a single instruction and stays paused
+    pub fn step(&mut self,chip8:&mut Chip8)->Stop {
+        self.mode = Mode::Paused;
+        match self.execute(chip8) {
+            Ok(_)=>Stop::Step,
+            Err(stop)=>stop
+        }
+    }
+    ///Like step, but runs a whole 2NNN call as one step, the caller keeps calling run_frame
+    pub fn next(&mut self,chip8:&mut Chip8)->Option<Stop> {
+        match chip8.decode_at(chip8.cpu.program_counter()) {
+            Ok(Instruction::Call(_))=>{
+                let ret = chip8.cpu.program_counter().wrapping_add(2);
+                self.mode = Mode::Over{depth:chip8.cpu.memory().stack_depth(),ret};
+                None
+            }
+            _=>Some(self.step(chip8))
+        }
+    }
+    ///Runs until the current call returns, the caller keeps calling run_frame
+    pub fn finish(&mut self,chip8:&Chip8)->Option<Stop> {
+        let depth = chip8.cpu.memory().stack_depth();
+        if depth == 0 {
+            let cpu = &chip8.cpu;
+            return Some(Stop::Error(Error::new(ErrorKind::StackUnderflow).at(cpu.program_counter(),cpu.instruction(),&[])));
+        }
+        self.mode = Mode::Finish{depth};
+        None
+    }
+    ///Runs the rest of a 60hz frame unless paused, or until something stops execution
+    pub fn run_frame(&mut self,chip8:&mut Chip8)->Option<Stop> {
+        if self.mode == Mode::Paused {
+            return None;
+        }
+        loop {
+            let ended = match self.execute(chip8) {
+                Ok(ended)=>ended,
+                Err(stop)=>{
+                    self.mode = Mode::Paused;
+                    return Some(stop);
+                }
+            };
+            let pc = chip8.cpu.program_counter();
+            let depth = chip8.cpu.memory().stack_depth();
+            let done = match self.mode {
+                Mode::Over{depth:d,ret}=>depth == d && pc == ret,
+                Mode::Finish{depth:d}=>depth < d,
+                _=>false
+            };
+            if done {
+                self.mode = Mode::Paused;
+                return Some(Stop::Step);
+            }
+            if self.breakpoints.contains(&pc) {
+                self.mode = Mode::Paused;
+                return Some(Stop::Breakpoint(pc));
+            }
+            if ended {
+                return None;
+            }
+        }
+    }
+    ///Runs one command of the REPL, returning what to print
+    pub fn command(&mut self,chip8:&mut Chip8,line:&str)->Result<String,String> {
+        let words:Vec<&str> = line.split_whitespace().collect();
+        let arg = |n:usize|->Result<u16,String>{
+            let word = words.get(n).ok_or("missing argument")?;
+            parse_number(word).ok_or_else(||format!("'{}' isn't a number",word))
+        };
+        let mut out = String::new();
+        match words.first().copied().unwrap_or("") {
+            ""=>{}
+            "b"|"break"=>{
+                let addr = arg(1)?;
+                self.add_breakpoint(addr);
+                let _ = write!(out,"breakpoint at {:04X}",addr);
+            }
+            "d"|"delete"=>match words.get(1) {
+                Some(_)=>{
+                    let addr = arg(1)?;
+                    if !self.remove_breakpoint(addr) && !self.remove_watchpoint(addr) {
+                        return Err(format!("nothing set at {:04X}",addr));
+                    }
+                }
+                None=>{
+                    self.clear_breakpoints();
+                    self.watchpoints.clear();
+                }
+            }
+            kind @ ("watch"|"rwatch"|"awatch")=>{
+                let addr = arg(1)?;
+                let len = if words.len() > 2 {arg(2)?} else {1};
+                let kind = match kind {
+                    "watch"=>WatchKind::Write,
+                    "rwatch"=>WatchKind::Read,
+                    _=>WatchKind::Access
+                };
+                self.add_watchpoint(Watchpoint{addr,len:len.max(1),kind});
+                let _ = write!(out,"{:?} watchpoint at {:04X}..{:04X}",kind,addr,addr as u32+len.max(1) as u32);
+            }
+            "i"|"info"=>{
+                for addr in &self.breakpoints {
+                    let _ = writeln!(out,"breakpoint {:04X}",addr);
+                }
+                for w in &self.watchpoints {
+                    let _ = writeln!(out,"{:?} watchpoint {:04X}..{:04X}",w.kind,w.addr,w.addr as u32+w.len as u32);
+                }
+            }
+            "s"|"step"=>{
+                let n = if words.len() > 1 {arg(1)?} else {1};
+                let mut stop = Stop::Step;
+                for _ in 0..n.max(1) {
+                    stop = self.step(chip8);
+                    if stop != Stop::Step {
+                        break;
+                    }
+                }
+                out = describe(chip8,stop);
+            }
+            "n"|"next"=>if let Some(stop) = self.next(chip8) {
+                out = describe(chip8,stop);
+            }
+            "f"|"finish"=>if let Some(stop) = self.finish(chip8) {
+                out = describe(chip8,stop);
+            }
+            "c"|"continue"=>self.resume(),
+            "p"|"pause"=>{
+                self.pause();
+                out = registers(chip8);
+            }
+            "r"|"regs"=>out = registers(chip8),
+            "x"=>{
+                let addr = if words.len() > 1 {arg(1)?} else {chip8.cpu.register_i()};
+                let len = if words.len() > 2 {arg(2)?} else {64};
+                out = hexdump(chip8,addr,len);
+            }
+            "l"|"list"=>{
+                let mut addr = if words.len() > 1 {arg(1)?} else {chip8.cpu.program_counter()};
+                let n = if words.len() > 2 {arg(2)?} else {8};
+                for _ in 0..n {
+                    let opcode = chip8.cpu.memory().u16(addr);
+                    let text = match chip8.decode_at(addr) {
+                        Ok(instruction)=>instruction.to_string(),
+                        Err(_)=>format!("db #{:02X}, #{:02X}",opcode>>8,opcode&0xFF)
+                    };
+                    let marker = if addr == chip8.cpu.program_counter() {'>'} else {' '};
+                    let _ = writeln!(out,"{}{:04X}: {:04X}  {}",marker,addr,opcode,text);
+                    addr = addr.wrapping_add(2);
+                }
+            }
+            "set"=>{
+                let target = words.get(1).ok_or("set what?")?.to_lowercase();
+                let value = arg(2)?;
+                match target.as_str() {
+                    "i"=>chip8.cpu.set_register_i(value),
+                    "pc"=>chip8.cpu.jump(value),
+                    "dt"=>chip8.set_delay_timer(value as u8),
+                    "st"=>chip8.set_sound_timer(value as u8),
+                    reg if reg.len() == 2 && reg.starts_with('v')=>{
+                        let x = u8::from_str_radix(&reg[1..],16).map_err(|_|format!("no register {}",reg))?;
+                        chip8.cpu.set_register(x,value as u8);
+                    }
+                    _=>return Err(format!("can't set '{}'",target))
+                }
+            }
+            "poke"=>{
+                let addr = arg(1)?;
+                for n in 2..words.len() {
+                    let byte = arg(n)?;
+                    chip8.cpu.memory_mut().set_byte(addr.wrapping_add(n as u16-2),byte as u8);
+                }
+            }
+            "h"|"help"=>out = C8_DEBUGGER_HELP.to_string(),
+            other=>return Err(format!("unknown command '{}', try help",other))
+        }
+        Ok(out)
+    }
+}
+
+pub const C8_DEBUGGER_HELP:&str = "\
+break ADDR            pause when PC reaches ADDR
+watch ADDR [LEN]      pause after a write to ADDR (rwatch: reads, awatch: both)
+delete [ADDR]         remove a breakpoint or watchpoint, or all of them
+info                  list breakpoints and watchpoints
+step [N]              run N instructions
+next                  step, running calls to completion
+finish                run until the current call returns
+continue              run until a breakpoint or watchpoint
+pause                 stop running
+regs                  show registers, I, PC, timers and the stack
+x [ADDR] [LEN]        hex dump memory, from I by default
+list [ADDR] [N]       disassemble N instructions, from PC by default
+set vX|i|pc|dt|st N  change a register
+poke ADDR BYTE...     write bytes to memory
+quit                  exit";
+
+///Reads 0x1F, #1F, $1F or plain decimal
+pub fn parse_number(text:&str)->Option<u16> {
+    let text = text.trim();
+    if let Some(hex) = text.strip_prefix("0x").or_else(||text.strip_prefix('#')).or_else(||text.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+///A line saying where execution stopped, followed by the registers
+pub fn describe(chip8:&Chip8,stop:Stop)->String {
+    let mut out = match stop {
+        Stop::Breakpoint(addr)=>format!("breakpoint at {:04X}\n",addr),
+        Stop::Watchpoint{pc,access}=>format!("{:04X} {} {:04X}..{:04X}\n",pc,if access.write {"wrote"} else {"read"},
+                                             access.addr,access.addr as u32+access.len as u32),
+        Stop::Error(error)=>format!("{}\n",error),
+        Stop::Step=>String::new(),
+    };
+    out.push_str(&registers(chip8));
+    out
+}
+
+pub fn registers(chip8:&Chip8)->String {
+    let cpu = &chip8.cpu;
+    let mut out = String::new();
+    for (n,reg) in cpu.registery().iter().enumerate() {
+        let sep = match n {
+            0=>"",
+            8=>"\n",
+            _=>" "
+        };
+        let _ = write!(out,"{}V{:X}={:02X}",sep,n,reg);
+    }
+    let pc = cpu.program_counter();
+    let next = match chip8.decode_at(cpu.program_counter()) {
+        Ok(instruction)=>instruction.to_string(),
+        Err(_)=>"invalid".to_string()
+    };
+    let _ = writeln!(out,"\nI={:04X} PC={:04X} DT={:02X} ST={:02X}",cpu.register_i(),pc,chip8.delay_timer(),chip8.sound_timer());
+    let stack:Vec<String> = cpu.memory().callstack().iter().map(|a|format!("{:04X}",a)).collect();
+    let _ = writeln!(out,"stack=[{}]",stack.join(" "));
+    match chip8.key_wait() {
+        KeyWait::Idle=>{}
+        KeyWait::Press(x)=>{let _ = writeln!(out,"FX0A waiting for a key into V{:X}",x);}
+        KeyWait::Release(x,key)=>{let _ = writeln!(out,"FX0A waiting for key {:X} to be released into V{:X}",key.into_byte(),x);}
+    }
+    let _ = write!(out,"{:04X}: {:04X}  {}",pc,cpu.instruction(),next);
+    out
+}
+
+pub fn hexdump(chip8:&Chip8,addr:u16,len:u16)->String {
+    let memory = chip8.cpu.memory();
+    let mut out = String::new();
+    for row in (0..len as u32).step_by(16) {
+        let start = addr.wrapping_add(row as u16);
+        let _ = write!(out,"{:04X}:",start);
+        for n in 0..16.min(len as u32-row) {
+            let _ = write!(out," {:02X}",memory.byte(start.wrapping_add(n as u16)));
+        }
+        out.push('\n');
+    }
+    out.pop();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocked_fx0a_hits_no_watchpoints() {
+        let mut chip8 = Chip8::waiting_for_key();
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(Watchpoint{addr:0x300,len:1,kind:WatchKind::Write});
+        debugger.resume();
+        for _ in 0..5 {
+            assert!(debugger.run_frame(&mut chip8).is_none());
+            assert_eq!(chip8.cpu.program_counter(),0x202);
+        }
+        match debugger.run_frame(&mut chip8) {
+            Some(Stop::Watchpoint{pc,access})=>assert_eq!((pc,access.addr,access.write),(0x204,0x300,true)),
+            _=>panic!("expected the LD [I], V0 after the key to hit the watchpoint")
+        }
+        assert_eq!(chip8.cpu.memory().byte(0x300),5);
+    }
+}
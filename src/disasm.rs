@@ -0,0 +1,223 @@
+use std::collections::{BTreeSet,VecDeque};
+use std::fmt::Write;
+use crate::chip8::InstructionSet;
+use crate::instruction::Instruction;
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+enum Byte {
+    Unknown,
+    Code,
+    ///Second half (or operand) of an instruction
+    Operand,
+}
+
+///Splits a rom into code and data by following every jump, call and skip from its entry point
+pub struct Disassembler<'a> {
+    rom:&'a [u8],
+    origin:u16,
+    set:InstructionSet,
+    bytes:Vec<Byte>,
+    labels:BTreeSet<u16>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(rom:&'a [u8],origin:u16,set:InstructionSet)->Self {
+        let mut disasm = Self{
+            rom,
+            origin,
+            set,
+            bytes:vec![Byte::Unknown;rom.len()],
+            labels:BTreeSet::new(),
+        };
+        //the first pass finds what I points at, the second stops code running on into it
+        let data = disasm.trace(&BTreeSet::new());
+        disasm.trace(&data);
+        disasm
+    }
+    #[inline]
+    fn offset(&self,addr:u16)->Option<usize> {
+        let offset = addr.checked_sub(self.origin)? as usize;
+        if offset < self.rom.len() {Some(offset)} else {None}
+    }
+    #[inline]
+    fn word(&self,addr:u16)->Option<u16> {
+        let offset = self.offset(addr)?;
+        let low = *self.rom.get(offset+1)?;
+        Some(((self.rom[offset] as u16)<<8)|low as u16)
+    }
+    ///The instruction starting at addr, None for data or anything outside the rom
+    fn decode(&self,addr:u16)->Option<Instruction> {
+        Instruction::decode(self.word(addr)?,self.set).ok()
+    }
+    ///Marks everything reachable from the origin, falling through into an address in data ends
+    ///the code there. Returns the addresses loaded into I
+    fn trace(&mut self,data:&BTreeSet<u16>)->BTreeSet<u16> {
+        self.bytes.iter_mut().for_each(|byte|*byte = Byte::Unknown);
+        self.labels.clear();
+        let mut pointers = BTreeSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(self.origin);
+        while let Some(mut addr) = queue.pop_front() {
+            loop {
+                let offset = match self.offset(addr) {
+                    Some(offset) if self.bytes[offset] == Byte::Unknown=>offset,
+                    _=>break
+                };
+                let instruction = match self.decode(addr) {
+                    Some(instruction)=>instruction,
+                    _=>break
+                };
+                let len = instruction.len() as usize;
+                //a jump into the middle of an instruction leaves what's already marked alone
+                if offset+len > self.rom.len() || self.bytes[offset+1..offset+len].iter().any(|b|*b != Byte::Unknown) {
+                    break;
+                }
+                self.bytes[offset] = Byte::Code;
+                for byte in &mut self.bytes[offset+1..offset+len] {
+                    *byte = Byte::Operand;
+                }
+                let next = addr+len as u16;
+                match instruction {
+                    Instruction::Jp(target)=>{
+                        self.labels.insert(target);
+                        queue.push_back(target);
+                        break;
+                    }
+                    Instruction::JpV0(target)=>{
+                        //only the base of the jump table is known
+                        self.labels.insert(target);
+                        queue.push_back(target);
+                        break;
+                    }
+                    Instruction::Call(target)=>{
+                        self.labels.insert(target);
+                        queue.push_back(target);
+                    }
+                    Instruction::Ret|Instruction::Exit=>break,
+                    Instruction::LdI(target)=>{
+                        self.labels.insert(target);
+                        pointers.insert(target);
+                    }
+                    Instruction::LdILong(_)=>{
+                        if let Some(target) = self.word(addr+2) {
+                            self.labels.insert(target);
+                            pointers.insert(target);
+                        }
+                    }
+                    Instruction::SeByte{..}|Instruction::SneByte{..}|Instruction::SeReg{..}|
+                    Instruction::SneReg{..}|Instruction::Skp(_)|Instruction::Sknp(_)=>{
+                        let skipped = self.decode(next).map(|i|i.len()).unwrap_or(2);
+                        queue.push_back(next+skipped);
+                    }
+                    _=>{}
+                }
+                if data.contains(&next) {
+                    break;
+                }
+                addr = next;
+            }
+        }
+        pointers
+    }
+    #[inline]
+    pub fn is_code(&self,addr:u16)->bool {
+        self.offset(addr).map(|offset|self.bytes[offset] != Byte::Unknown).unwrap_or(false)
+    }
+    ///Labels that start a line of output, anything else is printed as a plain address
+    fn has_label(&self,addr:u16)->bool {
+        self.labels.contains(&addr) && match self.offset(addr) {
+            Some(offset)=>self.bytes[offset] != Byte::Operand,
+            _=>false
+        }
+    }
+    fn target(&self,addr:u16)->String {
+        if self.has_label(addr) {
+            format!("label_{:03X}",addr)
+        } else {
+            format!("#{:03X}",addr)
+        }
+    }
+    fn mnemonic(&self,addr:u16,instruction:Instruction)->String {
+        match instruction {
+            Instruction::Sys(target)=>format!("SYS {}",self.target(target)),
+            Instruction::Jp(target)=>format!("JP {}",self.target(target)),
+            Instruction::Call(target)=>format!("CALL {}",self.target(target)),
+            Instruction::LdI(target)=>format!("LD I, {}",self.target(target)),
+            Instruction::JpV0(target)=>format!("JP V0, {}",self.target(target)),
+            Instruction::LdILong(_)=>format!("LD I, LONG {}",self.target(self.word(addr+2).unwrap_or(0))),
+            _=>instruction.to_string()
+        }
+    }
+    ///Assembly listing of the whole rom, sprites and other data are printed as bitmaps
+    pub fn listing(&self)->String {
+        let mut out = String::new();
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let addr = self.origin+offset as u16;
+            if self.has_label(addr) {
+                let _ = writeln!(out,"label_{:03X}:",addr);
+            }
+            match self.bytes[offset] {
+                Byte::Code=>{
+                    let instruction = self.decode(addr).unwrap();
+                    let len = instruction.len() as usize;
+                    let raw:String = self.rom[offset..offset+len].iter().map(|b|format!("{:02X}",b)).collect();
+                    let _ = writeln!(out,"    {:<24}; {:04X}: {}",self.mnemonic(addr,instruction),addr,raw);
+                    offset+=len;
+                }
+                _=>{
+                    let byte = self.rom[offset];
+                    let bitmap:String = (0..8).map(|bit|if byte&(0x80>>bit)>0 {'#'} else {'.'}).collect();
+                    let _ = writeln!(out,"    {:<24}; {:04X}: {:02X} {}",format!("db 0b{:08b}",byte),addr,byte,bitmap);
+                    offset+=1;
+                }
+            }
+        }
+        out
+    }
+}
+
+///Disassembles a rom loaded at origin, anything outside set is data
+#[inline]
+pub fn disassemble(rom:&[u8],origin:u16,set:InstructionSet)->String {
+    Disassembler::new(rom,origin,set).listing()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(listing:&str,addr:u16)->String {
+        let at = format!("; {:04X}:",addr);
+        listing.lines().find(|l|l.contains(&at)).unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn code_stops_at_what_i_points_at() {
+        let rom = [
+            0x60,0x00, 0x61,0x00, 0xA2,0x10, 0xD0,0x15, 0xF0,0x0A, 0x00,0xE0, 0x60,0x05, 0x70,0x01,
+            0xA4,0x41,0xFF,0x00,0x18,
+        ];
+        let listing = disassemble(&rom,0x200,InstructionSet::XoChip);
+        assert!(line(&listing,0x20E).contains("ADD V0, #01"),"{}",listing);
+        assert!(line(&listing,0x210).contains("db 0b10100100"),"{}",listing);
+        assert!(listing.contains("LD I, label_210"),"{}",listing);
+    }
+
+    #[test]
+    fn long_loads_are_only_code_for_xochip() {
+        let rom = [0xF0,0x00,0x12,0x34,0x00,0xEE];
+        let xochip = disassemble(&rom,0x200,InstructionSet::XoChip);
+        assert!(line(&xochip,0x200).contains("LD I, LONG #1234"),"{}",xochip);
+        let chip8 = disassemble(&rom,0x200,InstructionSet::Chip8);
+        assert!(line(&chip8,0x200).contains("db"),"{}",chip8);
+    }
+
+    #[test]
+    fn jumping_into_an_instruction_keeps_it() {
+        //the jump to 205 would take the first byte of the RET at 206 as its operand
+        let rom = [0x22,0x06,0x12,0x05,0x00,0x00,0x00,0xEE];
+        let listing = disassemble(&rom,0x200,InstructionSet::XoChip);
+        assert!(line(&listing,0x206).contains("RET"),"{}",listing);
+    }
+}
@@ -1 +1,729 @@
+use std::collections::{BTreeMap,HashMap};
+use std::fmt::Write;
+use std::path::{Path,PathBuf};
+use crate::cpu::CHIP8_PROGRAM_START;
+use crate::errors::SourceError;
+use crate::instruction::Instruction;
 
+const C8_MAX_INCLUDE_DEPTH:usize = 16;
+const C8_MAX_EXPR_DEPTH:usize = 64;
+
+#[derive(Clone,PartialEq,Debug)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Comma,
+    Colon,
+    LBracket,
+    RBracket,
+    LParen,
+    RParen,
+    Here,
+    Op(&'static str),
+}
+
+impl Token {
+    ///Tokens after which an operator, rather than a value, is expected
+    #[inline]
+    fn ends_value(&self)->bool {
+        matches!(self,Token::Ident(_)|Token::Number(_)|Token::RParen|Token::RBracket|Token::Here)
+    }
+}
+
+///Whether the next token is a value rather than an operator, so `$` and `%` start numbers.
+///The mnemonic or directive after any labels is followed by a value, as is `equ`
+fn value_expected(tokens:&[Token])->bool {
+    match tokens.split_last() {
+        None=>true,
+        Some((Token::Ident(name),_)) if name.eq_ignore_ascii_case("equ")=>true,
+        Some((Token::Ident(_),labels))=>labels.chunks(2).all(|pair|matches!(pair,[Token::Ident(_),Token::Colon])),
+        Some((token,_))=>!token.ends_value()
+    }
+}
+
+fn lex(line:&str)->Result<Vec<Token>,String> {
+    let chars:Vec<char> = line.chars().collect();
+    let mut tokens:Vec<Token> = vec![];
+    let mut i = 0;
+    let digits = |i:&mut usize,radix:u32|->Result<i64,String>{
+        let start = *i;
+        while *i < chars.len() && (chars[*i].is_digit(radix) || chars[*i] == '_') {
+            *i+=1;
+        }
+        let text:String = chars[start..*i].iter().filter(|c|**c != '_').collect();
+        i64::from_str_radix(&text, radix).map_err(|_|format!("bad number '{}'",text))
+    };
+    while i < chars.len() {
+        let ch = chars[i];
+        let value_expected = value_expected(&tokens);
+        match ch {
+            ';'=>break,
+            ' '|'\t'|'\r'=>i+=1,
+            ','=>{tokens.push(Token::Comma);i+=1}
+            ':'=>{tokens.push(Token::Colon);i+=1}
+            '['=>{tokens.push(Token::LBracket);i+=1}
+            ']'=>{tokens.push(Token::RBracket);i+=1}
+            '('=>{tokens.push(Token::LParen);i+=1}
+            ')'=>{tokens.push(Token::RParen);i+=1}
+            '"'=>{
+                let start = i+1;
+                i = start;
+                while i < chars.len() && chars[i] != '"' {
+                    i+=1;
+                }
+                if i >= chars.len() {
+                    return Err("unterminated string".to_string());
+                }
+                tokens.push(Token::Str(chars[start..i].iter().collect()));
+                i+=1;
+            }
+            '#'=>{
+                i+=1;
+                tokens.push(Token::Number(digits(&mut i,16)?));
+            }
+            '$' if value_expected && chars.get(i+1).map(|c|c.is_ascii_hexdigit()).unwrap_or(false)=>{
+                i+=1;
+                tokens.push(Token::Number(digits(&mut i,16)?));
+            }
+            '$'=>{tokens.push(Token::Here);i+=1}
+            '%' if value_expected=>{
+                i+=1;
+                tokens.push(Token::Number(digits(&mut i,2)?));
+            }
+            '0'..='9'=>{
+                let radix = match chars.get(i+1) {
+                    Some('x')|Some('X') if ch == '0'=>16,
+                    Some('b')|Some('B') if ch == '0' && chars.get(i+2).map(|c|*c == '0' || *c == '1').unwrap_or(false)=>2,
+                    _=>10
+                };
+                if radix != 10 {
+                    i+=2;
+                }
+                tokens.push(Token::Number(digits(&mut i,radix)?));
+            }
+            '<'|'>' if chars.get(i+1) == Some(&ch)=>{
+                tokens.push(Token::Op(if ch == '<' {"<<"} else {">>"}));
+                i+=2;
+            }
+            '+'=>{tokens.push(Token::Op("+"));i+=1}
+            '-'=>{tokens.push(Token::Op("-"));i+=1}
+            '*'=>{tokens.push(Token::Op("*"));i+=1}
+            '/'=>{tokens.push(Token::Op("/"));i+=1}
+            '%'=>{tokens.push(Token::Op("%"));i+=1}
+            '&'=>{tokens.push(Token::Op("&"));i+=1}
+            '|'=>{tokens.push(Token::Op("|"));i+=1}
+            '^'=>{tokens.push(Token::Op("^"));i+=1}
+            '~'=>{tokens.push(Token::Op("~"));i+=1}
+            _ if ch.is_alphabetic() || ch == '_' || ch == '.'=>{
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                    i+=1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            }
+            _=>return Err(format!("unexpected character '{}'",ch))
+        }
+    }
+    Ok(tokens)
+}
+
+///Constant expression, evaluated once every label has an address
+#[derive(Clone,Debug)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    Here,
+    Unary(&'static str,Box<Expr>),
+    Binary(&'static str,Box<Expr>,Box<Expr>),
+}
+
+const C8_BINARY_PRECEDENCE:[&[&str];6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<",">>"],
+    &["+","-"],
+    &["*","/","%"],
+];
+
+struct ExprParser<'a> {
+    tokens:&'a [Token],
+    pos:usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn parse(tokens:&'a [Token])->Result<Expr,String> {
+        let mut parser = Self{tokens,pos:0};
+        let expr = parser.binary(0)?;
+        match parser.tokens.get(parser.pos) {
+            None=>Ok(expr),
+            Some(token)=>Err(format!("unexpected {:?} in expression",token))
+        }
+    }
+    fn binary(&mut self,level:usize)->Result<Expr,String> {
+        if level >= C8_BINARY_PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level+1)?;
+        while let Some(Token::Op(op)) = self.tokens.get(self.pos) {
+            if !C8_BINARY_PRECEDENCE[level].contains(op) {
+                break;
+            }
+            self.pos+=1;
+            let rhs = self.binary(level+1)?;
+            lhs = Expr::Binary(op,Box::new(lhs),Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+    fn unary(&mut self)->Result<Expr,String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("expression expected")?;
+        self.pos+=1;
+        match token {
+            Token::Op(op) if op == "-" || op == "~" || op == "+"=>Ok(Expr::Unary(op,Box::new(self.unary()?))),
+            Token::Number(n)=>Ok(Expr::Number(n)),
+            Token::Ident(name)=>Ok(Expr::Symbol(name)),
+            Token::Here=>Ok(Expr::Here),
+            Token::LParen=>{
+                let expr = self.binary(0)?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::RParen)=>{self.pos+=1;Ok(expr)}
+                    _=>Err("missing ')'".to_string())
+                }
+            }
+            _=>Err(format!("unexpected {:?} in expression",token))
+        }
+    }
+}
+
+#[derive(Clone,Debug)]
+enum Operand {
+    Register(u8),
+    I,
+    IndirectI,
+    Keyword(String),
+    Long(Expr),
+    Value(Expr),
+}
+
+fn register(name:&str)->Option<u8> {
+    let mut chars = name.chars();
+    match (chars.next(),chars.next(),chars.next()) {
+        (Some('v'),Some(n),None)|(Some('V'),Some(n),None)=>n.to_digit(16).map(|n|n as u8),
+        _=>None
+    }
+}
+
+fn operand(tokens:&[Token])->Result<Operand,String> {
+    match tokens {
+        [Token::Ident(name)]=>{
+            if let Some(reg) = register(name) {
+                return Ok(Operand::Register(reg));
+            }
+            let upper = name.to_uppercase();
+            match upper.as_str() {
+                "I"=>Ok(Operand::I),
+                "DT"|"ST"|"K"|"F"|"HF"|"B"|"R"|"PITCH"=>Ok(Operand::Keyword(upper)),
+                _=>Ok(Operand::Value(Expr::Symbol(name.clone())))
+            }
+        }
+        [Token::LBracket,Token::Ident(name),Token::RBracket] if name.eq_ignore_ascii_case("i")=>Ok(Operand::IndirectI),
+        [Token::Ident(name),rest @ ..] if name.eq_ignore_ascii_case("long")=>Ok(Operand::Long(ExprParser::parse(rest)?)),
+        []=>Err("missing operand".to_string()),
+        _=>Ok(Operand::Value(ExprParser::parse(tokens)?))
+    }
+}
+
+///Splits on top level commas
+fn split_operands(tokens:&[Token])->Vec<&[Token]> {
+    if tokens.is_empty() {
+        return vec![];
+    }
+    let mut operands = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (n,token) in tokens.iter().enumerate() {
+        match token {
+            Token::LParen|Token::LBracket=>depth+=1,
+            Token::RParen|Token::RBracket=>depth-=1,
+            Token::Comma if depth == 0=>{
+                operands.push(&tokens[start..n]);
+                start = n+1;
+            }
+            _=>{}
+        }
+    }
+    operands.push(&tokens[start..]);
+    operands
+}
+
+#[derive(Clone,Debug)]
+enum Body {
+    Instruction(String,Vec<Operand>),
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+}
+
+#[derive(Clone,Debug)]
+struct Item {
+    addr:u16,
+    len:u16,
+    body:Body,
+    file:String,
+    line:usize,
+    text:String,
+}
+
+#[derive(Clone,Debug)]
+enum Symbol {
+    Label(u16),
+    Constant(Expr),
+}
+
+///One assembled statement, used to map addresses back to source lines
+#[derive(Clone,PartialEq,Debug)]
+pub struct ListingLine {
+    pub addr:u16,
+    pub bytes:Vec<u8>,
+    pub file:String,
+    pub line:usize,
+    pub text:String,
+}
+
+impl ListingLine {
+    ///Reads back one line written by Assembly::listing_text
+    pub fn parse(text:&str)->Option<Self> {
+        let mut fields = text.splitn(4,'\t');
+        let addr = u16::from_str_radix(fields.next()?,16).ok()?;
+        let hex = fields.next()?;
+        let bytes = (0..hex.len()/2).map(|n|u8::from_str_radix(hex.get(n*2..n*2+2)?,16).ok()).collect::<Option<Vec<u8>>>()?;
+        let (file,line) = fields.next()?.rsplit_once(':')?;
+        Some(Self{
+            addr,
+            bytes,
+            file:file.to_string(),
+            line:line.parse().ok()?,
+            text:fields.next().unwrap_or("").to_string(),
+        })
+    }
+}
+
+///The assembled rom, starting at origin
+#[derive(Clone,PartialEq,Debug)]
+pub struct Assembly {
+    pub origin:u16,
+    pub bytes:Vec<u8>,
+    pub labels:BTreeMap<String,u16>,
+    pub listing:Vec<ListingLine>,
+}
+
+impl Assembly {
+    ///Tab separated listing: address, bytes, file:line and the source text
+    pub fn listing_text(&self)->String {
+        let mut out = String::new();
+        for line in &self.listing {
+            let bytes:String = line.bytes.iter().map(|b|format!("{:02X}",b)).collect();
+            let _ = writeln!(out,"{:04X}\t{}\t{}:{}\t{}",line.addr,bytes,line.file,line.line,line.text.trim());
+        }
+        out
+    }
+}
+
+///Two pass assembler for Cowgod's mnemonics, with labels, db/dw, org, equ and include
+pub struct Assembler {
+    origin:u16,
+}
+
+impl Default for Assembler {
+    #[inline]
+    fn default()->Self {
+        Self{origin:CHIP8_PROGRAM_START}
+    }
+}
+
+struct Pass {
+    ///Wider than an address so running off the end of memory can be reported
+    addr:u32,
+    items:Vec<Item>,
+    symbols:HashMap<String,Symbol>,
+    depth:usize,
+}
+
+impl Pass {
+    ///The current address, once it's still inside the 64K address space
+    fn here(&self)->Result<u16,String> {
+        match self.addr {
+            0..=0xFFFF=>Ok(self.addr as u16),
+            _=>Err("the program runs past #FFFF".to_string())
+        }
+    }
+    ///Claims len bytes at the current address, which must all fit in the address space
+    fn place(&mut self,len:u16)->Result<u16,String> {
+        let addr = self.here()?;
+        if self.addr+len as u32 > 0x10000 {
+            return Err("the program runs past #FFFF".to_string());
+        }
+        self.addr+=len as u32;
+        Ok(addr)
+    }
+}
+
+impl Assembler {
+    #[inline]
+    pub fn new()->Self {
+        Self::default()
+    }
+    #[inline]
+    pub fn with_origin(mut self,origin:u16)->Self {
+        self.origin = origin;
+        self
+    }
+    ///Assembles source, includes are looked up relative to the working directory
+    pub fn assemble(&self,name:&str,source:&str)->Result<Assembly,SourceError> {
+        let mut pass = Pass{addr:self.origin as u32,items:vec![],symbols:HashMap::new(),depth:0};
+        self.first_pass(&mut pass,Path::new("."),name,source)?;
+        self.second_pass(pass)
+    }
+    pub fn assemble_file(&self,path:&Path)->Result<Assembly,SourceError> {
+        let name = path.display().to_string();
+        let source = std::fs::read_to_string(path).map_err(|e|SourceError::new(&name,0,e.to_string()))?;
+        let mut pass = Pass{addr:self.origin as u32,items:vec![],symbols:HashMap::new(),depth:0};
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_else(||PathBuf::from("."));
+        self.first_pass(&mut pass,&dir,&name,&source)?;
+        self.second_pass(pass)
+    }
+    fn first_pass(&self,pass:&mut Pass,dir:&Path,file:&str,source:&str)->Result<(),SourceError> {
+        for (n,text) in source.lines().enumerate() {
+            let line = n+1;
+            let err = |message:String|SourceError::new(file,line,message);
+            let mut tokens:&[Token] = &lex(text).map_err(err)?;
+            //labels
+            while let [Token::Ident(name),Token::Colon,rest @ ..] = tokens {
+                if register(name).is_some() {
+                    return Err(err(format!("'{}' is a register and can't be a label",name)));
+                }
+                let addr = pass.here().map_err(err)?;
+                if pass.symbols.insert(name.clone(),Symbol::Label(addr)).is_some() {
+                    return Err(err(format!("'{}' is already defined",name)));
+                }
+                tokens = rest;
+            }
+            let (first,rest) = match tokens {
+                [Token::Ident(first),rest @ ..]=>(first.clone(),rest),
+                []=>continue,
+                _=>return Err(err("expected an instruction or directive".to_string()))
+            };
+            if let [Token::Ident(equ),value @ ..] = rest {
+                if equ.eq_ignore_ascii_case("equ") {
+                    let expr = ExprParser::parse(value).map_err(err)?;
+                    if pass.symbols.insert(first.clone(),Symbol::Constant(expr)).is_some() {
+                        return Err(err(format!("'{}' is already defined",first)));
+                    }
+                    continue;
+                }
+            }
+            let keyword = first.to_lowercase();
+            let keyword = keyword.trim_start_matches('.');
+            let item = |addr:u16,len:u16,body:Body|Item{addr,len,body,file:file.to_string(),line,text:text.to_string()};
+            match keyword {
+                "include"=>{
+                    let name = match rest {
+                        [Token::Str(name)]=>name,
+                        _=>return Err(err("include expects a quoted file name".to_string()))
+                    };
+                    if pass.depth >= C8_MAX_INCLUDE_DEPTH {
+                        return Err(err("includes nested too deeply".to_string()));
+                    }
+                    let path = dir.join(name);
+                    let source = std::fs::read_to_string(&path).map_err(|e|err(format!("can't include '{}': {}",name,e)))?;
+                    let inner = path.parent().map(Path::to_path_buf).unwrap_or_else(||dir.to_path_buf());
+                    pass.depth+=1;
+                    self.first_pass(pass,&inner,&path.display().to_string(),&source)?;
+                    pass.depth-=1;
+                }
+                "org"=>{
+                    let expr = ExprParser::parse(rest).map_err(err)?;
+                    let addr = eval(&expr,&pass.symbols,pass.here().map_err(err)?,0).map_err(err)?;
+                    if addr < self.origin as i64 || addr > 0xFFFF {
+                        return Err(err(format!("org #{:X} is outside #{:X}..#FFFF",addr,self.origin)));
+                    }
+                    pass.addr = addr as u32;
+                }
+                "db"|"byte"=>{
+                    let mut exprs = vec![];
+                    for operand in split_operands(rest) {
+                        match operand {
+                            [Token::Str(text)]=>exprs.extend(text.bytes().map(|b|Expr::Number(b as i64))),
+                            _=>exprs.push(ExprParser::parse(operand).map_err(err)?)
+                        }
+                    }
+                    let len = exprs.len() as u16;
+                    let addr = pass.place(len).map_err(err)?;
+                    pass.items.push(item(addr,len,Body::Bytes(exprs)));
+                }
+                "dw"|"word"=>{
+                    let mut exprs = vec![];
+                    for operand in split_operands(rest) {
+                        exprs.push(ExprParser::parse(operand).map_err(err)?);
+                    }
+                    let len = exprs.len() as u16*2;
+                    let addr = pass.place(len).map_err(err)?;
+                    pass.items.push(item(addr,len,Body::Words(exprs)));
+                }
+                _=>{
+                    let mut operands = vec![];
+                    for operand_tokens in split_operands(rest) {
+                        operands.push(operand(operand_tokens).map_err(err)?);
+                    }
+                    let len = if operands.iter().any(|o|matches!(o,Operand::Long(_))) {4} else {2};
+                    let addr = pass.place(len).map_err(err)?;
+                    pass.items.push(item(addr,len,Body::Instruction(first.to_uppercase(),operands)));
+                }
+            }
+        }
+        Ok(())
+    }
+    ///An org that moves backwards can't have later items write over earlier ones
+    fn check_overlaps(items:&[Item])->Result<(),SourceError> {
+        let mut placed:Vec<(usize,&Item)> = items.iter().enumerate().filter(|(_,item)|item.len > 0).collect();
+        placed.sort_by_key(|(_,item)|item.addr);
+        for pair in placed.windows(2) {
+            let ((n,a),(m,b)) = (pair[0],pair[1]);
+            if (b.addr as u32) < a.addr as u32+a.len as u32 {
+                let (earlier,later) = if n < m {(a,b)} else {(b,a)};
+                return Err(SourceError::new(&later.file,later.line,format!(
+                    "#{:X}..#{:X} overlaps #{:X}..#{:X} from {}:{}",
+                    later.addr,later.addr as u32+later.len as u32,earlier.addr,earlier.addr as u32+earlier.len as u32,earlier.file,earlier.line)));
+            }
+        }
+        Ok(())
+    }
+    fn second_pass(&self,pass:Pass)->Result<Assembly,SourceError> {
+        Self::check_overlaps(&pass.items)?;
+        let end = pass.items.iter().map(|item|item.addr as usize+item.len as usize).max().unwrap_or(self.origin as usize);
+        let mut bytes = vec![0u8;end.saturating_sub(self.origin as usize)];
+        let mut listing = vec![];
+        for item in &pass.items {
+            let err = |message:String|SourceError::new(&item.file,item.line,message);
+            let eval = |expr:&Expr|eval(expr,&pass.symbols,item.addr,0);
+            let mut out:Vec<u8> = vec![];
+            match &item.body {
+                Body::Bytes(exprs)=>for expr in exprs {
+                    out.push(fit(eval(expr).map_err(err)?,8).map_err(err)? as u8);
+                }
+                Body::Words(exprs)=>for expr in exprs {
+                    out.extend(&(fit(eval(expr).map_err(err)?,16).map_err(err)? as u16).to_be_bytes());
+                }
+                Body::Instruction(mnemonic,operands)=>{
+                    for word in encode(mnemonic,operands,&eval).map_err(err)? {
+                        out.extend(&word.to_be_bytes());
+                    }
+                }
+            }
+            let offset = (item.addr-self.origin) as usize;
+            bytes[offset..offset+out.len()].copy_from_slice(&out);
+            listing.push(ListingLine{addr:item.addr,bytes:out,file:item.file.clone(),line:item.line,text:item.text.clone()});
+        }
+        let mut labels = BTreeMap::new();
+        for (name,symbol) in &pass.symbols {
+            if let Symbol::Label(addr) = symbol {
+                labels.insert(name.clone(),*addr);
+            }
+        }
+        Ok(Assembly{origin:self.origin,bytes,labels,listing})
+    }
+}
+
+fn eval(expr:&Expr,symbols:&HashMap<String,Symbol>,here:u16,depth:usize)->Result<i64,String> {
+    if depth > C8_MAX_EXPR_DEPTH {
+        return Err("constant refers to itself".to_string());
+    }
+    let eval_inner = |expr:&Expr|eval(expr,symbols,here,depth+1);
+    Ok(match expr {
+        Expr::Number(n)=>*n,
+        Expr::Here=>here as i64,
+        Expr::Symbol(name)=>match symbols.get(name) {
+            Some(Symbol::Label(addr))=>*addr as i64,
+            Some(Symbol::Constant(expr))=>eval_inner(expr)?,
+            _=>return Err(format!("undefined symbol '{}'",name))
+        }
+        Expr::Unary(op,expr)=>{
+            let value = eval_inner(expr)?;
+            match *op {
+                "-"=>value.wrapping_neg(),
+                "~"=>!value,
+                _=>value
+            }
+        }
+        Expr::Binary(op,lhs,rhs)=>{
+            let (lhs,rhs) = (eval_inner(lhs)?,eval_inner(rhs)?);
+            match *op {
+                "+"=>lhs.wrapping_add(rhs),
+                "-"=>lhs.wrapping_sub(rhs),
+                "*"=>lhs.wrapping_mul(rhs),
+                "/"|"%" if rhs == 0=>return Err("division by zero".to_string()),
+                "/"=>lhs/rhs,
+                "%"=>lhs%rhs,
+                "&"=>lhs&rhs,
+                "|"=>lhs|rhs,
+                "^"=>lhs^rhs,
+                "<<"=>lhs.wrapping_shl(rhs as u32),
+                _=>lhs.wrapping_shr(rhs as u32),
+            }
+        }
+    })
+}
+
+///Checks a value fits in bits, negative values down to -2^(bits-1) are stored as two's complement
+fn fit(value:i64,bits:u32)->Result<i64,String> {
+    let max = (1i64<<bits)-1;
+    if value > max || value < -(1i64<<(bits-1)) {
+        return Err(format!("{} doesn't fit in {} bits",value,bits));
+    }
+    Ok(value&max)
+}
+
+fn encode(mnemonic:&str,operands:&[Operand],eval:&dyn Fn(&Expr)->Result<i64,String>)->Result<Vec<u16>,String> {
+    use Operand::*;
+    let value = |expr:&Expr,bits:u32|->Result<u16,String>{Ok(fit(eval(expr)?,bits)? as u16)};
+    let keyword = |k:&str,name:&str|k == name;
+    let instruction = match (mnemonic,operands) {
+        ("CLS",[])=>Instruction::Cls,
+        ("RET",[])=>Instruction::Ret,
+        ("SCR",[])=>Instruction::ScrollRight,
+        ("SCL",[])=>Instruction::ScrollLeft,
+        ("EXIT",[])=>Instruction::Exit,
+        ("LOW",[])=>Instruction::Low,
+        ("HIGH",[])=>Instruction::High,
+        ("AUDIO",[])=>Instruction::Audio,
+        ("SCD",[Value(n)])=>Instruction::ScrollDown(value(n,4)? as u8),
+        ("SCU",[Value(n)])=>Instruction::ScrollUp(value(n,4)? as u8),
+        ("PLANE",[Value(n)])=>Instruction::Plane(value(n,4)? as u8),
+        ("SYS",[Value(addr)])=>Instruction::Sys(value(addr,12)?),
+        ("JP",[Value(addr)])=>Instruction::Jp(value(addr,12)?),
+        ("JP",[Register(0),Value(addr)])=>Instruction::JpV0(value(addr,12)?),
+        ("CALL",[Value(addr)])=>Instruction::Call(value(addr,12)?),
+        ("SE",[Register(x),Register(y)])=>Instruction::SeReg{x:*x,y:*y},
+        ("SE",[Register(x),Value(kk)])=>Instruction::SeByte{x:*x,kk:value(kk,8)? as u8},
+        ("SNE",[Register(x),Register(y)])=>Instruction::SneReg{x:*x,y:*y},
+        ("SNE",[Register(x),Value(kk)])=>Instruction::SneByte{x:*x,kk:value(kk,8)? as u8},
+        ("SAVE",[Register(x),Register(y)])=>Instruction::SaveRange{x:*x,y:*y},
+        ("LOAD",[Register(x),Register(y)])=>Instruction::LoadRange{x:*x,y:*y},
+        ("LD",[Register(x),Register(y)])=>Instruction::LdReg{x:*x,y:*y},
+        ("LD",[Register(x),Value(kk)])=>Instruction::LdByte{x:*x,kk:value(kk,8)? as u8},
+        ("LD",[Register(x),Keyword(k)]) if keyword(k,"DT")=>Instruction::LdVxDt(*x),
+        ("LD",[Register(x),Keyword(k)]) if keyword(k,"K")=>Instruction::LdKey(*x),
+        ("LD",[Register(x),Keyword(k)]) if keyword(k,"R")=>Instruction::LoadFlags(*x),
+        ("LD",[Register(x),IndirectI])=>Instruction::LoadRegs(*x),
+        ("LD",[IndirectI,Register(x)])=>Instruction::StoreRegs(*x),
+        ("LD",[I,Value(addr)])=>Instruction::LdI(value(addr,12)?),
+        ("LD",[I,Long(addr)])=>return Ok(vec![Instruction::LdILong(0).encode(),value(addr,16)?]),
+        ("LD",[Keyword(k),Register(x)])=>match k.as_str() {
+            "DT"=>Instruction::LdDtVx(*x),
+            "ST"=>Instruction::LdStVx(*x),
+            "F"=>Instruction::LdFont(*x),
+            "HF"=>Instruction::LdBigFont(*x),
+            "B"=>Instruction::Bcd(*x),
+            "R"=>Instruction::StoreFlags(*x),
+            "PITCH"=>Instruction::Pitch(*x),
+            _=>return Err(format!("can't load {} from a register",k))
+        }
+        ("ADD",[Register(x),Register(y)])=>Instruction::AddReg{x:*x,y:*y},
+        ("ADD",[Register(x),Value(kk)])=>Instruction::AddByte{x:*x,kk:value(kk,8)? as u8},
+        ("ADD",[I,Register(x)])=>Instruction::AddI(*x),
+        ("OR",[Register(x),Register(y)])=>Instruction::Or{x:*x,y:*y},
+        ("AND",[Register(x),Register(y)])=>Instruction::And{x:*x,y:*y},
+        ("XOR",[Register(x),Register(y)])=>Instruction::Xor{x:*x,y:*y},
+        ("SUB",[Register(x),Register(y)])=>Instruction::Sub{x:*x,y:*y},
+        ("SUBN",[Register(x),Register(y)])=>Instruction::Subn{x:*x,y:*y},
+        ("SHR",[Register(x)])=>Instruction::Shr{x:*x,y:*x},
+        ("SHR",[Register(x),Register(y)])=>Instruction::Shr{x:*x,y:*y},
+        ("SHL",[Register(x)])=>Instruction::Shl{x:*x,y:*x},
+        ("SHL",[Register(x),Register(y)])=>Instruction::Shl{x:*x,y:*y},
+        ("RND",[Register(x),Value(kk)])=>Instruction::Rnd{x:*x,kk:value(kk,8)? as u8},
+        ("DRW",[Register(x),Register(y),Value(n)])=>Instruction::Drw{x:*x,y:*y,n:value(n,4)? as u8},
+        ("SKP",[Register(x)])=>Instruction::Skp(*x),
+        ("SKNP",[Register(x)])=>Instruction::Sknp(*x),
+        _ if C8_MNEMONICS.contains(&mnemonic)=>return Err(format!("invalid operands for {}",mnemonic)),
+        _=>return Err(format!("unknown instruction '{}'",mnemonic))
+    };
+    Ok(vec![instruction.encode()])
+}
+
+const C8_MNEMONICS:[&str;31] = [
+    "CLS","RET","SCR","SCL","EXIT","LOW","HIGH","AUDIO","SCD","SCU","PLANE","SYS","JP","CALL",
+    "SE","SNE","SAVE","LOAD","LD","ADD","OR","AND","XOR","SUB","SUBN","SHR","SHL","RND","DRW","SKP","SKNP",
+];
+
+///Assembles source into a rom that loads at 0x200
+#[inline]
+pub fn assemble(source:&str)->Result<Vec<u8>,SourceError> {
+    Ok(Assembler::new().assemble("<source>",source)?.bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source:&str)->String {
+        Assembler::new().assemble("test.s",source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn instructions_and_labels() {
+        let assembly = Assembler::new().assemble("test.s","
+            start: LD V0, #0A
+                   LD I, sprite
+                   DRW V0, V1, 5
+                   SE V0, 1
+                   JP start
+            sprite: db #F0, #90
+        ").unwrap();
+        assert_eq!(assembly.bytes,[0x60,0x0A,0xA2,0x0A,0xD0,0x15,0x30,0x01,0x12,0x00,0xF0,0x90]);
+        assert_eq!(assembly.labels["sprite"],0x20A);
+        assert_eq!(assembly.listing.len(),6);
+    }
+
+    #[test]
+    fn numbers_after_mnemonics_and_directives() {
+        let bytes = assemble("
+            JP $200
+            db %10101010, $FF
+            mask equ %1111
+            label: dw $1234
+            db mask, 2+3*4, (2+3)*4, 1<<4|1, $-$200
+        ").unwrap();
+        assert_eq!(bytes,[0x12,0x00,0xAA,0xFF,0x12,0x34,0x0F,14,20,0x11,6]);
+    }
+
+    #[test]
+    fn org_pads_with_zeroes() {
+        let bytes = assemble("db 1\norg #204\ndb 2").unwrap();
+        assert_eq!(bytes,[1,0,0,0,2]);
+    }
+
+    #[test]
+    fn errors_have_lines() {
+        assert_eq!(error("CLS\nJP nowhere"),"test.s:2: undefined symbol 'nowhere'");
+        assert!(error("LD V0, #100").starts_with("test.s:1:"));
+        assert!(error("a: CLS\na: CLS").contains("already defined"));
+    }
+
+    #[test]
+    fn running_past_the_address_space_is_an_error() {
+        assert_eq!(error("org #FFFE\ndw 1, 2"),"test.s:2: the program runs past #FFFF");
+        assert_eq!(error("org #FFFE\nCLS\nCLS"),"test.s:3: the program runs past #FFFF");
+        assert_eq!(error("org #FFFE\nCLS\nend: db 1"),"test.s:3: the program runs past #FFFF");
+        let assembly = Assembler::new().assemble("test.s","org #FFFE\nCLS").unwrap();
+        assert_eq!(assembly.bytes.len(),0x10000-0x200);
+    }
+
+    #[test]
+    fn org_backwards_over_code_is_an_error() {
+        assert_eq!(error("CLS\nCLS\norg #202\ndb 1"),"test.s:4: #202..#203 overlaps #202..#204 from test.s:2");
+        assert_eq!(error("org #204\ndw 1, 2\norg #200\nJP #200\nLD I, long #300"),
+                   "test.s:5: #202..#206 overlaps #204..#208 from test.s:2");
+        let bytes = assemble("org #204\ndb 2\norg #200\ndb 1, 1, 1, 1\ndb").unwrap();
+        assert_eq!(bytes,[1,1,1,1,2]);
+    }
+}
@@ -2,50 +2,117 @@
 use std::fmt;
 use std::error::Error as SError;
 
-#[repr(u8)]
-#[derive(Debug,PartialEq,PartialOrd,Clone,Copy)]
-pub enum Error {
-    None=0,
-    StackUnderflow=1,
-    ExecutionLocked=2,
-    DelayTimerLocked=3,
-    SoundTimerLocked=4,
-    UnhookedKeyBoard=5,
-    InvalidInstruction=6
+///What went wrong, see Error for where
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum ErrorKind {
+    StackUnderflow,
+    InvalidInstruction,
+    ///A call with limit calls already on the stack
+    StackOverflow{limit:usize},
+    InvalidSaveState,
+    ///A size byte rom with only space bytes between the load address and the end of memory
+    RomTooLarge{size:usize,space:usize},
+    ///len bytes from addr run past the end of memory
+    MemoryOutOfRange{addr:u16,len:u16},
+    ///The character at offset isn't a hex digit, or is a digit missing its pair
+    InvalidHex{offset:usize},
+    ///A hex string holding found bytes where expected are needed
+    HexTooShort{expected:usize,found:usize},
+    ///A rom loaded from start up to end runs over the fonts at font_base
+    RomOverlapsFonts{font_base:u16,start:u16,end:usize},
 }
-impl Error {
-    #[inline]
-    pub fn locked(&self)->bool{
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self{
-            Error::DelayTimerLocked|Error::ExecutionLocked|Error::SoundTimerLocked=>true,
-            _=>false
+            ErrorKind::StackUnderflow=>write!(f,"Stack underflow! Returned with no call to return from!"),
+            ErrorKind::InvalidInstruction=>write!(f,"Invalid instruction!"),
+            ErrorKind::StackOverflow{limit}=>write!(f,"Stack overflow! More than {} nested calls!",limit),
+            ErrorKind::InvalidSaveState=>write!(f,"Save state is corrupt or from an incompatible version!"),
+            ErrorKind::RomTooLarge{size,space}=>write!(f,"Rom is too large! {} bytes but only {} fit in memory!",size,space),
+            ErrorKind::MemoryOutOfRange{addr,len}=>write!(f,"Memory out of range! {} bytes at {:04X} run past the end of memory!",len,addr),
+            ErrorKind::InvalidHex{offset}=>write!(f,"Invalid hex! Character {} isn't part of a pair of hex digits!",offset),
+            ErrorKind::HexTooShort{expected,found}=>write!(f,"Hex too short! {} bytes but {} are needed!",found,expected),
+            ErrorKind::RomOverlapsFonts{font_base,start,end}=>write!(f,"Rom overlaps the fonts! It runs from {:04X} to {:04X} but the fonts start at {:04X}!",start,end,font_base),
         }
     }
 }
 
+///An ErrorKind along with the state of the machine when it happened
+#[derive(Debug,PartialEq,Clone)]
+pub struct Error {
+    pub kind:ErrorKind,
+    ///Address of the instruction that failed
+    pub pc:Option<u16>,
+    pub opcode:Option<u16>,
+    ///Return addresses, innermost call last
+    pub callstack:Vec<u16>,
+}
+
+impl Error {
+    #[inline]
+    pub fn new(kind:ErrorKind)->Self {
+        Self{kind,pc:None,opcode:None,callstack:vec![]}
+    }
+    ///Records the instruction that failed and the calls that led to it
+    #[inline]
+    pub fn at(mut self,pc:u16,opcode:u16,callstack:&[u16])->Self {
+        self.pc = Some(pc);
+        self.opcode = Some(opcode);
+        self.callstack = callstack.to_vec();
+        self
+    }
+}
 
+impl From<ErrorKind> for Error {
+    #[inline]
+    fn from(kind:ErrorKind)->Self {
+        Self::new(kind)
+    }
+}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &self{
-            Error::StackUnderflow=>write!(f,"Stack underflow! Attempted to pop non-existant element from stack! Last Call trace: 0xDEADBEEF"),
-            Error::ExecutionLocked=>write!(f,"Exectution handle locked!"),
-            Error::DelayTimerLocked=>write!(f,"Delay handle locked!"),
-            Error::SoundTimerLocked=>write!(f,"Sound handle locked!"),
-            Error::UnhookedKeyBoard=>write!(f,"Keyboard disconnected!"),
-            Error::InvalidInstruction=>write!(f,"Invalid instruction: 0xDEADBEEF"),
-            _=>write!(f,"No error")
+        write!(f,"{}",self.kind)?;
+        if let Some(pc) = self.pc {
+            write!(f," At {:04X}",pc)?;
         }
-    }
-}
-impl SError for Error {
-    fn description(&self) -> &str {
-        match self {
-            Error::None=>"No error",
-            _=>"chip 8 error"
+        if let Some(opcode) = self.opcode {
+            write!(f," ({:04X})",opcode)?;
         }
+        if !self.callstack.is_empty() {
+            let calls:Vec<String> = self.callstack.iter().map(|a|format!("{:04X}",a)).collect();
+            write!(f,", call stack: {}",calls.join(" "))?;
+        }
+        Ok(())
     }
 }
+impl SError for ErrorKind {}
+impl SError for Error {}
 pub type Result<I> = std::result::Result<I,Error>;
 
 
+
+///A problem found while translating source code, such as assembly, into a rom
+#[derive(Debug,PartialEq,Clone)]
+pub struct SourceError {
+    pub file:String,
+    pub line:usize,
+    pub message:String
+}
+impl SourceError {
+    #[inline]
+    pub fn new(file:&str,line:usize,message:impl Into<String>)->Self {
+        Self{
+            file:file.to_string(),
+            line,
+            message:message.into()
+        }
+    }
+}
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"{}:{}: {}",self.file,self.line,self.message)
+    }
+}
+impl SError for SourceError {}
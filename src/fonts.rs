@@ -1,5 +1,7 @@
 use crate::cpu::*;
-pub const C8_CLASSIC_FONT:&'static str = 
+use crate::errors::Result;
+///The HP48 font of CHIP-48 and SCHIP
+pub const C8_CLASSIC_FONT:&str = 
 concat!(
     "F0909090F0",
     "2060202070",
@@ -18,15 +20,114 @@ concat!(
     "F080F080F0",
     "F080F08080");
 
+///The COSMAC VIP interpreter's font
+pub const C8_VIP_FONT:&str =
+concat!(
+    "F0909090F0",
+    "6020202070",
+    "F010F080F0",
+    "F010F010F0",
+    "A0A0F02020",
+    "F080F010F0",
+    "F080F090F0",
+    "F010101010",
+    "F090F090F0",
+    "F090F010F0",
+    "F090F09090",
+    "F0507050F0",
+    "F0808080F0",
+    "F0505050F0",
+    "F080F080F0",
+    "F080F08080");
+
+///The DREAM 6800's CHIPOS font, 3 pixels wide
+pub const C8_DREAM6800_FONT:&str =
+concat!(
+    "E0A0A0A0E0",
+    "4040404040",
+    "E020E080E0",
+    "E020E020E0",
+    "80A0A0E020",
+    "E080E020E0",
+    "E080E0A0E0",
+    "E020202020",
+    "E0A0E0A0E0",
+    "E0A0E020E0",
+    "E0A0E0A0A0",
+    "C0A0E0A0C0",
+    "E0808080E0",
+    "C0A0A0A0C0",
+    "E080E080E0",
+    "E080C08080");
+
+///The ETI-660's font, 3 pixels wide
+pub const C8_ETI660_FONT:&str =
+concat!(
+    "E0A0A0A0E0",
+    "2020202020",
+    "E020E080E0",
+    "E020E020E0",
+    "A0A0E02020",
+    "E080E020E0",
+    "E080E0A0E0",
+    "E020202020",
+    "E0A0E0A0E0",
+    "E0A0E020E0",
+    "E0A0E0A0A0",
+    "8080E0A0E0",
+    "E0808080E0",
+    "2020E0A0E0",
+    "E080E080E0",
+    "E080C08080");
 
+///Cli name of every small font
+pub const C8_FONTS:[(&str,&str);4] = [
+    ("classic",C8_CLASSIC_FONT),
+    ("vip",C8_VIP_FONT),
+    ("dream6800",C8_DREAM6800_FONT),
+    ("eti660",C8_ETI660_FONT),
+];
+
+///SCHIP 8x10 digits, extended with the hex letters used by XO-CHIP
+pub const C8_BIG_FONT:&str =
+concat!(
+    "3C7EE7C3C3C3C3E77E3C",
+    "1838581818181818183C",
+    "3E7FC3060C183060FFFF",
+    "3C7EC3030E0E03C37E3C",
+    "060E1E3666C6FFFF0606",
+    "FFFFC0C0FCFE03C37E3C",
+    "3E7CE0C0FCFEC3C37E3C",
+    "FFFF03060C1830606060",
+    "3C7EC3C37E7EC3C37E3C",
+    "3C7EC3C37F3F03033E7C",
+    "7EFFC3C3C3FFFFC3C3C3",
+    "FCFCC3C3FCFCC3C3FCFC",
+    "3CFFC3C0C0C0C0C3FF3C",
+    "FCFEC3C3C3C3C3C3FEFC",
+    "FFFFC0C0FFFFC0C0FFFF",
+    "FFFFC0C0FFFFC0C0C0C0");
+///Where the big font lives from the font base, right after the 16 small characters
+pub const C8_BIG_FONT_START:u16 = 16*5;
+///Bytes taken by both fonts
+pub const C8_FONT_PACK_SIZE:u16 = C8_BIG_FONT_START+16*10;
+
+///The small font for a name in C8_FONTS
+pub fn font(name:&str)->Option<&'static str> {
+    C8_FONTS.iter().find(|(n,_)|*n == name).map(|(_,font)|*font)
+}
+
+///Both fonts, copied into memory from base when a machine uses them
 pub struct FontPack{
-    pub (crate)fonts:MemoryStick
+    pub (crate)fonts:MemoryStick,
+    base:u16,
 }
 impl Default for FontPack {
     #[inline]
     fn default()->Self {
         Self{
-            fonts:MemoryStick::alloced(0xFF)
+            fonts:MemoryStick::alloced(0x100),
+            base:0x0,
         }
     }
 }
@@ -37,22 +138,46 @@ impl FontPack {
         Self::default()
     }
     #[inline]
-    pub fn with_font(font:&str)->Self{
+    pub fn with_font(font:&str)->Result<Self>{
         let mut font_pack = Self::default();
-        font_pack.load_font(font);
-        font_pack
+        font_pack.load_font(font)?;
+        Ok(font_pack)
+    }
+    ///Puts the fonts at base instead of 0x0
+    #[inline]
+    pub fn with_base(mut self,base:u16)->Self{
+        self.base = base;
+        self
     }
     #[inline]
-    pub fn load_font(&mut self,hex:&str){
-        load_font(&mut self.fonts, hex)
+    pub fn base(&self)->u16{
+        self.base
     }
     #[inline]
-    pub fn load_charfont(&mut self,ch:u8,hex:&str){
-        load_charfont(&mut self.fonts, ch, hex)
+    pub fn load_font(&mut self,hex:&str)->Result<()>{
+        load_font(&mut self.fonts,0x0,hex)
+    }
+    #[inline]
+    pub fn load_charfont(&mut self,ch:u8,hex:&str)->Result<()>{
+        load_charfont(&mut self.fonts,0x0,ch,hex)
+    }
+    #[inline]
+    pub fn load_big_font(&mut self,hex:&str)->Result<()>{
+        load_big_font(&mut self.fonts,0x0,hex)
     }
     #[inline]
     pub fn classic()->Self{
-        Self::with_font(C8_CLASSIC_FONT)
+        Self::named("classic").unwrap()
+    }
+    ///A font from C8_FONTS along with the big font
+    pub fn named(name:&str)->Option<Self>{
+        let mut font_pack = Self::with_font(font(name)?).ok()?;
+        font_pack.load_big_font(C8_BIG_FONT).ok()?;
+        Some(font_pack)
+    }
+    ///Both fonts as they go into memory from base
+    #[inline]
+    pub fn bytes(&self)->&[u8]{
+        &self.fonts.arena()[..C8_FONT_PACK_SIZE as usize]
     }
 }
-
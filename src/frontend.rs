@@ -0,0 +1,52 @@
+use crate::graphics::PixMap;
+use crate::keyboard::KeyEvent;
+pub use crate::audio::AudioSink;
+
+///What the player asked for, frontends turn their own events into these
+#[derive(Copy,Clone,Debug)]
+pub enum Input {
+    ///A chip8 key went down or up
+    Key(KeyEvent),
+    Quit,
+    ///Rewinding starts or stops
+    Rewind(bool),
+    SaveState(u8),
+    LoadState(u8),
+    ///Writes a crash bundle once the game has crashed
+    Confirm,
+}
+
+///Where the frontend gets the player's input from
+pub trait InputSource {
+    ///Everything that arrived since the last poll, never blocks
+    fn poll(&mut self)->Vec<Input>;
+}
+
+///What a status line shows about the running machine
+#[derive(Copy,Clone,PartialEq,Debug,Default)]
+pub struct Status {
+    pub pc:u16,
+    ///Instructions per second, measured over the last second
+    pub ips:u32,
+    pub beeping:bool,
+    pub paused:bool,
+}
+
+///Where the frontend shows the screen
+pub trait Display {
+    ///Shows the screen if it changed, with the panel text drawn over it
+    fn present(&mut self,pix:&mut PixMap,panel:Option<&str>)->Result<(),String>;
+    ///Called before each present, displays without a status line ignore it
+    fn status(&mut self,_status:&Status) {}
+}
+
+///One colour per combination of the two XO-CHIP bitplanes
+#[inline]
+pub fn color(byte:u8)->(u8,u8,u8) {
+    match byte&3 {
+        0=>(0,0,0),
+        1=>(255,255,255),
+        2=>(170,170,170),
+        _=>(85,85,85)
+    }
+}
@@ -1,50 +1,95 @@
+use crate::savestate::*;
 
+pub const C8_LORES_WIDTH:usize = 64;
+pub const C8_LORES_HEIGHT:usize = 32;
+pub const C8_HIRES_WIDTH:usize = 128;
+pub const C8_HIRES_HEIGHT:usize = 64;
 
-
-
-
-
+///Each pixel holds one bit per XO-CHIP bitplane, plain CHIP-8 only ever uses plane 1
 pub struct PixMap {
-    pub map:Box<[[u8;64];32]>,
-    pub has_updates:bool
+    pub map:Box<[[u8;C8_HIRES_WIDTH];C8_HIRES_HEIGHT]>,
+    pub has_updates:bool,
+    hires:bool,
+    planes:u8
 }
 
 impl PixMap {
     pub fn pack() ->PixMap {
         Self {
-            map:Box::new([[0;64];32]),
-            has_updates:true
+            map:Box::new([[0;C8_HIRES_WIDTH];C8_HIRES_HEIGHT]),
+            has_updates:true,
+            hires:false,
+            planes:1
         }
     }
     #[inline]
-    fn index(x:u8,y:u8)->(usize,usize) {
-        ((x&63)as usize,(y&31)as usize)   
+    pub fn width(&self)->usize {
+        if self.hires {C8_HIRES_WIDTH} else {C8_LORES_WIDTH}
+    }
+    #[inline]
+    pub fn height(&self)->usize {
+        if self.hires {C8_HIRES_HEIGHT} else {C8_LORES_HEIGHT}
+    }
+    #[inline]
+    pub fn is_hires(&self)->bool {
+        self.hires
+    }
+    ///Switches between 64x32 and 128x64, clearing the screen like SCHIP does
+    pub fn set_hires(&mut self,hires:bool){
+        self.hires = hires;
+        self.clear_all()
+    }
+    #[inline]
+    pub fn planes(&self)->u8 {
+        self.planes
+    }
+    ///XO-CHIP FN01, picks which bitplanes clear, scroll and draw act on
+    #[inline]
+    pub fn select_planes(&mut self,planes:u8){
+        self.planes = planes&3
     }
     #[inline]
-    fn set_map(&mut self,x:u8,y:u8) ->bool{
+    fn index(&self,x:u8,y:u8)->(usize,usize) {
+        ((x as usize)&(self.width()-1),(y as usize)&(self.height()-1))
+    }
+    #[inline]
+    fn set_map(&mut self,x:u8,y:u8,plane:u8) ->bool{
         self.has_updates = true;
-        let (x,y) = Self::index(x, y);
-        let b = self.map[y][x]>0; 
-        self.map[y][x]^=1;
+        let (x,y) = self.index(x, y);
+        let b = self.map[y][x]&plane>0;
+        self.map[y][x]^=plane;
         b
     }
     #[inline]
     pub fn get(&self,x:u8,y:u8) ->u8{
-        let (x,y) = Self::index(x, y);
-        self.map[y as usize][x as usize]
+        let (x,y) = self.index(x, y);
+        self.map[y][x]
     }
     #[inline]
     fn clear_map(&mut self,x:u8,y:u8)->bool {
         self.has_updates = true;
-        let (x,y) = Self::index(x, y);
+        let (x,y) = self.index(x, y);
         let b = self.map[y][x] > 0;
         self.map[y][x] = 0;
         b
     }
     pub fn draw(&mut self,x:u8,y:u8)->bool {
-        self.set_map(x,y)
+        self.set_map(x,y,1)
+    }
+    pub fn draw_plane(&mut self,x:u8,y:u8,plane:u8)->bool {
+        self.set_map(x,y,plane)
     }
+    ///Clears the selected planes
     pub fn clear(&mut self){
+        self.has_updates = true;
+        let keep = !self.planes;
+        for i in &mut self.map.iter_mut() {
+            for j in &mut i.iter_mut(){
+                *j&=keep;
+            }
+        }
+    }
+    pub fn clear_all(&mut self){
         self.has_updates = true;
         for i in &mut self.map.iter_mut() {
             for j in &mut i.iter_mut(){
@@ -53,17 +98,68 @@ impl PixMap {
         }
     }
     pub fn erase(&mut self,x:u8,y:u8) ->bool {
-        let b = self.clear_map(x,y);
-        b
+        self.clear_map(x,y)
     }
     pub fn decide(&mut self,x:u8,y:u8,bit:u8)->bool {
+        self.decide_plane(x, y, bit, 1)
+    }
+    pub fn decide_plane(&mut self,x:u8,y:u8,bit:u8,plane:u8)->bool {
         if bit>0{
-            self.draw(x, y)
+            self.draw_plane(x, y, plane)
         } else {
             //self.erase(x, y)
             false
         }
     }
+    #[inline]
+    fn shift(&mut self,to:(usize,usize),from:Option<(usize,usize)>){
+        let moved = match from {
+            Some((x,y))=>self.map[y][x]&self.planes,
+            _=>0
+        };
+        let (x,y) = to;
+        self.map[y][x] = (self.map[y][x]&!self.planes)|moved;
+    }
+    ///Scrolls the selected planes down by n rows
+    pub fn scroll_down(&mut self,n:usize){
+        let (w,h) = (self.width(),self.height());
+        for y in (0..h).rev() {
+            for x in 0..w {
+                self.shift((x,y),if y>=n {Some((x,y-n))} else {None});
+            }
+        }
+        self.has_updates = true;
+    }
+    ///Scrolls the selected planes up by n rows
+    pub fn scroll_up(&mut self,n:usize){
+        let (w,h) = (self.width(),self.height());
+        for y in 0..h {
+            for x in 0..w {
+                self.shift((x,y),if y+n<h {Some((x,y+n))} else {None});
+            }
+        }
+        self.has_updates = true;
+    }
+    ///Scrolls the selected planes right by n columns
+    pub fn scroll_right(&mut self,n:usize){
+        let (w,h) = (self.width(),self.height());
+        for y in 0..h {
+            for x in (0..w).rev() {
+                self.shift((x,y),if x>=n {Some((x-n,y))} else {None});
+            }
+        }
+        self.has_updates = true;
+    }
+    ///Scrolls the selected planes left by n columns
+    pub fn scroll_left(&mut self,n:usize){
+        let (w,h) = (self.width(),self.height());
+        for y in 0..h {
+            for x in 0..w {
+                self.shift((x,y),if x+n<w {Some((x+n,y))} else {None});
+            }
+        }
+        self.has_updates = true;
+    }
     pub fn flush(&mut self){
         self.has_updates=false;
     }
@@ -73,4 +169,23 @@ impl PixMap {
     pub fn force_redisplay(&mut self){
         self.has_updates = true
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for PixMap {
+    fn save(&self,out:&mut Vec<u8>) {
+        put_bool(out,self.hires);
+        out.push(self.planes);
+        for row in self.map.iter() {
+            out.extend_from_slice(row);
+        }
+    }
+    fn load(&mut self,input:&mut &[u8])->std::io::Result<()> {
+        self.hires = get_bool(input)?;
+        self.planes = get_u8(input)?&3;
+        for row in self.map.iter_mut() {
+            get_bytes(input,row)?;
+        }
+        self.has_updates = true;
+        Ok(())
+    }
+}
@@ -0,0 +1,401 @@
+use std::fmt;
+use crate::chip8::InstructionSet;
+use crate::errors::*;
+
+macro_rules! x_in_xkk {
+    ($inst:expr) => {
+        (($inst>>8)&0xF) as u8
+    };
+}
+macro_rules! kk_in_xkk {
+    ($inst:expr) => {
+        ($inst&0xFF) as u8
+    };
+}
+macro_rules! y_in_xy {
+    ($inst:expr) => {
+        (($inst>>4)&0xF) as u8
+    };
+}
+macro_rules! last_nibble {
+    ($inst:expr) => {
+        ($inst&0xF) as u8
+    };
+}
+macro_rules! nnn {
+    ($inst:expr) => {
+        ($inst&0xFFF)
+    };
+}
+
+///One decoded opcode, named after Cowgod's mnemonics
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+pub enum Instruction {
+    ///0NNN
+    Sys(u16),
+    ///00E0
+    Cls,
+    ///00EE
+    Ret,
+    ///00CN
+    ScrollDown(u8),
+    ///00DN
+    ScrollUp(u8),
+    ///00FB
+    ScrollRight,
+    ///00FC
+    ScrollLeft,
+    ///00FD
+    Exit,
+    ///00FE
+    Low,
+    ///00FF
+    High,
+    ///1NNN
+    Jp(u16),
+    ///2NNN
+    Call(u16),
+    ///3XKK
+    SeByte{x:u8,kk:u8},
+    ///4XKK
+    SneByte{x:u8,kk:u8},
+    ///5XY0
+    SeReg{x:u8,y:u8},
+    ///5XY2
+    SaveRange{x:u8,y:u8},
+    ///5XY3
+    LoadRange{x:u8,y:u8},
+    ///6XKK
+    LdByte{x:u8,kk:u8},
+    ///7XKK
+    AddByte{x:u8,kk:u8},
+    ///8XY0
+    LdReg{x:u8,y:u8},
+    ///8XY1
+    Or{x:u8,y:u8},
+    ///8XY2
+    And{x:u8,y:u8},
+    ///8XY3
+    Xor{x:u8,y:u8},
+    ///8XY4
+    AddReg{x:u8,y:u8},
+    ///8XY5
+    Sub{x:u8,y:u8},
+    ///8XY6
+    Shr{x:u8,y:u8},
+    ///8XY7
+    Subn{x:u8,y:u8},
+    ///8XYE
+    Shl{x:u8,y:u8},
+    ///9XY0
+    SneReg{x:u8,y:u8},
+    ///ANNN
+    LdI(u16),
+    ///BNNN
+    JpV0(u16),
+    ///CXKK
+    Rnd{x:u8,kk:u8},
+    ///DXYN
+    Drw{x:u8,y:u8,n:u8},
+    ///EX9E
+    Skp(u8),
+    ///EXA1
+    Sknp(u8),
+    ///F000 NNNN, the address is the word after the opcode
+    LdILong(u16),
+    ///FN01
+    Plane(u8),
+    ///F002
+    Audio,
+    ///FX07
+    LdVxDt(u8),
+    ///FX0A
+    LdKey(u8),
+    ///FX15
+    LdDtVx(u8),
+    ///FX18
+    LdStVx(u8),
+    ///FX1E
+    AddI(u8),
+    ///FX29
+    LdFont(u8),
+    ///FX30
+    LdBigFont(u8),
+    ///FX33
+    Bcd(u8),
+    ///FX3A
+    Pitch(u8),
+    ///FX55
+    StoreRegs(u8),
+    ///FX65
+    LoadRegs(u8),
+    ///FX75
+    StoreFlags(u8),
+    ///FX85
+    LoadFlags(u8),
+}
+
+impl Instruction {
+    ///Decodes one opcode, instructions newer than set are invalid.
+    ///The opcode alone leaves LdILong's address at 0, see with_operand
+    pub fn decode(op:u16,set:InstructionSet)->Result<Self> {
+        let x = x_in_xkk!(op);
+        let y = y_in_xy!(op);
+        let kk = kk_in_xkk!(op);
+        let n = last_nibble!(op);
+        let instruction = match (op>>12)&0xF {
+            0x0=>match op {
+                0x00E0=>Instruction::Cls,
+                0x00EE=>Instruction::Ret,
+                0x00C0..=0x00CF=>Instruction::ScrollDown(n),
+                0x00D0..=0x00DF=>Instruction::ScrollUp(n),
+                0x00FB=>Instruction::ScrollRight,
+                0x00FC=>Instruction::ScrollLeft,
+                0x00FD=>Instruction::Exit,
+                0x00FE=>Instruction::Low,
+                0x00FF=>Instruction::High,
+                _=>Instruction::Sys(nnn!(op))
+            }
+            0x1=>Instruction::Jp(nnn!(op)),
+            0x2=>Instruction::Call(nnn!(op)),
+            0x3=>Instruction::SeByte{x,kk},
+            0x4=>Instruction::SneByte{x,kk},
+            0x5=>match n {
+                0x0=>Instruction::SeReg{x,y},
+                0x2=>Instruction::SaveRange{x,y},
+                0x3=>Instruction::LoadRange{x,y},
+                _=>return Err(ErrorKind::InvalidInstruction.into())
+            }
+            0x6=>Instruction::LdByte{x,kk},
+            0x7=>Instruction::AddByte{x,kk},
+            0x8=>match n {
+                0x0=>Instruction::LdReg{x,y},
+                0x1=>Instruction::Or{x,y},
+                0x2=>Instruction::And{x,y},
+                0x3=>Instruction::Xor{x,y},
+                0x4=>Instruction::AddReg{x,y},
+                0x5=>Instruction::Sub{x,y},
+                0x6=>Instruction::Shr{x,y},
+                0x7=>Instruction::Subn{x,y},
+                0xE=>Instruction::Shl{x,y},
+                _=>return Err(ErrorKind::InvalidInstruction.into())
+            }
+            0x9=>match n {
+                0x0=>Instruction::SneReg{x,y},
+                _=>return Err(ErrorKind::InvalidInstruction.into())
+            }
+            0xA=>Instruction::LdI(nnn!(op)),
+            0xB=>Instruction::JpV0(nnn!(op)),
+            0xC=>Instruction::Rnd{x,kk},
+            0xD=>Instruction::Drw{x,y,n},
+            0xE=>match kk {
+                0x9E=>Instruction::Skp(x),
+                0xA1=>Instruction::Sknp(x),
+                _=>return Err(ErrorKind::InvalidInstruction.into())
+            }
+            _=>match (x,kk) {
+                (0x0,0x00)=>Instruction::LdILong(0),
+                (_,0x01)=>Instruction::Plane(x),
+                (0x0,0x02)=>Instruction::Audio,
+                (_,0x07)=>Instruction::LdVxDt(x),
+                (_,0x0A)=>Instruction::LdKey(x),
+                (_,0x15)=>Instruction::LdDtVx(x),
+                (_,0x18)=>Instruction::LdStVx(x),
+                (_,0x1E)=>Instruction::AddI(x),
+                (_,0x29)=>Instruction::LdFont(x),
+                (_,0x30)=>Instruction::LdBigFont(x),
+                (_,0x33)=>Instruction::Bcd(x),
+                (_,0x3A)=>Instruction::Pitch(x),
+                (_,0x55)=>Instruction::StoreRegs(x),
+                (_,0x65)=>Instruction::LoadRegs(x),
+                (_,0x75)=>Instruction::StoreFlags(x),
+                (_,0x85)=>Instruction::LoadFlags(x),
+                _=>return Err(ErrorKind::InvalidInstruction.into())
+            }
+        };
+        if instruction.set() > set {
+            return Err(ErrorKind::InvalidInstruction.into());
+        }
+        Ok(instruction)
+    }
+    ///Fills in the word after the opcode for instructions that take one
+    #[inline]
+    pub fn with_operand(self,word:u16)->Self {
+        match self {
+            Instruction::LdILong(_)=>Instruction::LdILong(word),
+            _=>self
+        }
+    }
+    pub fn encode(&self)->u16 {
+        let xy = |op:u16,x:u8,y:u8,n:u8|op|((x as u16&0xF)<<8)|((y as u16&0xF)<<4)|(n as u16&0xF);
+        let xkk = |op:u16,x:u8,kk:u8|op|((x as u16&0xF)<<8)|kk as u16;
+        match *self {
+            Instruction::Sys(addr)=>nnn!(addr),
+            Instruction::Cls=>0x00E0,
+            Instruction::Ret=>0x00EE,
+            Instruction::ScrollDown(n)=>0x00C0|(n as u16&0xF),
+            Instruction::ScrollUp(n)=>0x00D0|(n as u16&0xF),
+            Instruction::ScrollRight=>0x00FB,
+            Instruction::ScrollLeft=>0x00FC,
+            Instruction::Exit=>0x00FD,
+            Instruction::Low=>0x00FE,
+            Instruction::High=>0x00FF,
+            Instruction::Jp(addr)=>0x1000|nnn!(addr),
+            Instruction::Call(addr)=>0x2000|nnn!(addr),
+            Instruction::SeByte{x,kk}=>xkk(0x3000,x,kk),
+            Instruction::SneByte{x,kk}=>xkk(0x4000,x,kk),
+            Instruction::SeReg{x,y}=>xy(0x5000,x,y,0x0),
+            Instruction::SaveRange{x,y}=>xy(0x5000,x,y,0x2),
+            Instruction::LoadRange{x,y}=>xy(0x5000,x,y,0x3),
+            Instruction::LdByte{x,kk}=>xkk(0x6000,x,kk),
+            Instruction::AddByte{x,kk}=>xkk(0x7000,x,kk),
+            Instruction::LdReg{x,y}=>xy(0x8000,x,y,0x0),
+            Instruction::Or{x,y}=>xy(0x8000,x,y,0x1),
+            Instruction::And{x,y}=>xy(0x8000,x,y,0x2),
+            Instruction::Xor{x,y}=>xy(0x8000,x,y,0x3),
+            Instruction::AddReg{x,y}=>xy(0x8000,x,y,0x4),
+            Instruction::Sub{x,y}=>xy(0x8000,x,y,0x5),
+            Instruction::Shr{x,y}=>xy(0x8000,x,y,0x6),
+            Instruction::Subn{x,y}=>xy(0x8000,x,y,0x7),
+            Instruction::Shl{x,y}=>xy(0x8000,x,y,0xE),
+            Instruction::SneReg{x,y}=>xy(0x9000,x,y,0x0),
+            Instruction::LdI(addr)=>0xA000|nnn!(addr),
+            Instruction::JpV0(addr)=>0xB000|nnn!(addr),
+            Instruction::Rnd{x,kk}=>xkk(0xC000,x,kk),
+            Instruction::Drw{x,y,n}=>xy(0xD000,x,y,n),
+            Instruction::Skp(x)=>xkk(0xE000,x,0x9E),
+            Instruction::Sknp(x)=>xkk(0xE000,x,0xA1),
+            Instruction::LdILong(_)=>0xF000,
+            Instruction::Plane(n)=>xkk(0xF000,n,0x01),
+            Instruction::Audio=>0xF002,
+            Instruction::LdVxDt(x)=>xkk(0xF000,x,0x07),
+            Instruction::LdKey(x)=>xkk(0xF000,x,0x0A),
+            Instruction::LdDtVx(x)=>xkk(0xF000,x,0x15),
+            Instruction::LdStVx(x)=>xkk(0xF000,x,0x18),
+            Instruction::AddI(x)=>xkk(0xF000,x,0x1E),
+            Instruction::LdFont(x)=>xkk(0xF000,x,0x29),
+            Instruction::LdBigFont(x)=>xkk(0xF000,x,0x30),
+            Instruction::Bcd(x)=>xkk(0xF000,x,0x33),
+            Instruction::Pitch(x)=>xkk(0xF000,x,0x3A),
+            Instruction::StoreRegs(x)=>xkk(0xF000,x,0x55),
+            Instruction::LoadRegs(x)=>xkk(0xF000,x,0x65),
+            Instruction::StoreFlags(x)=>xkk(0xF000,x,0x75),
+            Instruction::LoadFlags(x)=>xkk(0xF000,x,0x85),
+        }
+    }
+    ///The oldest instruction set that has this instruction
+    pub fn set(&self)->InstructionSet {
+        match self {
+            Instruction::ScrollDown(_)|Instruction::ScrollRight|Instruction::ScrollLeft|
+            Instruction::Exit|Instruction::Low|Instruction::High|
+            Instruction::LdBigFont(_)|Instruction::StoreFlags(_)|Instruction::LoadFlags(_)=>InstructionSet::SuperChip,
+            Instruction::ScrollUp(_)|Instruction::SaveRange{..}|Instruction::LoadRange{..}|
+            Instruction::LdILong(_)|Instruction::Plane(_)|Instruction::Audio|Instruction::Pitch(_)=>InstructionSet::XoChip,
+            _=>InstructionSet::Chip8
+        }
+    }
+    ///Size in bytes including any operand words
+    #[inline]
+    pub fn len(&self)->u16 {
+        match self {
+            Instruction::LdILong(_)=>4,
+            _=>2
+        }
+    }
+    ///Never true, every instruction has an opcode
+    #[inline]
+    pub fn is_empty(&self)->bool {
+        false
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys(addr)=>write!(f,"SYS #{:03X}",addr),
+            Instruction::Cls=>write!(f,"CLS"),
+            Instruction::Ret=>write!(f,"RET"),
+            Instruction::ScrollDown(n)=>write!(f,"SCD {}",n),
+            Instruction::ScrollUp(n)=>write!(f,"SCU {}",n),
+            Instruction::ScrollRight=>write!(f,"SCR"),
+            Instruction::ScrollLeft=>write!(f,"SCL"),
+            Instruction::Exit=>write!(f,"EXIT"),
+            Instruction::Low=>write!(f,"LOW"),
+            Instruction::High=>write!(f,"HIGH"),
+            Instruction::Jp(addr)=>write!(f,"JP #{:03X}",addr),
+            Instruction::Call(addr)=>write!(f,"CALL #{:03X}",addr),
+            Instruction::SeByte{x,kk}=>write!(f,"SE V{:X}, #{:02X}",x,kk),
+            Instruction::SneByte{x,kk}=>write!(f,"SNE V{:X}, #{:02X}",x,kk),
+            Instruction::SeReg{x,y}=>write!(f,"SE V{:X}, V{:X}",x,y),
+            Instruction::SaveRange{x,y}=>write!(f,"SAVE V{:X}, V{:X}",x,y),
+            Instruction::LoadRange{x,y}=>write!(f,"LOAD V{:X}, V{:X}",x,y),
+            Instruction::LdByte{x,kk}=>write!(f,"LD V{:X}, #{:02X}",x,kk),
+            Instruction::AddByte{x,kk}=>write!(f,"ADD V{:X}, #{:02X}",x,kk),
+            Instruction::LdReg{x,y}=>write!(f,"LD V{:X}, V{:X}",x,y),
+            Instruction::Or{x,y}=>write!(f,"OR V{:X}, V{:X}",x,y),
+            Instruction::And{x,y}=>write!(f,"AND V{:X}, V{:X}",x,y),
+            Instruction::Xor{x,y}=>write!(f,"XOR V{:X}, V{:X}",x,y),
+            Instruction::AddReg{x,y}=>write!(f,"ADD V{:X}, V{:X}",x,y),
+            Instruction::Sub{x,y}=>write!(f,"SUB V{:X}, V{:X}",x,y),
+            Instruction::Shr{x,y}=>write!(f,"SHR V{:X}, V{:X}",x,y),
+            Instruction::Subn{x,y}=>write!(f,"SUBN V{:X}, V{:X}",x,y),
+            Instruction::Shl{x,y}=>write!(f,"SHL V{:X}, V{:X}",x,y),
+            Instruction::SneReg{x,y}=>write!(f,"SNE V{:X}, V{:X}",x,y),
+            Instruction::LdI(addr)=>write!(f,"LD I, #{:03X}",addr),
+            Instruction::JpV0(addr)=>write!(f,"JP V0, #{:03X}",addr),
+            Instruction::Rnd{x,kk}=>write!(f,"RND V{:X}, #{:02X}",x,kk),
+            Instruction::Drw{x,y,n}=>write!(f,"DRW V{:X}, V{:X}, {}",x,y,n),
+            Instruction::Skp(x)=>write!(f,"SKP V{:X}",x),
+            Instruction::Sknp(x)=>write!(f,"SKNP V{:X}",x),
+            Instruction::LdILong(addr)=>write!(f,"LD I, LONG #{:04X}",addr),
+            Instruction::Plane(n)=>write!(f,"PLANE {}",n),
+            Instruction::Audio=>write!(f,"AUDIO"),
+            Instruction::LdVxDt(x)=>write!(f,"LD V{:X}, DT",x),
+            Instruction::LdKey(x)=>write!(f,"LD V{:X}, K",x),
+            Instruction::LdDtVx(x)=>write!(f,"LD DT, V{:X}",x),
+            Instruction::LdStVx(x)=>write!(f,"LD ST, V{:X}",x),
+            Instruction::AddI(x)=>write!(f,"ADD I, V{:X}",x),
+            Instruction::LdFont(x)=>write!(f,"LD F, V{:X}",x),
+            Instruction::LdBigFont(x)=>write!(f,"LD HF, V{:X}",x),
+            Instruction::Bcd(x)=>write!(f,"LD B, V{:X}",x),
+            Instruction::Pitch(x)=>write!(f,"LD PITCH, V{:X}",x),
+            Instruction::StoreRegs(x)=>write!(f,"LD [I], V{:X}",x),
+            Instruction::LoadRegs(x)=>write!(f,"LD V{:X}, [I]",x),
+            Instruction::StoreFlags(x)=>write!(f,"LD R, V{:X}",x),
+            Instruction::LoadFlags(x)=>write!(f,"LD V{:X}, R",x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_opcode_round_trips() {
+        for &set in &[InstructionSet::Chip8,InstructionSet::SuperChip,InstructionSet::XoChip] {
+            for op in 0..=0xFFFF {
+                if let Ok(instruction) = Instruction::decode(op,set) {
+                    assert_eq!(instruction.encode(),op,"{:?} decoded {:04X} as {}",set,op,instruction);
+                    assert!(instruction.set() <= set);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn newer_instructions_are_invalid_on_older_sets() {
+        assert!(Instruction::decode(0x00FE,InstructionSet::Chip8).is_err());
+        assert!(Instruction::decode(0x00C4,InstructionSet::Chip8).is_err());
+        assert_eq!(Instruction::decode(0x00C4,InstructionSet::SuperChip),Ok(Instruction::ScrollDown(4)));
+        assert!(Instruction::decode(0xF000,InstructionSet::SuperChip).is_err());
+        assert_eq!(Instruction::decode(0x0123,InstructionSet::Chip8),Ok(Instruction::Sys(0x123)));
+    }
+
+    #[test]
+    fn long_loads_show_their_address() {
+        let instruction = Instruction::decode(0xF000,InstructionSet::XoChip).unwrap().with_operand(0x1234);
+        assert_eq!(instruction.to_string(),"LD I, LONG #1234");
+        assert_eq!(instruction.len(),4);
+    }
+}
@@ -1,4 +1,7 @@
-#[derive(Copy, Clone,PartialEq,PartialOrd,Debug)]
+use std::collections::VecDeque;
+use crate::savestate::*;
+
+#[derive(Copy, Clone,PartialEq,Eq,PartialOrd,Debug)]
 #[repr(u8)]
 pub enum KeyEventKind {
     KeyRelease=0,
@@ -7,39 +10,42 @@ pub enum KeyEventKind {
 }
 
 
-#[derive(Copy, Clone,Debug)]
+///The 16 keys of the hex keypad, each valued as its hex digit
+#[derive(Copy, Clone,PartialEq,Eq,Debug)]
 #[repr(u8)]
 pub enum Key {
-    A=0,
-    B=1,
-    C=2,
-    D=3,
-    E=4,
-    F=5,
-    Zero=6,
-    One=7,
-    Two=8,
-    Three=9,
-    Four=10,
-    Five=11,
-    Six=12,
-    Seven=13,
-    Eight=14,
-    Nine=15
+    Zero=0x0,
+    One=0x1,
+    Two=0x2,
+    Three=0x3,
+    Four=0x4,
+    Five=0x5,
+    Six=0x6,
+    Seven=0x7,
+    Eight=0x8,
+    Nine=0x9,
+    A=0xA,
+    B=0xB,
+    C=0xC,
+    D=0xD,
+    E=0xE,
+    F=0xF
 }
+///Every key, indexed by its hex digit
+const C8_KEYS:[Key;16] = [
+    Key::Zero,Key::One,Key::Two,Key::Three,Key::Four,Key::Five,Key::Six,Key::Seven,
+    Key::Eight,Key::Nine,Key::A,Key::B,Key::C,Key::D,Key::E,Key::F
+];
 impl Key {
+    #[inline]
     pub fn from_byte(byte:u8)->Option<Self>{
-        if byte <16{
-            Some(unsafe{std::mem::transmute(byte)})
-        }else{
-            None
-        }
+        C8_KEYS.get(byte as usize).copied()
     }
     pub fn into_byte(&self)->u8 {
         *self as u8
     }
 }
-#[derive(Clone,Copy,Debug)]
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
 pub struct KeyEvent {
     pub key:Key,
     pub kind:KeyEventKind
@@ -56,7 +62,7 @@ impl KeyEvent {
     pub fn from_keyd(keys:u8,kind:KeyEventKind)->Self {
         KeyEvent{
             key:Key::from_byte(keys&0xF).unwrap(),
-            kind:kind
+            kind
         }
     }
     #[inline]
@@ -69,21 +75,65 @@ impl KeyEvent {
     }
 }
 
-#[derive(Clone,Copy)]
-pub struct Keyboard([KeyEventKind;16]);
+///Key states plus the events waiting for their frame
+#[derive(Clone)]
+pub struct Keyboard {
+    keys:[KeyEventKind;16],
+    ///Sorted by frame, events for the same frame stay in the order they were queued
+    queue:VecDeque<(u64,KeyEvent)>,
+}
 impl From<[KeyEventKind;16]> for Keyboard {
     #[inline]
     fn from(a:[KeyEventKind;16])->Self{
-        return Self(a)
+        Self{keys:a,queue:VecDeque::new()}
+    }
+}
+impl Default for Keyboard {
+    #[inline]
+    fn default()->Self {
+        Self::new()
     }
 }
 impl Keyboard {
     pub fn new()->Self{
-        return Self([KeyEventKind::KeyDefault;16])
+        Self::from([KeyEventKind::KeyDefault;16])
+    }
+    ///Queues event for the start of frame, events for frames already run happen at the next one
+    pub fn queue(&mut self,frame:u64,event:KeyEvent){
+        let at = self.queue.iter().rposition(|(f,_)|*f <= frame).map_or(0,|n|n+1);
+        self.queue.insert(at,(frame,event))
+    }
+    ///Events still waiting for their frame
+    #[inline]
+    pub fn pending(&self)->usize{
+        self.queue.len()
+    }
+    ///Applies the events due by frame, a key pressed and released within one frame
+    ///has the release, and whatever follows for that key, held back a frame so the
+    ///program always sees the tap. Other keys' events still happen this frame
+    pub fn apply(&mut self,frame:u64){
+        let (mut pressed,mut held) = (0u16,0u16);
+        let mut n = 0;
+        while let Some(&(at,event)) = self.queue.get(n) {
+            if at > frame {
+                break
+            }
+            let bit = 1<<event.key.into_byte();
+            if held&bit != 0 || (event.kind != KeyEventKind::KeyPress && pressed&bit != 0) {
+                held|=bit;
+                n+=1;
+                continue
+            }
+            if event.kind == KeyEventKind::KeyPress {
+                pressed|=bit;
+            }
+            self.action(event);
+            self.queue.remove(n);
+        }
     }
     #[inline]
     pub fn action(&mut self,event:KeyEvent){
-        self.0[event.key.into_byte() as usize] = event.kind
+        self.keys[event.key.into_byte() as usize] = event.kind
     }
     #[inline]
     pub fn commit(&mut self,event:impl std::iter::Iterator<Item=KeyEvent>){
@@ -97,35 +147,32 @@ impl Keyboard {
     }
     #[inline]
     pub fn is_pressed(&self,key:Key) ->bool{
-       return self.0[key.into_byte() as usize] == KeyEventKind::KeyPress;
+       self.keys[key.into_byte() as usize] == KeyEventKind::KeyPress
     }
     #[inline]
     pub fn is_currenly_released(&self,key:Key) ->bool{
-       return self.0[key.into_byte() as usize] == KeyEventKind::KeyRelease;
+       self.keys[key.into_byte() as usize] == KeyEventKind::KeyRelease
     }
     #[inline]
     pub fn is_released(&self,key:Key) ->bool{
-       return match self.0[key.into_byte() as usize]{
-           KeyEventKind::KeyRelease|KeyEventKind::KeyDefault=>true,
-           _=>false
-        }
+       matches!(self.keys[key.into_byte() as usize],KeyEventKind::KeyRelease|KeyEventKind::KeyDefault)
     }
     #[inline]
     pub fn all_events(&self) ->impl std::iter::Iterator<Item=KeyEvent>+'_ {
-        return self.0.iter().enumerate().map(|(i,x)| KeyEvent::from_keyd(i as u8,*x))
+        self.keys.iter().enumerate().map(|(i,x)| KeyEvent::from_keyd(i as u8,*x))
     }
     
     #[inline]
     pub fn all_pressed(&self) ->impl std::iter::Iterator<Item=KeyEvent>+'_ {
-        return self.all_events().filter(|x|x.kind == KeyEventKind::KeyPress)
+        self.all_events().filter(|x|x.kind == KeyEventKind::KeyPress)
     }
     #[inline]
     pub fn all_currenly_released(&self) ->impl std::iter::Iterator<Item=KeyEvent>+'_ {
-        return self.all_events().filter(|x|x.kind == KeyEventKind::KeyRelease)
+        self.all_events().filter(|x|x.kind == KeyEventKind::KeyRelease)
     }
     #[inline]
     pub fn all_released(&self) ->impl std::iter::Iterator<Item=KeyEvent>+'_ {
-        return self.all_events().filter(|x|x.kind == KeyEventKind::KeyRelease ||
+        self.all_events().filter(|x|x.kind == KeyEventKind::KeyRelease ||
                                          x.kind == KeyEventKind::KeyDefault)
     }
     
@@ -136,9 +183,10 @@ impl Keyboard {
     pub fn release(&mut self,key:Key){
        self.action(KeyEvent::new(key, KeyEventKind::KeyRelease))
     }
+    ///Released keys go back to their default state, the machine does this at the end of every frame
     #[inline]
     pub fn reset(&mut self){
-       for i in &mut self.0 {
+       for i in &mut self.keys {
            if *i == KeyEventKind::KeyRelease {
                *i = KeyEventKind::KeyDefault
            }
@@ -149,3 +197,67 @@ impl Keyboard {
     
 }
 
+impl Snapshot for Keyboard {
+    fn save(&self,out:&mut Vec<u8>) {
+        for kind in &self.keys {
+            out.push(*kind as u8);
+        }
+        put_u32(out,self.queue.len() as u32);
+        for (frame,event) in &self.queue {
+            put_u64(out,*frame);
+            out.push(event.key.into_byte());
+            out.push(event.kind as u8);
+        }
+    }
+    fn load(&mut self,input:&mut &[u8])->std::io::Result<()> {
+        let kind = |byte|match byte {
+            0=>Ok(KeyEventKind::KeyRelease),
+            1=>Ok(KeyEventKind::KeyPress),
+            2=>Ok(KeyEventKind::KeyDefault),
+            _=>Err(invalid("bad key state"))
+        };
+        for key in &mut self.keys {
+            *key = kind(get_u8(input)?)?;
+        }
+        self.queue.clear();
+        for _ in 0..get_u32(input)? {
+            let frame = get_u64(input)?;
+            let key = Key::from_byte(get_u8(input)?).ok_or_else(||invalid("bad key"))?;
+            self.queue.push_back((frame,KeyEvent::new(key,kind(get_u8(input)?)?)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(key:u8,kind:KeyEventKind)->KeyEvent {
+        KeyEvent::new(Key::from_byte(key).unwrap(),kind)
+    }
+
+    #[test]
+    fn keys_round_trip_through_bytes() {
+        for byte in 0..16 {
+            assert_eq!(Key::from_byte(byte).map(|k|k.into_byte()),Some(byte));
+        }
+        assert_eq!(Key::from_byte(16),None);
+    }
+
+    #[test]
+    fn a_tap_only_holds_back_its_own_release() {
+        let mut keyboard = Keyboard::new();
+        keyboard.queue(0,event(0x1,KeyEventKind::KeyPress));
+        keyboard.queue(0,event(0x1,KeyEventKind::KeyRelease));
+        keyboard.queue(0,event(0x1,KeyEventKind::KeyPress));
+        keyboard.queue(0,event(0x2,KeyEventKind::KeyPress));
+        keyboard.apply(0);
+        assert!(keyboard.is_pressed(Key::One));
+        assert!(keyboard.is_pressed(Key::Two));
+        assert_eq!(keyboard.pending(),2);
+        keyboard.apply(1);
+        assert!(keyboard.is_pressed(Key::One));
+        assert_eq!(keyboard.pending(),0);
+    }
+}
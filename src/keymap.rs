@@ -0,0 +1,209 @@
+use std::path::Path;
+use crate::errors::SourceError;
+use crate::keyboard::Key;
+
+///Keypad keys in the order they're laid out on the COSMAC VIP, left to right and top to bottom
+pub const C8_KEYPAD_ORDER:[u8;16] = [0x1,0x2,0x3,0xC,0x4,0x5,0x6,0xD,0x7,0x8,0x9,0xE,0xA,0x0,0xB,0xF];
+
+///Named layouts, the keyboard keys for each keypad key in C8_KEYPAD_ORDER, several keys are separated by spaces
+pub const C8_KEYMAP_PRESETS:[(&str,[&str;16]);6] = [
+    ("qwerty",["1","2","3","4","q","w","e","r","a","s","d","f","z","x","c","v"]),
+    //the top row is bound shifted and unshifted
+    ("azerty",["1 &","2 é","3 \"","4 '","a","z","e","r","q","s","d","f","w","x","c","v"]),
+    ("qwertz",["1","2","3","4","q","w","e","r","a","s","d","f","y","x","c","v"]),
+    ("dvorak",["1","2","3","4","'",",",".","p","a","o","e","u",";","q","j","k"]),
+    ("numpad",["kp7","kp8","kp9","kp/","kp4","kp5","kp6","kp*","kp1","kp2","kp3","kp-","kp0","kp.","kpenter","kp+"]),
+    //the left half of the keypad for one player, the right half for the other
+    ("split",["1","2","9","0","q","w","o","p","a","s","l",";","z","x",".","/"]),
+];
+
+///Lowercase with keypad keys shortened, so `Keypad 7` is `kp7` and `Q` is `q`
+pub fn key_name(name:&str)->String {
+    let name = name.to_lowercase();
+    match name.strip_prefix("keypad") {
+        Some(rest)=>format!("kp{}",rest.trim()),
+        None=>name.replace(' ',"")
+    }
+}
+
+///Which keyboard keys press which keypad keys, every frontend looks keys up by name
+#[derive(Clone,Debug)]
+pub struct Keymap {
+    bindings:Vec<(String,Key)>,
+}
+
+impl Keymap {
+    ///A keymap with nothing bound
+    #[inline]
+    pub fn new()->Self {
+        Self{bindings:vec![]}
+    }
+    pub fn preset(name:&str)->Option<Self> {
+        let (_,keys) = C8_KEYMAP_PRESETS.iter().find(|(n,_)|n.eq_ignore_ascii_case(name))?;
+        let mut keymap = Self::new();
+        for (names,digit) in keys.iter().zip(C8_KEYPAD_ORDER.iter()) {
+            for name in names.split_whitespace() {
+                keymap.bind(name,Key::from_byte(*digit)?);
+            }
+        }
+        Some(keymap)
+    }
+    ///Reads a keymap file, each line binds a keypad key to one or more keyboard keys:
+    ///
+    ///```text
+    ///preset = qwerty   # start from a preset
+    ///c = 4 kp/         # keypad C on 4 and the keypad's /
+    ///```
+    pub fn parse(file:&str,text:&str)->Result<Self,SourceError> {
+        let mut keymap = Self::new();
+        //keyboard keys bound by this file and the line that bound them, presets may be overridden but not these
+        let mut bound:Vec<(String,usize)> = vec![];
+        for (n,line) in text.lines().enumerate() {
+            let err = |message:String|SourceError::new(file,n+1,message);
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (left,right) = line.split_once('=').ok_or_else(||err("expected `key = keyboard keys`".to_string()))?;
+            let (left,names) = (left.trim(),right.split_whitespace().collect::<Vec<_>>());
+            if left.eq_ignore_ascii_case("preset") {
+                let name = match names[..] {
+                    [name]=>name,
+                    _=>return Err(err("preset takes one name".to_string()))
+                };
+                let preset = Self::preset(name).ok_or_else(||err(format!("unknown preset {}",name)))?;
+                for (name,key) in preset.bindings {
+                    keymap.bind(&name,key);
+                }
+                continue;
+            }
+            let key = match left.len() {
+                1=>u8::from_str_radix(left,16).ok().and_then(Key::from_byte),
+                _=>None
+            }.ok_or_else(||err(format!("{} isn't a keypad key, use 0-9 or A-F",left)))?;
+            if names.is_empty() {
+                return Err(err(format!("no keyboard keys for {}",left)));
+            }
+            for name in names {
+                let name = key_name(name);
+                if let Some((_,line)) = bound.iter().find(|(n,_)|*n == name) {
+                    return Err(err(format!("{} is already bound on line {}",name,line)));
+                }
+                keymap.bind(&name,key);
+                bound.push((name,n+1));
+            }
+        }
+        Ok(keymap)
+    }
+    pub fn load(path:&Path)->Result<Self,SourceError> {
+        let file = path.display().to_string();
+        let text = std::fs::read_to_string(path).map_err(|e|SourceError::new(&file,0,e.to_string()))?;
+        Self::parse(&file,&text)
+    }
+    ///Binds the keyboard key to key, replacing whatever it pressed before
+    pub fn bind(&mut self,name:&str,key:Key) {
+        let name = key_name(name);
+        self.bindings.retain(|(n,_)|*n != name);
+        self.bindings.push((name,key));
+    }
+    ///The keypad key pressed by a keyboard key, names are matched as key_name
+    pub fn key(&self,name:&str)->Option<Key> {
+        let name = key_name(name);
+        self.bindings.iter().find(|(n,_)|*n == name).map(|(_,key)|*key)
+    }
+    #[inline]
+    pub fn bindings(&self)->&[(String,Key)] {
+        &self.bindings
+    }
+}
+
+impl Default for Keymap {
+    fn default()->Self {
+        Self::preset("qwerty").unwrap_or_else(||Self{bindings:vec![]})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(digit:u8)->Option<Key> {
+        Key::from_byte(digit)
+    }
+
+    fn error(text:&str)->(usize,String) {
+        let e = Keymap::parse("keys",text).unwrap_err();
+        assert_eq!(e.file,"keys");
+        (e.line,e.message)
+    }
+
+    #[test]
+    fn parse_starts_from_a_preset_and_overrides_it() {
+        let keymap = Keymap::parse("keys","# pong\n\npreset = qwerty\nc = 4 KP/  # both\n5 = Up q\n").unwrap();
+        assert_eq!(keymap.key("4"),key(0xC));
+        assert_eq!(keymap.key("kp/"),key(0xC));
+        assert_eq!(keymap.key("UP"),key(0x5));
+        //q pressed 4 in the preset
+        assert_eq!(keymap.key("q"),key(0x5));
+        assert_eq!(keymap.key("w"),key(0x5));
+        assert_eq!(keymap.key("x"),key(0x0));
+        assert_eq!(keymap.key("m"),None);
+        //a replaced binding doesn't linger
+        assert_eq!(keymap.bindings().iter().filter(|(n,_)|n == "q").count(),1);
+    }
+
+    #[test]
+    fn parse_without_a_preset_binds_only_its_lines() {
+        let keymap = Keymap::parse("keys","A = kp0\nf = kpenter").unwrap();
+        assert_eq!(keymap.bindings().len(),2);
+        assert_eq!(keymap.key("Keypad 0"),key(0xA));
+        assert_eq!(keymap.key("kpenter"),key(0xF));
+        assert_eq!(keymap.key("1"),None);
+    }
+
+    #[test]
+    fn every_preset_binds_the_whole_keypad() {
+        for (name,_) in C8_KEYMAP_PRESETS.iter() {
+            let keymap = Keymap::preset(name).unwrap();
+            for digit in 0..16 {
+                assert!(keymap.bindings().iter().any(|(_,k)|Some(*k) == key(digit)),"{} leaves {:X} unbound",name,digit);
+            }
+        }
+        assert!(Keymap::preset("QWERTY").is_some());
+        assert!(Keymap::preset("colemak").is_none());
+    }
+
+    #[test]
+    fn presets_follow_the_vip_layout() {
+        let qwerty = Keymap::preset("qwerty").unwrap();
+        assert_eq!(qwerty.key("1"),key(0x1));
+        assert_eq!(qwerty.key("4"),key(0xC));
+        assert_eq!(qwerty.key("x"),key(0x0));
+        assert_eq!(qwerty.key("v"),key(0xF));
+        let azerty = Keymap::preset("azerty").unwrap();
+        assert_eq!(azerty.key("&"),key(0x1));
+        assert_eq!(azerty.key("1"),key(0x1));
+        assert_eq!(azerty.key("w"),key(0xA));
+        assert_eq!(Keymap::preset("numpad").unwrap().key("Keypad Enter"),key(0xB));
+        let split = Keymap::preset("split").unwrap();
+        assert_eq!(split.key("s"),key(0x8));
+        assert_eq!(split.key("l"),key(0x9));
+        assert_eq!(Keymap::default().key("z"),key(0xA));
+    }
+
+    #[test]
+    fn bad_lines_report_their_line() {
+        assert_eq!(error("1 = 1\nc 4"),(2,"expected `key = keyboard keys`".to_string()));
+        assert_eq!(error("\n\ng = x"),(3,"g isn't a keypad key, use 0-9 or A-F".to_string()));
+        assert_eq!(error("10 = x"),(1,"10 isn't a keypad key, use 0-9 or A-F".to_string()));
+        assert_eq!(error("# none\n5 =   # yet"),(2,"no keyboard keys for 5".to_string()));
+        assert_eq!(error("preset = colemak"),(1,"unknown preset colemak".to_string()));
+        assert_eq!(error("1 = 1\npreset = qwerty azerty"),(2,"preset takes one name".to_string()));
+    }
+
+    #[test]
+    fn keys_bound_twice_report_the_first_line() {
+        assert_eq!(error("1 = x\n\n2 = up X"),(3,"x is already bound on line 1".to_string()));
+        assert_eq!(error("1 = kp1 KP1"),(1,"kp1 is already bound on line 1".to_string()));
+    }
+}
@@ -6,3 +6,24 @@ pub mod keyboard;
 pub mod aux;
 pub mod chip8;
 pub mod fonts;
+pub mod quirks;
+pub mod platform;
+pub mod instruction;
+pub mod disasm;
+pub mod scheduler;
+pub mod savestate;
+pub mod rewind;
+pub mod debugger;
+pub mod dap;
+pub mod trace;
+pub mod overlay;
+pub mod crash;
+pub mod audio;
+pub mod wav;
+pub mod frontend;
+pub mod keymap;
+pub mod tty;
+#[cfg(feature="sdl")]
+pub mod sdl;
+pub mod encoder;
+pub mod octo;
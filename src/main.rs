@@ -1,170 +1,880 @@
-use sdl2::event::{Event,WindowEvent};
-use sdl2::keyboard::{Scancode,Keycode};
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::rect::Rect;
-use clap::{App,Arg};
+use std::path::{Path,PathBuf};
+use clap::{App,AppSettings,Arg,ArgMatches};
 use chip8::{
     chip8::Chip8,
-    graphics::PixMap,
-    keyboard::{Key,KeyEvent,KeyEventKind},
-    errors::Error};
+    cpu::CHIP8_PROGRAM_START,
+    disasm::disassemble,
+    encoder::Assembler,
+    octo::Octo,
+    chip8::InstructionSet,
+    platform::{Platform,Profile,C8_PLATFORMS},
+    fonts::{font,C8_FONTS,C8_FONT_PACK_SIZE},
+    quirks::C8_QUIRK_FLAGS,
+    scheduler::{Scheduler,SystemClock},
+    rewind::Rewind,
+    debugger::{Debugger,describe,registers,parse_number},
+    dap::{DapServer,read_message},
+    trace::{self,Tracer},
+    crash::{InputLog,CrashBundle,SavedBundle,crash_report},
+    audio::{AudioSettings,NullSink,Waveform,C8_WAVEFORMS},
+    frontend::{AudioSink,Display,Input,InputSource,Status},
+    keymap::{Keymap,C8_KEYMAP_PRESETS},
+    tty::{Terminal,TtyScreen,TtyInput},
+    wav::WavRecorder};
+#[cfg(feature="sdl")]
+use chip8::sdl::SdlWindow;
+use std::io::{BufRead,BufWriter,Write};
+use std::fs::File;
+use std::sync::mpsc::{channel,Receiver,RecvTimeoutError};
 
-#[derive(Copy,Clone)]
-struct WindowSize {
-    x:u32,
-    y:u32
+///Where traces and recordings are written
+type Output = BufWriter<File>;
+
+#[cfg(feature="sdl")]
+const C8_DEFAULT_FRONTEND:&str = "sdl";
+#[cfg(not(feature="sdl"))]
+const C8_DEFAULT_FRONTEND:&str = "tty";
+
+///Sound and keyboard options for everything that opens a window
+fn frontend_args(app:App<'static>)->App<'static> {
+    app
+    .arg(
+        Arg::new("keymap")
+        .long("keymap")
+        .takes_value(true)
+        .value_name("NAME|FILE")
+        .help("keyboard layout, one of qwerty, azerty, qwertz, dvorak, numpad or split, or a keymap file")
+    )
+    .arg(
+        Arg::new("tone")
+        .long("tone")
+        .takes_value(true)
+        .value_name("HZ")
+        .help("pitch of the beep (default: 440)")
+    )
+    .arg(
+        Arg::new("waveform")
+        .long("waveform")
+        .takes_value(true)
+        .possible_values(C8_WAVEFORMS.iter().map(|(name,_)|*name))
+        .help("shape of the beep (default: square)")
+    )
+    .arg(
+        Arg::new("volume")
+        .long("volume")
+        .takes_value(true)
+        .value_name("0-100")
+        .help("loudness of the beep (default: 25)")
+    )
+    .arg(
+        Arg::new("mute")
+        .long("mute")
+        .takes_value(false)
+        .help("no sound")
+    )
 }
 
+///Tracing, recording and frontend options for running a rom, shared by the player and the debugger
+fn session_args(app:App<'static>)->App<'static> {
+    app
+    .arg(
+        Arg::new("trace")
+        .long("trace")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("log every executed instruction: cycle, PC, opcode, disassembly, changed registers and I")
+    )
+    .arg(
+        Arg::new("audio-out")
+        .long("audio-out")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("record the beep to a WAV file, in step with the emulated frames")
+    )
+    .arg(
+        Arg::new("frames")
+        .long("frames")
+        .takes_value(true)
+        .value_name("N")
+        .help("quit after running N frames")
+    )
+    .arg(
+        Arg::new("frontend")
+        .long("frontend")
+        .takes_value(true)
+        .possible_values(["sdl","tty"])
+        .help("sdl opens a window, tty draws in the terminal for SSH sessions")
+    )
+    .arg(
+        Arg::new("headless")
+        .long("headless")
+        .takes_value(false)
+        .requires("frames")
+        .help("run as fast as possible without a window, audio device or keyboard")
+    )
+}
+
+fn audio_settings(matches:&ArgMatches)->Result<AudioSettings,String> {
+    let mut settings = AudioSettings::default();
+    if let Some(tone) = matches.value_of("tone") {
+        settings.frequency = tone.parse::<f32>().ok().filter(|hz|*hz > 0.0).ok_or("tone must be a positive number of hz")?;
+    }
+    if let Some(waveform) = matches.value_of("waveform").and_then(Waveform::from_name) {
+        settings.waveform = waveform;
+    }
+    if let Some(volume) = matches.value_of("volume") {
+        let volume = volume.parse::<u8>().ok().filter(|v|*v <= 100).ok_or("volume must be 0 to 100")?;
+        settings.volume = volume as f32/100.0;
+    }
+    settings.muted = matches.is_present("mute");
+    Ok(settings)
+}
+
+///Platform and quirk options shared by everything that runs a rom
+fn machine_args(mut app:App<'static>)->App<'static> {
+    app = app
+    .arg(
+        Arg::new("cosmic")
+        .short('c')
+        .long("cosmic")
+        .takes_value(false)
+        .conflicts_with_all(&["platform","schip","xochip"])
+        .help("shorthand for --platform vip")
+    )
+    .arg(
+        Arg::new("schip")
+        .short('s')
+        .long("schip")
+        .takes_value(false)
+        .conflicts_with_all(&["platform","xochip"])
+        .help("shorthand for --platform schip-modern")
+    )
+    .arg(
+        Arg::new("xochip")
+        .short('x')
+        .long("xochip")
+        .takes_value(false)
+        .conflicts_with("platform")
+        .help("shorthand for --platform xochip")
+    )
+    .arg(
+        Arg::new("platform")
+        .short('p')
+        .long("platform")
+        .takes_value(true)
+        .possible_values(C8_PLATFORMS.iter().map(|(name,_)|*name))
+        .help("emulate the quirks, instruction set, memory and speed of a platform")
+    )
+    .arg(
+        Arg::new("font")
+        .long("font")
+        .takes_value(true)
+        .possible_values(C8_FONTS.iter().map(|(name,_)|*name))
+        .help("the small font FX29 points at, by default the platform's own")
+    )
+    .arg(
+        Arg::new("font-base")
+        .long("font-base")
+        .takes_value(true)
+        .value_name("ADDR")
+        .help("load the fonts from ADDR instead of 0x0")
+    )
+    .arg(
+        Arg::new("ipf")
+        .long("ipf")
+        .takes_value(true)
+        .value_name("N")
+        .help("instructions executed per 60hz frame")
+    )
+    .arg(
+        Arg::new("stack-depth")
+        .long("stack-depth")
+        .takes_value(true)
+        .value_name("N")
+        .help("maximum number of nested calls, 0 for the 256 call hard limit")
+    );
+    for (name,help) in C8_QUIRK_FLAGS.iter() {
+        app = app.arg(
+            Arg::new(*name)
+            .long(name)
+            .takes_value(true)
+            .value_name("on|off")
+            .possible_values(["on","off"])
+            .help(*help)
+        );
+    }
+    app
+}
 
-fn color(byte:u8)->u8 {
-    match byte {
-        0=>0,
-        _=>255
+fn profile(matches:&ArgMatches)->Result<Profile,String> {
+    let platform = if matches.is_present("cosmic") {
+        Some(Platform::Vip)
+    } else if matches.is_present("schip") {
+        Some(Platform::SChipModern)
+    } else if matches.is_present("xochip") {
+        Some(Platform::XoChip)
+    } else {
+        matches.value_of("platform").and_then(Platform::from_name)
+    };
+    let mut profile = platform.map(Profile::from).unwrap_or_default();
+    for (name,_) in C8_QUIRK_FLAGS.iter() {
+        if let Some(value) = matches.value_of(name) {
+            profile.quirks.set(name, value == "on");
+        }
+    }
+    if let Some(depth) = matches.value_of("stack-depth") {
+        profile.quirks.stack_depth = depth.parse().map_err(|_|"stack depth must be a number")?;
+    }
+    if let Some(ipf) = matches.value_of("ipf") {
+        profile.instructions_per_frame = ipf.parse().map_err(|_|"instructions per frame must be a number")?;
+    }
+    if let Some(name) = matches.value_of("font") {
+        profile.font = font(name).ok_or_else(||format!("no font {}",name))?;
     }
+    if let Some(base) = matches.value_of("font-base") {
+        profile.font_base = parse_number(base).ok_or("font base must be an address")?;
+        let end = profile.font_base as usize+C8_FONT_PACK_SIZE as usize;
+        if end > profile.memory_size {
+            return Err(format!("fonts at {:04X} would run past the end of memory at {:04X}",profile.font_base,profile.memory_size));
+        }
+    }
+    Ok(profile)
 }
+
 pub fn main() -> Result<(), String> {
-    let matches = App::new(env!("CARGO_PKG_NAME"))
+    let app = App::new(env!("CARGO_PKG_NAME"))
     .about("A chip8 emulator to play some killer games!")
     .version(env!("CARGO_PKG_VERSION"))
     .author("Blake Brown")
+    .setting(AppSettings::SubcommandsNegateReqs)
+    .setting(AppSettings::ArgsNegateSubcommands)
     .arg(
         Arg::new("game")
         .required(true)
-        .help("the path to the chip8 rom to run")
+        .help("the path to the chip8 rom, or .8o Octo source, to run")
     )
-    .arg(
-        Arg::new("cosmic")
-        .short('c')
-        .long("cosmic")
-        .takes_value(false)
-        .help("run the emulator in cosmic vip mode (default: false)")
+    .subcommand(
+        App::new("disasm")
+        .about("disassemble a rom, following its control flow to separate code from data")
+        .arg(
+            Arg::new("rom")
+            .required(true)
+            .help("the path to the chip8 rom to disassemble")
+        )
+        .arg(
+            Arg::new("origin")
+            .long("origin")
+            .takes_value(true)
+            .value_name("ADDR")
+            .help("address the rom is loaded at (default: 0x200)")
+        )
+        .arg(
+            Arg::new("output")
+            .short('o')
+            .long("output")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("write the listing to a file instead of stdout")
+        )
+        .arg(
+            Arg::new("target")
+            .short('t')
+            .long("target")
+            .takes_value(true)
+            .possible_values(["chip8","schip","xochip"])
+            .default_value("xochip")
+            .help("the instruction set the rom was written for, anything newer is data")
+        )
+    )
+    .subcommand(
+        App::new("asm")
+        .about("assemble Cowgod-style mnemonics into a rom")
+        .arg(
+            Arg::new("source")
+            .required(true)
+            .help("the path to the assembly source")
+        )
+        .arg(
+            Arg::new("output")
+            .short('o')
+            .long("output")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("where to write the rom (default: the source with a .ch8 extension)")
+        )
+        .arg(
+            Arg::new("origin")
+            .long("origin")
+            .takes_value(true)
+            .value_name("ADDR")
+            .help("address the rom is loaded at (default: 0x200)")
+        )
+        .arg(
+            Arg::new("listing")
+            .long("listing")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("also write a listing of addresses, bytes and source lines")
+        )
     )
-    .get_matches();
+    .subcommand(
+        App::new("octo")
+        .about("compile an Octo program into a rom")
+        .arg(
+            Arg::new("source")
+            .required(true)
+            .help("the path to the .8o source")
+        )
+        .arg(
+            Arg::new("output")
+            .short('o')
+            .long("output")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("where to write the rom (default: the source with a .ch8 extension)")
+        )
+        .arg(
+            Arg::new("target")
+            .short('t')
+            .long("target")
+            .takes_value(true)
+            .possible_values(["chip8","schip","xochip"])
+            .default_value("xochip")
+            .help("the instruction set the program may use")
+        )
+    );
+    let app = app.subcommand(machine_args(frontend_args(session_args(
+        App::new("debug")
+        .about("run a rom paused under an interactive debugger, type help for its commands")
+        .arg(
+            Arg::new("game")
+            .required(true)
+            .help("the path to the chip8 rom, or .8o Octo source, to debug")
+        )
+    ))));
+    let app = app.subcommand(
+        App::new("trace-diff")
+        .about("compare two --trace logs and report where they first diverge")
+        .arg(
+            Arg::new("a")
+            .required(true)
+            .help("the first trace")
+        )
+        .arg(
+            Arg::new("b")
+            .required(true)
+            .help("the trace to compare it against")
+        )
+    );
+    let app = app.subcommand(machine_args(
+        App::new("replay")
+        .about("rerun a crash bundle's input log from its start state and check it crashes the same way")
+        .arg(
+            Arg::new("bundle")
+            .required(true)
+            .help("the directory written when enter is pressed after a crash")
+        )
+        .arg(
+            Arg::new("frames")
+            .long("frames")
+            .takes_value(true)
+            .value_name("N")
+            .help("give up if it hasn't crashed after N frames (default: 36000)")
+        )
+    ));
+    let app = app.subcommand(machine_args(
+        App::new("dap")
+        .about("serve the Debug Adapter Protocol on stdin/stdout so editors can debug roms")
+    ));
+    let matches = machine_args(frontend_args(session_args(app))).get_matches();
+    match matches.subcommand() {
+        Some(("disasm",matches))=>disasm(matches),
+        Some(("asm",matches))=>asm(matches),
+        Some(("octo",matches))=>octo(matches),
+        Some(("debug",matches))=>run(matches,true),
+        Some(("dap",matches))=>dap(matches),
+        Some(("replay",matches))=>replay(matches),
+        Some(("trace-diff",matches))=>trace_diff(matches),
+        _=>run(&matches,false)
+    }
+}
+
+fn disasm(matches:&ArgMatches)->Result<(),String> {
+    let path = matches.value_of("rom").ok_or("No rom provided")?;
+    let origin = match matches.value_of("origin") {
+        Some(origin)=>parse_number(origin).ok_or("origin must be an address")?,
+        _=>CHIP8_PROGRAM_START
+    };
+    let rom = std::fs::read(path).map_err(|e|e.to_string())?;
+    let listing = disassemble(&rom, origin, target(matches));
+    match matches.value_of("output") {
+        Some(output)=>std::fs::write(output, listing).map_err(|e|e.to_string()),
+        _=>{
+            print!("{}",listing);
+            Ok(())
+        }
+    }
+}
+
+fn asm(matches:&ArgMatches)->Result<(),String> {
+    let source = Path::new(matches.value_of("source").ok_or("No source provided")?);
+    let origin = match matches.value_of("origin") {
+        Some(origin)=>parse_number(origin).ok_or("origin must be an address")?,
+        _=>CHIP8_PROGRAM_START
+    };
+    let assembly = Assembler::new().with_origin(origin).assemble_file(source).map_err(|e|e.to_string())?;
+    let output = match matches.value_of("output") {
+        Some(output)=>PathBuf::from(output),
+        _=>source.with_extension("ch8")
+    };
+    std::fs::write(&output, &assembly.bytes).map_err(|e|e.to_string())?;
+    if let Some(listing) = matches.value_of("listing") {
+        std::fs::write(listing, assembly.listing_text()).map_err(|e|e.to_string())?;
+    }
+    Ok(())
+}
+
+fn octo(matches:&ArgMatches)->Result<(),String> {
+    let source = Path::new(matches.value_of("source").ok_or("No source provided")?);
+    let program = compile_octo(source,target(matches))?;
+    let output = match matches.value_of("output") {
+        Some(output)=>PathBuf::from(output),
+        _=>source.with_extension("ch8")
+    };
+    std::fs::write(&output, &program).map_err(|e|e.to_string())
+}
+
+///--target, XO-CHIP when it's missing
+fn target(matches:&ArgMatches)->InstructionSet {
+    match matches.value_of("target") {
+        Some("chip8")=>InstructionSet::Chip8,
+        Some("schip")=>InstructionSet::SuperChip,
+        _=>InstructionSet::XoChip
+    }
+}
+
+fn compile_octo(source:&Path,target:InstructionSet)->Result<Vec<u8>,String> {
+    let text = std::fs::read_to_string(source).map_err(|e|e.to_string())?;
+    let program = Octo::new().with_target(target).compile(&source.display().to_string(),&text).map_err(|e|e.to_string())?;
+    Ok(program.bytes)
+}
+
+///Reads a rom, compiling it first when it is Octo source
+fn read_rom(path:&Path,target:InstructionSet)->Result<Vec<u8>,String> {
+    match path.extension() {
+        Some(ext) if ext == "8o"=>compile_octo(path,target),
+        _=>std::fs::read(path).map_err(|e|e.to_string())
+    }
+}
+
+fn trace_diff(matches:&ArgMatches)->Result<(),String> {
+    let read = |name:&str|->Result<String,String>{
+        let path = matches.value_of(name).ok_or("Two traces are needed")?;
+        std::fs::read_to_string(path).map_err(|e|format!("{}: {}",path,e))
+    };
+    match trace::diff(&read("a")?,&read("b")?) {
+        Some(divergence)=>{
+            print!("{}",divergence);
+            std::process::exit(1)
+        }
+        None=>{
+            println!("traces match");
+            Ok(())
+        }
+    }
+}
+
+fn replay(matches:&ArgMatches)->Result<(),String> {
+    let dir = Path::new(matches.value_of("bundle").ok_or("No bundle provided")?);
+    let frames = match matches.value_of("frames") {
+        Some(n)=>n.parse::<u64>().map_err(|_|"frames must be a number")?,
+        None=>36000
+    };
+    let bundle = SavedBundle::read(dir).map_err(|e|e.to_string())?;
+    let mut chip8 = Chip8::create(profile(matches)?).map_err(|e|e.to_string())?;
+    match bundle.replay(&mut chip8,frames).map_err(|e|e.to_string())? {
+        Some(e)=>{
+            println!("{}",crash_report(&chip8,&e));
+            if bundle.matches_crash(&chip8) {
+                println!("reproduced, the machine matches crash.state");
+                Ok(())
+            } else {
+                Err("crashed, but the machine differs from crash.state".to_string())
+            }
+        }
+        None=>Err(format!("no crash after {} frames",frames))
+    }
+}
+
+///Reads debugger commands from stdin without blocking the window
+fn spawn_console()->Receiver<String> {
+    let (tx,rx) = channel();
+    std::thread::spawn(move||{
+        let stdin = std::io::stdin();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0)|Err(_)=>{
+                    let _ = tx.send("quit".to_string());
+                    break
+                }
+                Ok(_)=>if tx.send(line.trim().to_string()).is_err() {
+                    break
+                }
+            }
+        }
+    });
+    rx
+}
+
+///Runs headless, the editor launches the rom and drives execution through requests
+fn dap(matches:&ArgMatches)->Result<(),String> {
+    let (tx,requests) = channel();
+    std::thread::spawn(move||{
+        let stdin = std::io::stdin();
+        let mut input = stdin.lock();
+        while let Ok(Some(request)) = read_message(&mut input) {
+            if tx.send(request).is_err() {
+                break
+            }
+        }
+    });
+    let stdout = std::io::stdout();
+    let mut server = DapServer::new(profile(matches)?,stdout.lock());
+    let mut scheduler = Scheduler::new(SystemClock::new());
+    while !server.is_done() {
+        let request = if server.is_running() {
+            match requests.recv_timeout(scheduler.until_next()) {
+                Ok(request)=>Some(request),
+                Err(RecvTimeoutError::Timeout)=>None,
+                Err(RecvTimeoutError::Disconnected)=>break
+            }
+        } else {
+            match requests.recv() {
+                Ok(request)=>Some(request),
+                Err(_)=>break
+            }
+        };
+        if let Some(request) = request {
+            server.handle(&request).map_err(|e|e.to_string())?;
+            continue;
+        }
+        for _ in 0..scheduler.due_frames() {
+            server.run_frame().map_err(|e|e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    let _ = std::io::stdout().flush();
+}
+
+fn run(matches:&ArgMatches,debug:bool)->Result<(),String> {
     let game = match matches.value_of("game") {
         Some(a)=>Ok(a),
         _=>Err("No game provided")
     }?;
-    let mut chip8 = Chip8::create(matches.is_present("cosmic"));
-    let rom = std::fs::read(game).map_err(|e|e.to_string())?;
-    chip8.cpu.memory_mut().load_big_binary_instructions(0x200,&rom);
-    chip8.cpu.memory().dump(0x200..0x220);
-    let (sound,delay) = chip8.start().map_err(|e| e.to_string())?;
-    let sdl_context = sdl2::init()?;
-    let video_subsystem = sdl_context.video()?;
-    let mut size = WindowSize{x:800,y:600};
-    let window = video_subsystem
-        .window(game, size.x, size.y)
-        .resizable()
-        .position_centered()
-        .opengl()
-        .build()
-        .map_err(|e| e.to_string())?;
-
-    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
-    let texture_creator = canvas.texture_creator();
-
-    let mut texture = texture_creator
-        .create_texture_streaming(PixelFormatEnum::RGB24, 64, 32)
-        .map_err(|e| e.to_string())?;
-    
-    let mut update_canvas = |pix:&mut PixMap,size:WindowSize,use_data:bool|->Result<(),String>{
-                if use_data{
-                    canvas.clear();
-                    texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-                        for y in 0..32 {
-                            for x in 0..64 {
-                                let offset = y * pitch + x * 3;
-                                let col:u8 = color(pix.get(x as u8,y as u8));
-                                buffer[offset] = col;
-                                buffer[offset + 1] = col;
-                                buffer[offset + 2] = col;
-                            }
-                        }
-                    })?;
-                    canvas.copy(&texture, None, Some(Rect::new(0, 0, size.x, size.y)))?;
-                    pix.flush();
-                }
-                canvas.present();
-                Ok(())
+    let profile = profile(matches)?;
+    let mut chip8 = Chip8::create(profile).map_err(|e|e.to_string())?;
+    let rom = read_rom(Path::new(game),profile.set)?;
+    chip8.load_rom(&rom).map_err(|e|e.to_string())?;
+    chip8.start();
+    let create = |path:&str|File::create(path).map(BufWriter::new).map_err(|e|format!("{}: {}",path,e));
+    let tracer = match matches.value_of("trace") {
+        Some(path)=>Some(Tracer::new(create(path)?)),
+        None=>None
+    };
+    let settings = audio_settings(matches)?;
+    //the recording keeps the sound even when the speakers are muted
+    let recorder = match matches.value_of("audio-out") {
+        Some(path)=>Some(WavRecorder::new(create(path)?,AudioSettings{muted:false,..settings}).map_err(|e|format!("{}: {}",path,e))?),
+        None=>None
+    };
+    let limit = match matches.value_of("frames") {
+        Some(n)=>Some(n.parse::<u64>().map_err(|_|"frames must be a number")?),
+        None=>None
     };
-            
-           
-    let mut display = |pix:&mut PixMap,size:WindowSize|->Result<(),String>{
-        return update_canvas(pix,size,pix.ready())
+    let mut session = Session{
+        game:game.to_string(),
+        rom,
+        chip8,
+        tracer,
+        recorder,
+        limit,
+        debugger:None,
     };
-    display(chip8.graphics_mut(),size)?;
-    let mut event_pump = sdl_context.event_pump()?;
-    
+    let result = if matches.is_present("headless") {
+        headless(&mut session)
+    } else {
+        if debug {
+            println!("{}",registers(&session.chip8));
+            prompt();
+            session.debugger = Some((Debugger::new(),spawn_console()));
+        }
+        let keymap = keymap(matches,game)?;
+        match matches.value_of("frontend").unwrap_or(C8_DEFAULT_FRONTEND) {
+            "tty" if debug=>Err("The debugger reads commands from the terminal, use the sdl frontend".to_string()),
+            "tty"=>terminal(&mut session,keymap),
+            _=>windowed(&mut session,profile,settings,keymap)
+        }
+    };
+    session.chip8.close();
+    session.finish()?;
+    result
+}
+
+///Draws in the terminal, there's no sound but the status line shows when it beeps
+fn terminal(session:&mut Session,keymap:Keymap)->Result<(),String> {
+    let _terminal = Terminal::open().map_err(|e|format!("Can't use the terminal: {}",e))?;
+    play(session,&mut TtyScreen::new(),&mut TtyInput::new(keymap),&mut NullSink)
+}
+
+#[cfg(feature="sdl")]
+fn windowed(session:&mut Session,profile:Profile,settings:AudioSettings,keymap:Keymap)->Result<(),String> {
+    let mut window = SdlWindow::open(&session.game,profile.set.display())?;
+    let mut input = window.input(keymap)?;
+    let mut audio = window.audio(settings);
+    let mut screen = window.screen()?;
+    play(session,&mut screen,&mut input,audio.as_mut())
+}
+
+#[cfg(not(feature="sdl"))]
+fn windowed(_session:&mut Session,_profile:Profile,_settings:AudioSettings,_keymap:Keymap)->Result<(),String> {
+    Err("This build has no window, rebuild with the sdl feature or use --frontend tty".to_string())
+}
+
+///A rom being run, whichever frontend it's shown on
+struct Session {
+    game:String,
+    rom:Vec<u8>,
+    chip8:Chip8,
+    tracer:Option<Tracer<Output>>,
+    recorder:Option<WavRecorder<Output>>,
+    ///Frames to run before quitting
+    limit:Option<u64>,
+    debugger:Option<(Debugger,Receiver<String>)>,
+}
+
+impl Session {
+    fn run_frame(&mut self)->chip8::errors::Result<()> {
+        match &mut self.tracer {
+            Some(tracer)=>tracer.run_frame(&mut self.chip8),
+            None=>self.chip8.run_frame()
+        }
+    }
+    ///Flushes the trace and completes the recording's header
+    fn finish(&mut self)->Result<(),String> {
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.flush().map_err(|e|e.to_string())?;
+        }
+        if let Some(recorder) = self.recorder.take() {
+            recorder.finish().map_err(|e|e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+///Runs frames back to back, for recording and testing without a display
+fn headless(session:&mut Session)->Result<(),String> {
+    for _ in 0..session.limit.unwrap_or(0) {
+        if let Err(e) = session.run_frame() {
+            println!("{}",crash_report(&session.chip8,&e));
+            return Err(e.to_string());
+        }
+        if let Some(recorder) = &mut session.recorder {
+            recorder.update(&session.chip8.sound());
+        }
+    }
+    Ok(())
+}
+
+fn play(session:&mut Session,display:&mut dyn Display,input:&mut dyn InputSource,audio:&mut dyn AudioSink)->Result<(),String> {
+    let Session{game,rom,chip8,tracer,recorder,limit,debugger} = session;
+    //crash bundles replay the input log from start_state, the log has the machine's frame numbers
+    let mut start_state = chip8.save_state();
+    let mut inputs = InputLog::new();
+    let mut frames_run:u64 = 0;
+    let mut crash:Option<String> = None;
+    let mut notice = String::new();
+    let mut scheduler = Scheduler::new(SystemClock::new());
+    let mut rewind = Rewind::default();
+    let mut rewinding = false;
+    //instructions per second, measured from the count at the mark
+    let mut ips = 0;
+    let mut ips_mark = (std::time::Instant::now(),chip8.instructions_executed());
+    display.present(chip8.graphics_mut(),None)?;
     'running: loop {
-        for event in event_pump.poll_iter() {
+        for event in input.poll() {
             match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => break 'running,
-                Event::KeyUp{scancode,..}|Event::KeyDown{scancode,..}=>{
-                    match scancode{
-                        Some(scancode)=>match norm_key_event(scancode, match event{
-                            Event::KeyUp{..}=>KeyEventKind::KeyRelease,
-                            _=>KeyEventKind::KeyPress,
-                        }) {
-                            Some(key)=>{
-                                println!("Clackin {:?}",key);
-                                chip8.keyboard_mut().action(key);
-                            }
-                            _=>{
-                                
+                Input::Quit=>break 'running,
+                Input::Confirm if crash.is_some()=>{
+                    let bundle = CrashBundle{
+                        report:crash.clone().unwrap_or_default(),
+                        rom,
+                        start_state:&start_state,
+                        crash_state:chip8.save_state(),
+                        inputs:&inputs,
+                    };
+                    notice = match bundle.write(Path::new(game)) {
+                        Ok(dir)=>format!("crash bundle written to {}",dir.display()),
+                        Err(e)=>format!("can't write crash bundle: {}",e)
+                    };
+                    println!("{}",notice);
+                }
+                _ if crash.is_some()=>{}
+                Input::Confirm=>{}
+                Input::Rewind(on)=>rewinding = on,
+                Input::LoadState(slot)=>{
+                    let path = state_path(game,slot);
+                    match std::fs::read(&path) {
+                        Ok(state)=>match chip8.load_state(&state) {
+                            Ok(_)=>{
+                                rewind.clear();
+                                start_state = chip8.save_state();
+                                inputs.clear();
+                                println!("Loaded state {} from {}",slot,path.display())
                             }
+                            Err(e)=>println!("Can't load state {}: {}",slot,e)
                         }
-                        _=>{
-
-                        }
+                        Err(e)=>println!("Can't read state {}: {}",slot,e)
+                    }
+                }
+                Input::SaveState(slot)=>{
+                    let path = state_path(game,slot);
+                    match std::fs::write(&path,chip8.save_state()) {
+                        Ok(_)=>println!("Saved state {} to {}",slot,path.display()),
+                        Err(e)=>println!("Can't save state {}: {}",slot,e)
                     }
                 }
-                Event::Window{win_event,..}=>{
-                    if let WindowEvent::Resized(x,y) =win_event {
-                        size.x=x as u32;
-                        size.y=y as u32;
-                        chip8.graphics_mut().force_redisplay();
-                    } 
+                Input::Key(key)=>{
+                    inputs.record(chip8.frame(),key);
+                    chip8.queue_input(key);
+                }
+            }
+        }
+        if let Some((debugger,console)) = debugger {
+            while let Ok(line) = console.try_recv() {
+                if line == "q" || line == "quit" {
+                    break 'running
+                }
+                match debugger.command(chip8,&line) {
+                    Ok(out) if out.is_empty()=>{}
+                    Ok(out)=>println!("{}",out),
+                    Err(e)=>println!("{}",e)
+                }
+                if debugger.is_paused() {
+                    prompt();
                 }
-               
-                _ => {}
             }
         }
-        let err= chip8.execute_step();
-        if err != Error::None {
-            break
+        let frames = scheduler.due_frames();
+        for _ in 0..frames {
+            if crash.is_some() {
+                break
+            } else if let Some((debugger,_)) = debugger {
+                if debugger.is_paused() {
+                    break
+                }
+                if let Some(stop) = debugger.run_frame(chip8) {
+                    println!("\n{}",describe(chip8,stop));
+                    prompt();
+                    break
+                }
+                if let Some(recorder) = recorder {
+                    recorder.update(&chip8.sound());
+                }
+            } else if rewinding {
+                //rewound frames aren't recorded or counted towards --frames
+                rewind.step_back(chip8);
+                start_state = chip8.save_state();
+                inputs.clear();
+                continue
+            } else {
+                let result = match tracer {
+                    Some(tracer)=>tracer.run_frame(chip8),
+                    None=>chip8.run_frame()
+                };
+                if let Err(e) = result {
+                    let report = crash_report(chip8,&e);
+                    println!("{}",report);
+                    chip8.close();
+                    crash = Some(report);
+                    notice = "enter: write a crash bundle   esc: quit".to_string();
+                    break
+                }
+                rewind.record(chip8);
+                if let Some(recorder) = recorder {
+                    recorder.update(&chip8.sound());
+                }
+            }
+            frames_run+=1;
+            if *limit == Some(frames_run) {
+                break 'running
+            }
         }
-        display(chip8.graphics_mut(),size)?;
-        chip8.keyboard_mut().reset()
+        if frames > 0 {
+            let paused = debugger.as_ref().is_some_and(|(debugger,_)|debugger.is_paused());
+            if crash.is_some() || paused {
+                audio.silence();
+            } else {
+                audio.update(&chip8.sound());
+            }
+            let elapsed = ips_mark.0.elapsed();
+            if elapsed.as_secs() >= 1 {
+                let executed = chip8.instructions_executed();
+                ips = ((executed-ips_mark.1) as f64/elapsed.as_secs_f64()) as u32;
+                ips_mark = (std::time::Instant::now(),executed);
+            }
+            display.status(&Status{
+                pc:chip8.cpu.program_counter(),
+                ips,
+                beeping:chip8.sound().is_playing() && crash.is_none() && !paused,
+                paused,
+            });
+            let panel = crash.as_ref().map(|report|format!("{}\n{}",report,notice));
+            display.present(chip8.graphics_mut(),panel.as_deref())?;
+        }
+        std::thread::sleep(scheduler.until_next());
     }
-    chip8.close(sound,delay);
     Ok(())
 }
 
-fn norm_key(key:Scancode)->Option<Key> {
-    Some(match key {
-        Scancode::Num1=>Key::One,
-        Scancode::Num2=>Key::Two,
-        Scancode::Num3=>Key::Three,
-        Scancode::Num4=>Key::C,
-        Scancode::Q=>Key::Four,
-        Scancode::W=>Key::Five,
-        Scancode::E=>Key::Six,
-        Scancode::R=>Key::D,
-        Scancode::A=>Key::Seven,
-        Scancode::S=>Key::Eight,
-        Scancode::D=>Key::Nine,
-        Scancode::F=>Key::E,
-        Scancode::Z=>Key::A,
-        Scancode::X=>Key::Zero,
-        Scancode::C=>Key::B,
-        Scancode::V=>Key::F,
-        _=>return None  
-    })
-}
-fn norm_key_event(key:Scancode,kind:KeyEventKind)->Option<KeyEvent> {
-    Some(KeyEvent::new(norm_key(key)?,kind))
-}
\ No newline at end of file
+///--keymap, otherwise game.ch8.keymap next to the rom, then the global keymap, then qwerty
+fn keymap(matches:&ArgMatches,game:&str)->Result<Keymap,String> {
+    let load = |path:&Path|Keymap::load(path).map_err(|e|e.to_string());
+    if let Some(name) = matches.value_of("keymap") {
+        return match Keymap::preset(name) {
+            Some(keymap)=>Ok(keymap),
+            None if Path::new(name).exists()=>load(Path::new(name)),
+            None=>{
+                let presets:Vec<&str> = C8_KEYMAP_PRESETS.iter().map(|(name,_)|*name).collect();
+                Err(format!("{} isn't a keymap file or one of {}",name,presets.join(", ")))
+            }
+        };
+    }
+    let rom = PathBuf::from(format!("{}.keymap",game));
+    if rom.exists() {
+        return load(&rom);
+    }
+    match global_keymap() {
+        Some(global) if global.exists()=>load(&global),
+        _=>Ok(Keymap::default())
+    }
+}
+
+///$XDG_CONFIG_HOME/chip8/keymap, or ~/.config/chip8/keymap
+fn global_keymap()->Option<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty()=>PathBuf::from(dir),
+        _=>PathBuf::from(std::env::var_os("HOME")?).join(".config")
+    };
+    Some(config.join("chip8").join("keymap"))
+}
+
+///Save states live next to the rom, game.ch8 keeps slot 1 in game.ch8.1.state
+fn state_path(game:&str,slot:u8)->PathBuf {
+    PathBuf::from(format!("{}.{}.state",game,slot))
+}
@@ -0,0 +1,1123 @@
+use std::collections::{BTreeMap,HashMap,VecDeque};
+use crate::chip8::InstructionSet;
+use crate::cpu::{CHIP8_MEM_SIZE,CHIP8_PROGRAM_START,XOCHIP_MEM_SIZE};
+use crate::errors::SourceError;
+use crate::instruction::Instruction;
+
+const C8_MAX_MACRO_EXPANSIONS:usize = 100000;
+
+#[derive(Clone,Debug)]
+struct Token {
+    text:String,
+    line:usize,
+}
+
+fn tokenize(source:&str)->Result<VecDeque<Token>,(usize,String)> {
+    let mut tokens = VecDeque::new();
+    for (n,text) in source.lines().enumerate() {
+        let line = n+1;
+        let mut chars = text.chars().peekable();
+        while let Some(&ch) = chars.peek() {
+            if ch.is_whitespace() {
+                chars.next();
+            } else if ch == '#' {
+                break;
+            } else if ch == '"' {
+                let mut string = String::from('"');
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"')=>break,
+                        Some(ch)=>string.push(ch),
+                        None=>return Err((line,"unterminated string".to_string()))
+                    }
+                }
+                tokens.push_back(Token{text:string,line});
+            } else {
+                let mut word = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_whitespace() {
+                        break;
+                    }
+                    word.push(ch);
+                    chars.next();
+                }
+                tokens.push_back(Token{text:word,line});
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn number(text:&str)->Option<i64> {
+    let (negative,text) = match text.strip_prefix('-') {
+        Some(rest)=>(true,rest),
+        _=>(false,text)
+    };
+    let value = if let Some(hex) = text.strip_prefix("0x").or_else(||text.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = text.strip_prefix("0b").or_else(||text.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if text.starts_with(|c:char|c.is_ascii_digit()) {
+        text.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative {-value} else {value})
+}
+
+#[derive(Clone,Debug)]
+struct Macro {
+    args:Vec<String>,
+    body:Vec<Token>,
+}
+
+///Where a forward reference to a label gets patched in
+#[derive(Copy,Clone,Debug)]
+enum Patch {
+    ///Low 12 bits of the opcode at the address
+    Nnn,
+    ///Whole 16 bit word at the address
+    Word,
+    ///Second byte of a 6XKK, the high byte of the address with a nibble on top
+    High(u8),
+    ///Second byte of a 6XKK, the low byte of the address
+    Low,
+}
+
+#[derive(Clone,Debug)]
+struct Fixup {
+    addr:u16,
+    name:String,
+    patch:Patch,
+    line:usize,
+}
+
+#[derive(Clone,Debug)]
+enum Block {
+    If{jump:u16,line:usize},
+    Else{jump:u16,line:usize},
+    Loop{start:u16,whiles:Vec<u16>,line:usize},
+}
+
+///Right hand side of a comparison
+#[derive(Copy,Clone,PartialEq,Debug)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Key,
+    NotKey,
+}
+
+///A compiled Octo program, starting at 0x200
+#[derive(Clone,PartialEq,Debug)]
+pub struct Program {
+    pub bytes:Vec<u8>,
+    pub labels:BTreeMap<String,u16>,
+    pub breakpoints:BTreeMap<String,u16>,
+}
+
+///Compiler for the Octo language
+pub struct Octo {
+    target:InstructionSet,
+}
+
+impl Default for Octo {
+    #[inline]
+    fn default()->Self {
+        Self{target:InstructionSet::XoChip}
+    }
+}
+
+impl Octo {
+    #[inline]
+    pub fn new()->Self {
+        Self::default()
+    }
+    ///Instructions from newer sets than the target are rejected
+    #[inline]
+    pub fn with_target(mut self,target:InstructionSet)->Self {
+        self.target = target;
+        self
+    }
+    pub fn compile(&self,name:&str,source:&str)->Result<Program,SourceError> {
+        let tokens = tokenize(source).map_err(|(line,message)|SourceError::new(name,line,message))?;
+        let mut compiler = Compiler::new(name,self.target,tokens);
+        compiler.run()?;
+        compiler.finish()
+    }
+}
+
+struct Compiler<'a> {
+    file:&'a str,
+    target:InstructionSet,
+    tokens:VecDeque<Token>,
+    line:usize,
+    rom:Vec<u8>,
+    here:u16,
+    has_main:bool,
+    labels:HashMap<String,u16>,
+    constants:HashMap<String,f64>,
+    aliases:HashMap<String,u8>,
+    macros:HashMap<String,Macro>,
+    fixups:Vec<Fixup>,
+    blocks:Vec<Block>,
+    breakpoints:BTreeMap<String,u16>,
+    expansions:usize,
+    ///First word of the statement being compiled, for diagnostics
+    statement:String,
+}
+
+type CResult<T> = Result<T,SourceError>;
+
+impl<'a> Compiler<'a> {
+    fn new(file:&'a str,target:InstructionSet,tokens:VecDeque<Token>)->Self {
+        Self{
+            file,
+            target,
+            tokens,
+            line:1,
+            rom:vec![0;2],
+            //room for the jump to main
+            here:CHIP8_PROGRAM_START+2,
+            has_main:true,
+            labels:HashMap::new(),
+            constants:HashMap::new(),
+            aliases:HashMap::new(),
+            macros:HashMap::new(),
+            fixups:vec![],
+            blocks:vec![],
+            breakpoints:BTreeMap::new(),
+            expansions:0,
+            statement:String::new(),
+        }
+    }
+    #[inline]
+    fn error<T>(&self,message:impl Into<String>)->CResult<T> {
+        Err(SourceError::new(self.file,self.line,message))
+    }
+    fn next(&mut self)->CResult<String> {
+        match self.tokens.pop_front() {
+            Some(token)=>{
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None=>self.error("unexpected end of file")
+        }
+    }
+    #[inline]
+    fn peek(&self)->Option<&str> {
+        self.tokens.front().map(|t|t.text.as_str())
+    }
+    fn expect(&mut self,text:&str)->CResult<()> {
+        let token = self.next()?;
+        if token != text {
+            return self.error(format!("expected '{}' but found '{}'",text,token));
+        }
+        Ok(())
+    }
+    fn limit(&self)->usize {
+        if self.target == InstructionSet::XoChip {XOCHIP_MEM_SIZE} else {CHIP8_MEM_SIZE}
+    }
+    fn emit_byte(&mut self,byte:u8)->CResult<()> {
+        if self.here as usize >= self.limit() {
+            return self.error(format!("program doesn't fit in {} bytes of memory",self.limit()));
+        }
+        let offset = (self.here-CHIP8_PROGRAM_START) as usize;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset+1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here = self.here.wrapping_add(1);
+        Ok(())
+    }
+    #[inline]
+    fn emit_word(&mut self,word:u16)->CResult<()> {
+        self.emit_byte((word>>8) as u8)?;
+        self.emit_byte(word as u8)
+    }
+    ///Emits an instruction, checking the target supports it
+    fn op(&mut self,instruction:Instruction)->CResult<()> {
+        let set = instruction.set();
+        if set > self.target {
+            let name = match set {
+                InstructionSet::SuperChip=>"schip",
+                _=>"xochip"
+            };
+            return self.error(format!("'{}' ({}) needs the {} target",self.statement,instruction,name));
+        }
+        self.emit_word(instruction.encode())
+    }
+    fn word_at(&self,addr:u16)->u16 {
+        let offset = (addr-CHIP8_PROGRAM_START) as usize;
+        ((self.rom[offset] as u16)<<8)|self.rom[offset+1] as u16
+    }
+    fn set_word_at(&mut self,addr:u16,word:u16) {
+        let offset = (addr-CHIP8_PROGRAM_START) as usize;
+        self.rom[offset] = (word>>8) as u8;
+        self.rom[offset+1] = word as u8;
+    }
+    ///Points the jump at addr somewhere else
+    #[inline]
+    fn patch_jump(&mut self,addr:u16,target:u16) {
+        let word = self.word_at(addr);
+        self.set_word_at(addr, (word&0xF000)|(target&0xFFF));
+    }
+
+    fn register_name(&self,text:&str)->Option<u8> {
+        if let Some(reg) = self.aliases.get(text) {
+            return Some(*reg);
+        }
+        let mut chars = text.chars();
+        match (chars.next(),chars.next(),chars.next()) {
+            (Some('v'),Some(n),None)|(Some('V'),Some(n),None)=>n.to_digit(16).map(|n|n as u8),
+            _=>None
+        }
+    }
+    #[inline]
+    fn is_register(&self)->bool {
+        self.peek().and_then(|t|self.register_name(t)).is_some()
+    }
+    fn register(&mut self)->CResult<u8> {
+        let token = self.next()?;
+        match self.register_name(&token) {
+            Some(reg)=>Ok(reg),
+            None=>self.error(format!("expected a register but found '{}'",token))
+        }
+    }
+    ///A number, constant or already defined label
+    fn known_value(&self,text:&str)->Option<f64> {
+        if let Some(n) = number(text) {
+            return Some(n as f64);
+        }
+        if let Some(value) = self.constants.get(text) {
+            return Some(*value);
+        }
+        self.labels.get(text).map(|addr|*addr as f64)
+    }
+    fn value(&mut self)->CResult<i64> {
+        let token = self.next()?;
+        match self.known_value(&token) {
+            Some(value)=>Ok(value.floor() as i64),
+            None if self.register_name(&token).is_some()=>self.error(format!("expected a value but found register '{}'",token)),
+            None=>self.error(format!("undefined name '{}'",token))
+        }
+    }
+    fn sized(&mut self,value:i64,min:i64,max:i64)->CResult<i64> {
+        if value < min || value > max {
+            return self.error(format!("value {} is out of range {}..{}",value,min,max));
+        }
+        Ok(value)
+    }
+    ///A byte immediate, negative numbers are stored as two's complement
+    fn byte(&mut self)->CResult<u8> {
+        let value = self.value()?;
+        Ok(self.sized(value,-128,255)? as u8)
+    }
+    fn nibble(&mut self)->CResult<u8> {
+        let value = self.value()?;
+        Ok(self.sized(value,0,15)? as u8)
+    }
+    ///An address, labels may be defined later and are patched once known
+    fn address(&mut self,patch:Patch,at:u16)->CResult<u16> {
+        let token = self.next()?;
+        let max = match patch {
+            Patch::Nnn=>0xFFF,
+            _=>0xFFFF
+        };
+        match self.known_value(&token) {
+            Some(value)=>{
+                let value = value.floor() as i64;
+                if value > max && max == 0xFFF {
+                    return self.error(format!("address {:#X} doesn't fit in 12 bits, use 'i := long'",value));
+                }
+                Ok(self.sized(value,0,max)? as u16)
+            }
+            None if self.register_name(&token).is_some()=>self.error(format!("expected an address but found register '{}'",token)),
+            None=>{
+                self.fixups.push(Fixup{addr:at,name:token,patch,line:self.line});
+                Ok(0)
+            }
+        }
+    }
+
+    fn run(&mut self)->CResult<()> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if let Some(block) = self.blocks.last() {
+            let (line,what) = match block {
+                Block::If{line,..}|Block::Else{line,..}=>(*line,"'begin' without 'end'"),
+                Block::Loop{line,..}=>(*line,"'loop' without 'again'"),
+            };
+            return Err(SourceError::new(self.file,line,what));
+        }
+        Ok(())
+    }
+    fn finish(mut self)->CResult<Program> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let addr = match self.labels.get(&fixup.name) {
+                Some(addr)=>*addr,
+                None=>return self.error(format!("undefined label '{}'",fixup.name))
+            };
+            let word = self.word_at(fixup.addr);
+            let word = match fixup.patch {
+                Patch::Nnn if addr > 0xFFF=>return self.error(format!("label '{}' at {:#X} doesn't fit in 12 bits, use 'i := long'",fixup.name,addr)),
+                Patch::Nnn=>(word&0xF000)|addr,
+                Patch::Word=>addr,
+                Patch::High(nibble)=>(word&0xFF00)|(((nibble as u16)<<4)|(addr>>8))&0xFF,
+                Patch::Low=>(word&0xFF00)|(addr&0xFF),
+            };
+            self.set_word_at(fixup.addr, word);
+        }
+        if self.has_main {
+            match self.labels.get("main") {
+                Some(main)=>{
+                    let main = *main;
+                    self.set_word_at(CHIP8_PROGRAM_START, Instruction::Jp(main).encode());
+                }
+                None=>return Err(SourceError::new(self.file,1,"program has no ': main' label"))
+            }
+        }
+        let labels = self.labels.into_iter().collect();
+        Ok(Program{bytes:self.rom,labels,breakpoints:self.breakpoints})
+    }
+
+    fn define_label(&mut self,name:String,addr:u16)->CResult<()> {
+        if self.register_name(&name).is_some() || number(&name).is_some() {
+            return self.error(format!("'{}' can't be used as a name",name));
+        }
+        if self.labels.insert(name.clone(),addr).is_some() {
+            return self.error(format!("label '{}' is already defined",name));
+        }
+        Ok(())
+    }
+    ///Tokens up to the matching '}', the opening brace is already consumed
+    fn braced(&mut self)->CResult<Vec<Token>> {
+        let mut depth = 1;
+        let mut body = vec![];
+        loop {
+            let token = match self.tokens.pop_front() {
+                Some(token)=>token,
+                None=>return self.error("missing '}'")
+            };
+            self.line = token.line;
+            match token.text.as_str() {
+                "{"=>depth+=1,
+                "}"=>{
+                    depth-=1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _=>{}
+            }
+            body.push(token);
+        }
+    }
+    ///A `{ expression }` following the current token
+    fn calc_block(&mut self)->CResult<f64> {
+        self.expect("{")?;
+        let body = self.braced()?;
+        let mut calc = Calc{compiler:self,tokens:body.into(),};
+        let value = calc.expr()?;
+        if let Some(token) = calc.tokens.pop_front() {
+            self.line = token.line;
+            return self.error(format!("unexpected '{}' in expression",token.text));
+        }
+        Ok(value)
+    }
+
+    fn statement(&mut self)->CResult<()> {
+        let token = self.next()?;
+        self.statement = token.clone();
+        if let Some(reg) = self.register_name(&token) {
+            return self.register_statement(reg);
+        }
+        match token.as_str() {
+            ":"=>{
+                let name = self.next()?;
+                if name == "main" && self.here == CHIP8_PROGRAM_START+2 && self.rom.len() == 2 {
+                    //main comes first, so there is no need to jump to it
+                    self.has_main = false;
+                    self.rom.clear();
+                    self.here = CHIP8_PROGRAM_START;
+                }
+                let here = self.here;
+                self.define_label(name,here)?;
+            }
+            ":next"=>{
+                let name = self.next()?;
+                let here = self.here+1;
+                self.define_label(name,here)?;
+            }
+            ":alias"=>{
+                let name = self.next()?;
+                let reg = if self.peek() == Some("{") {
+                    let value = self.calc_block()?.floor() as i64;
+                    self.sized(value,0,15)? as u8
+                } else {
+                    self.register()?
+                };
+                self.aliases.insert(name,reg);
+            }
+            ":const"=>{
+                let name = self.next()?;
+                let value = self.value()?;
+                self.constants.insert(name,value as f64);
+            }
+            ":calc"=>{
+                let name = self.next()?;
+                let value = self.calc_block()?;
+                self.constants.insert(name,value);
+            }
+            ":byte"=>{
+                let value = if self.peek() == Some("{") {
+                    self.calc_block()?.floor() as i64
+                } else {
+                    self.value()?
+                };
+                let byte = self.sized(value,-128,255)? as u8;
+                self.emit_byte(byte)?;
+            }
+            ":pointer"=>{
+                let here = self.here;
+                let addr = if self.peek() == Some("{") {
+                    let value = self.calc_block()?.floor() as i64;
+                    self.sized(value,0,0xFFFF)? as u16
+                } else {
+                    self.address(Patch::Word,here)?
+                };
+                self.emit_word(addr)?;
+            }
+            ":org"=>{
+                let value = if self.peek() == Some("{") {
+                    self.calc_block()?.floor() as i64
+                } else {
+                    self.value()?
+                };
+                let limit = self.limit() as i64-1;
+                self.here = self.sized(value,CHIP8_PROGRAM_START as i64,limit)? as u16;
+            }
+            ":unpack"=>{
+                let kind = self.next()?;
+                let nibble = if kind == "long" {
+                    None
+                } else {
+                    match self.known_value(&kind) {
+                        Some(value) if (0.0..16.0).contains(&value)=>Some(value as u8),
+                        _=>return self.error(format!("':unpack' expects a nibble or 'long', not '{}'",kind))
+                    }
+                };
+                let here = self.here;
+                let fixups = self.fixups.len();
+                let addr = self.address(Patch::High(nibble.unwrap_or(0)),here)?;
+                let high = ((nibble.unwrap_or(0) as u16)<<4)|(addr>>8);
+                self.op(Instruction::LdByte{x:0,kk:high as u8})?;
+                if self.fixups.len() > fixups {
+                    let fixup = Fixup{addr:self.here,patch:Patch::Low,..self.fixups[fixups].clone()};
+                    self.fixups.push(fixup);
+                }
+                self.op(Instruction::LdByte{x:1,kk:addr as u8})?;
+            }
+            ":breakpoint"=>{
+                let name = self.next()?;
+                self.breakpoints.insert(name,self.here);
+            }
+            ":monitor"=>{
+                self.next()?;
+                self.next()?;
+            }
+            ":assert"=>{
+                let message = match self.peek() {
+                    Some(text) if text.starts_with('"')=>Some(self.next()?[1..].to_string()),
+                    _=>None
+                };
+                if self.calc_block()? == 0.0 {
+                    return self.error(match message {
+                        Some(message)=>format!("assertion failed: {}",message),
+                        None=>"assertion failed".to_string()
+                    });
+                }
+            }
+            ":macro"=>{
+                let name = self.next()?;
+                let mut args = vec![];
+                loop {
+                    let arg = self.next()?;
+                    if arg == "{" {
+                        break;
+                    }
+                    args.push(arg);
+                }
+                let body = self.braced()?;
+                self.macros.insert(name,Macro{args,body});
+            }
+            ":stringmode"|":string"|":call"|":include"|":segment"=>{
+                return self.error(format!("'{}' is not supported",token));
+            }
+            "return"|";"=>self.op(Instruction::Ret)?,
+            "clear"=>self.op(Instruction::Cls)?,
+            "hires"=>self.op(Instruction::High)?,
+            "lores"=>self.op(Instruction::Low)?,
+            "exit"=>self.op(Instruction::Exit)?,
+            "scroll-left"=>self.op(Instruction::ScrollLeft)?,
+            "scroll-right"=>self.op(Instruction::ScrollRight)?,
+            "scroll-down"=>{
+                let n = self.nibble()?;
+                self.op(Instruction::ScrollDown(n))?;
+            }
+            "scroll-up"=>{
+                let n = self.nibble()?;
+                self.op(Instruction::ScrollUp(n))?;
+            }
+            "audio"=>self.op(Instruction::Audio)?,
+            "plane"=>{
+                let n = self.nibble()?;
+                self.op(Instruction::Plane(n))?;
+            }
+            "bcd"=>{
+                let x = self.register()?;
+                self.op(Instruction::Bcd(x))?;
+            }
+            "saveflags"=>{
+                let x = self.register()?;
+                self.op(Instruction::StoreFlags(x))?;
+            }
+            "loadflags"=>{
+                let x = self.register()?;
+                self.op(Instruction::LoadFlags(x))?;
+            }
+            "save"|"load"=>{
+                let x = self.register()?;
+                let save = token == "save";
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    self.op(if save {Instruction::SaveRange{x,y}} else {Instruction::LoadRange{x,y}})?;
+                } else {
+                    self.op(if save {Instruction::StoreRegs(x)} else {Instruction::LoadRegs(x)})?;
+                }
+            }
+            "sprite"=>{
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.op(Instruction::Drw{x,y,n})?;
+            }
+            "jump"|"jump0"|"native"=>{
+                let here = self.here;
+                let addr = self.address(Patch::Nnn,here)?;
+                self.op(match token.as_str() {
+                    "jump"=>Instruction::Jp(addr),
+                    "jump0"=>Instruction::JpV0(addr),
+                    _=>Instruction::Sys(addr)
+                })?;
+            }
+            "delay"|"buzzer"|"pitch"=>{
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.op(match token.as_str() {
+                    "delay"=>Instruction::LdDtVx(x),
+                    "buzzer"=>Instruction::LdStVx(x),
+                    _=>Instruction::Pitch(x)
+                })?;
+            }
+            "i"=>self.index_statement()?,
+            "if"=>{
+                let line = self.line;
+                let comparison = self.condition_tokens()?;
+                match self.next()?.as_str() {
+                    "then"=>self.condition(comparison,false)?,
+                    "begin"=>{
+                        self.condition(comparison,true)?;
+                        let jump = self.here;
+                        self.op(Instruction::Jp(0))?;
+                        self.blocks.push(Block::If{jump,line});
+                    }
+                    other=>return self.error(format!("expected 'then' or 'begin' but found '{}'",other))
+                }
+            }
+            "else"=>match self.blocks.pop() {
+                Some(Block::If{jump,line})=>{
+                    let skip = self.here;
+                    self.op(Instruction::Jp(0))?;
+                    let here = self.here;
+                    self.patch_jump(jump,here);
+                    self.blocks.push(Block::Else{jump:skip,line});
+                }
+                _=>return self.error("'else' without 'if ... begin'")
+            }
+            "end"=>match self.blocks.pop() {
+                Some(Block::If{jump,..})|Some(Block::Else{jump,..})=>{
+                    let here = self.here;
+                    self.patch_jump(jump,here);
+                }
+                _=>return self.error("'end' without 'if ... begin'")
+            }
+            "loop"=>{
+                let start = self.here;
+                let line = self.line;
+                self.blocks.push(Block::Loop{start,whiles:vec![],line});
+            }
+            "while"=>{
+                let comparison = self.condition_tokens()?;
+                self.condition(comparison,true)?;
+                let jump = self.here;
+                self.op(Instruction::Jp(0))?;
+                match self.blocks.iter_mut().rev().find(|b|matches!(b,Block::Loop{..})) {
+                    Some(Block::Loop{whiles,..})=>whiles.push(jump),
+                    _=>return self.error("'while' outside of a loop")
+                }
+            }
+            "again"=>match self.blocks.pop() {
+                Some(Block::Loop{start,whiles,..})=>{
+                    self.op(Instruction::Jp(start))?;
+                    let here = self.here;
+                    for jump in whiles {
+                        self.patch_jump(jump,here);
+                    }
+                }
+                _=>return self.error("'again' without 'loop'")
+            }
+            "then"|"begin"=>return self.error(format!("'{}' without 'if'",token)),
+            _=>{
+                if let Some(value) = number(&token).or_else(||self.constants.get(&token).map(|v|v.floor() as i64)) {
+                    let byte = self.sized(value,-128,255)? as u8;
+                    return self.emit_byte(byte);
+                }
+                if let Some(mac) = self.macros.get(&token).cloned() {
+                    return self.expand(&token,mac);
+                }
+                if token.starts_with(':') {
+                    return self.error(format!("unknown directive '{}'",token));
+                }
+                if ["+=","-=",":=","=-","|=","&=","^=",">>=","<<=","==","!="].contains(&token.as_str()) {
+                    return self.error(format!("'{}' needs a register on its left",token));
+                }
+                //anything else is a call to a label
+                let here = self.here;
+                self.tokens.push_front(Token{text:token,line:self.line});
+                let addr = self.address(Patch::Nnn,here)?;
+                self.op(Instruction::Call(addr))?;
+            }
+        }
+        Ok(())
+    }
+    fn expand(&mut self,name:&str,mac:Macro)->CResult<()> {
+        self.expansions+=1;
+        if self.expansions > C8_MAX_MACRO_EXPANSIONS {
+            return self.error(format!("macro '{}' expands forever",name));
+        }
+        let mut args = HashMap::new();
+        for arg in &mac.args {
+            let text = self.next()?;
+            args.insert(arg.clone(),Token{text,line:self.line});
+        }
+        //body tokens keep the line they were written on, arguments the line they were passed on
+        for token in mac.body.into_iter().rev() {
+            let token = args.get(&token.text).cloned().unwrap_or(token);
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+    fn index_statement(&mut self)->CResult<()> {
+        match self.next()?.as_str() {
+            ":="=>match self.peek() {
+                Some("long")=>{
+                    self.next()?;
+                    self.op(Instruction::LdILong(0))?;
+                    let here = self.here;
+                    let addr = self.address(Patch::Word,here)?;
+                    self.emit_word(addr)
+                }
+                Some("hex")=>{
+                    self.next()?;
+                    let x = self.register()?;
+                    self.op(Instruction::LdFont(x))
+                }
+                Some("bighex")=>{
+                    self.next()?;
+                    let x = self.register()?;
+                    self.op(Instruction::LdBigFont(x))
+                }
+                _=>{
+                    let here = self.here;
+                    let addr = self.address(Patch::Nnn,here)?;
+                    self.op(Instruction::LdI(addr))
+                }
+            }
+            "+="=>{
+                let x = self.register()?;
+                self.op(Instruction::AddI(x))
+            }
+            other=>self.error(format!("expected ':=' or '+=' after 'i' but found '{}'",other))
+        }
+    }
+    fn register_statement(&mut self,x:u8)->CResult<()> {
+        let operator = self.next()?;
+        match operator.as_str() {
+            ":="=>match self.peek() {
+                Some("key")=>{self.next()?;self.op(Instruction::LdKey(x))}
+                Some("delay")=>{self.next()?;self.op(Instruction::LdVxDt(x))}
+                Some("random")=>{
+                    self.next()?;
+                    let kk = self.byte()?;
+                    self.op(Instruction::Rnd{x,kk})
+                }
+                _ if self.is_register()=>{
+                    let y = self.register()?;
+                    self.op(Instruction::LdReg{x,y})
+                }
+                _=>{
+                    let kk = self.byte()?;
+                    self.op(Instruction::LdByte{x,kk})
+                }
+            }
+            "+="|"-=" if !self.is_register()=>{
+                let kk = self.byte()?;
+                let kk = if operator == "-=" {kk.wrapping_neg()} else {kk};
+                self.op(Instruction::AddByte{x,kk})
+            }
+            "+="|"-="|"=-"|"|="|"&="|"^="|">>="|"<<="=>{
+                let y = self.register()?;
+                self.op(match operator.as_str() {
+                    "+="=>Instruction::AddReg{x,y},
+                    "-="=>Instruction::Sub{x,y},
+                    "=-"=>Instruction::Subn{x,y},
+                    "|="=>Instruction::Or{x,y},
+                    "&="=>Instruction::And{x,y},
+                    "^="=>Instruction::Xor{x,y},
+                    ">>="=>Instruction::Shr{x,y},
+                    _=>Instruction::Shl{x,y},
+                })
+            }
+            _=>self.error(format!("unknown operator '{}'",operator))
+        }
+    }
+    ///Reads `vx op value`, returning the register, comparison and right hand side
+    fn condition_tokens(&mut self)->CResult<(u8,Comparison,Option<Operand>)> {
+        let x = self.register()?;
+        let comparison = match self.next()?.as_str() {
+            "=="=>Comparison::Eq,
+            "!="=>Comparison::Ne,
+            "<"=>Comparison::Lt,
+            ">"=>Comparison::Gt,
+            "<="=>Comparison::Le,
+            ">="=>Comparison::Ge,
+            "key"=>return Ok((x,Comparison::Key,None)),
+            "-key"=>return Ok((x,Comparison::NotKey,None)),
+            other=>return self.error(format!("unknown comparison '{}'",other))
+        };
+        let rhs = if self.is_register() {Operand::Register(self.register()?)} else {Operand::Byte(self.byte()?)};
+        Ok((x,comparison,Some(rhs)))
+    }
+    ///Emits a skip taken when the condition is skip_when
+    fn condition(&mut self,(x,comparison,rhs):(u8,Comparison,Option<Operand>),skip_when:bool)->CResult<()> {
+        let skip_equal = match comparison {
+            Comparison::Key=>return self.op(if skip_when {Instruction::Skp(x)} else {Instruction::Sknp(x)}),
+            Comparison::NotKey=>return self.op(if skip_when {Instruction::Sknp(x)} else {Instruction::Skp(x)}),
+            Comparison::Eq=>skip_when,
+            Comparison::Ne=>!skip_when,
+            _=>{
+                if x == 0xF {
+                    return self.error("vf can't be compared with < > <= or >=, it holds the result");
+                }
+                //vf := rhs, then subtract so vf ends up as the no-borrow flag
+                self.op(match rhs {
+                    Some(Operand::Register(y))=>Instruction::LdReg{x:0xF,y},
+                    Some(Operand::Byte(kk))=>Instruction::LdByte{x:0xF,kk},
+                    None=>unreachable!()
+                })?;
+                let (subtract,truth) = match comparison {
+                    Comparison::Lt=>(Instruction::Subn{x:0xF,y:x},0),
+                    Comparison::Gt=>(Instruction::Sub{x:0xF,y:x},0),
+                    Comparison::Le=>(Instruction::Sub{x:0xF,y:x},1),
+                    _=>(Instruction::Subn{x:0xF,y:x},1),
+                };
+                self.op(subtract)?;
+                return self.op(if skip_when {
+                    Instruction::SeByte{x:0xF,kk:truth}
+                } else {
+                    Instruction::SneByte{x:0xF,kk:truth}
+                });
+            }
+        };
+        self.op(match (rhs,skip_equal) {
+            (Some(Operand::Register(y)),true)=>Instruction::SeReg{x,y},
+            (Some(Operand::Register(y)),false)=>Instruction::SneReg{x,y},
+            (Some(Operand::Byte(kk)),true)=>Instruction::SeByte{x,kk},
+            (Some(Operand::Byte(kk)),false)=>Instruction::SneByte{x,kk},
+            (None,_)=>unreachable!()
+        })
+    }
+}
+
+///`:calc` expressions, evaluated right to left with no precedence like Octo does
+struct Calc<'c,'a> {
+    compiler:&'c Compiler<'a>,
+    tokens:VecDeque<Token>,
+}
+
+impl<'c,'a> Calc<'c,'a> {
+    fn error<T>(&self,message:impl Into<String>)->CResult<T> {
+        self.compiler.error(message)
+    }
+    fn next(&mut self)->CResult<String> {
+        match self.tokens.pop_front() {
+            Some(token)=>Ok(token.text),
+            None=>self.error("incomplete expression")
+        }
+    }
+    fn expr(&mut self)->CResult<f64> {
+        let lhs = self.term()?;
+        let op = match self.tokens.front() {
+            Some(token) if token.text != ")"=>self.next()?,
+            _=>return Ok(lhs)
+        };
+        let rhs = self.expr()?;
+        let (a,b) = (lhs as i64,rhs as i64);
+        Ok(match op.as_str() {
+            "+"=>lhs+rhs,
+            "-"=>lhs-rhs,
+            "*"=>lhs*rhs,
+            "/"=>lhs/rhs,
+            "%"=>lhs%rhs,
+            "&"=>(a&b) as f64,
+            "|"=>(a|b) as f64,
+            "^"=>(a^b) as f64,
+            "<<"=>a.wrapping_shl(b as u32) as f64,
+            ">>"=>a.wrapping_shr(b as u32) as f64,
+            "pow"=>lhs.powf(rhs),
+            "min"=>lhs.min(rhs),
+            "max"=>lhs.max(rhs),
+            "<"=>(lhs < rhs) as i64 as f64,
+            "<="=>(lhs <= rhs) as i64 as f64,
+            ">"=>(lhs > rhs) as i64 as f64,
+            ">="=>(lhs >= rhs) as i64 as f64,
+            "=="=>(lhs == rhs) as i64 as f64,
+            "!="=>(lhs != rhs) as i64 as f64,
+            _=>return self.error(format!("unknown operator '{}'",op))
+        })
+    }
+    fn term(&mut self)->CResult<f64> {
+        let token = self.next()?;
+        let unary = |f:fn(f64)->f64,calc:&mut Self|->CResult<f64>{Ok(f(calc.term()?))};
+        match token.as_str() {
+            "("=>{
+                let value = self.expr()?;
+                match self.next()?.as_str() {
+                    ")"=>Ok(value),
+                    _=>self.error("missing ')'")
+                }
+            }
+            "-"=>unary(|v|-v,self),
+            "~"=>unary(|v|!(v as i64) as f64,self),
+            "!"=>unary(|v|(v == 0.0) as i64 as f64,self),
+            "sin"=>unary(f64::sin,self),
+            "cos"=>unary(f64::cos,self),
+            "tan"=>unary(f64::tan,self),
+            "exp"=>unary(f64::exp,self),
+            "log"=>unary(f64::ln,self),
+            "abs"=>unary(f64::abs,self),
+            "sqrt"=>unary(f64::sqrt,self),
+            "sign"=>unary(|v|if v == 0.0 {0.0} else {v.signum()},self),
+            "ceil"=>unary(f64::ceil,self),
+            "floor"=>unary(f64::floor,self),
+            "@"=>{
+                let addr = self.term()? as i64;
+                let offset = addr-CHIP8_PROGRAM_START as i64;
+                match self.compiler.rom.get(offset as usize) {
+                    Some(byte) if offset >= 0=>Ok(*byte as f64),
+                    _=>Ok(0.0)
+                }
+            }
+            "PI"=>Ok(std::f64::consts::PI),
+            "E"=>Ok(std::f64::consts::E),
+            "HERE"=>Ok(self.compiler.here as f64),
+            _=>match self.compiler.known_value(&token) {
+                Some(value)=>Ok(value),
+                None=>self.error(format!("undefined name '{}' in expression",token))
+            }
+        }
+    }
+}
+
+///Compiles Octo source for XO-CHIP
+#[inline]
+pub fn compile(source:&str)->Result<Vec<u8>,SourceError> {
+    Ok(Octo::new().compile("<source>",source)?.bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::platform::Profile;
+
+    fn compile_error(source:&str)->SourceError {
+        Octo::new().compile("test.8o",source).unwrap_err()
+    }
+
+    fn run(source:&str)->Chip8 {
+        let rom = Octo::new().with_target(InstructionSet::Chip8).compile("test.8o",source).unwrap().bytes;
+        let mut chip8 = Chip8::create(Profile::default()).unwrap();
+        chip8.load_rom(&rom).unwrap();
+        chip8.start();
+        for _ in 0..4 {
+            chip8.run_frame().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn comparisons() {
+        //each true comparison sets its own register, v0 is 5 and v2 is 10
+        let chip8 = run("
+            : main
+            v0 := 5
+            v2 := 10
+            if v0 < 10 then v3 := 1
+            if v0 < 5 then v4 := 1
+            if v0 > 3 then v5 := 1
+            if v0 > 5 then v6 := 1
+            if v0 <= 5 then v7 := 1
+            if v0 <= 4 then v8 := 1
+            if v0 >= 5 then v9 := 1
+            if v0 >= 6 then va := 1
+            if v0 < v2 then vb := 1
+            if v0 >= v2 then vc := 1
+            loop again
+        ");
+        let results:Vec<u8> = (3..=0xC).map(|n|*chip8.cpu.register(n)).collect();
+        assert_eq!(results,[1,0,1,0,1,0,1,0,1,0]);
+    }
+
+    #[test]
+    fn macro_errors_point_into_the_macro() {
+        let source = "
+            :macro set-both A B {
+                v0 := A
+                v1 := B
+            }
+            : main
+            set-both 1
+              300
+        ";
+        let error = compile_error(source);
+        assert_eq!((error.line,error.message.as_str()),(8,"value 300 is out of range -128..255"));
+        let error = compile_error(&source.replace("300","3").replace("v1 := B","v1 := B vz"));
+        assert_eq!((error.line,error.message.as_str()),(4,"undefined label 'vz'"));
+    }
+
+    #[test]
+    fn loops_and_if_blocks() {
+        let chip8 = run("
+            : main
+            loop
+                v0 += 1
+                while v0 != 5
+                v1 += 2
+            again
+            if v0 == 5 begin
+                v2 := 1
+            else
+                v2 := 2
+            end
+            if v1 == 5 begin
+                v3 := 1
+            else
+                v3 := 2
+            end
+            if v1 == 8 begin v4 := 1 end
+            loop again
+        ");
+        assert_eq!(chip8.cpu.registery()[..5],[5,8,1,2,1]);
+    }
+
+    #[test]
+    fn macros_substitute_their_arguments() {
+        let chip8 = run("
+            :macro store-pair REG VALUE DEST {
+                REG := VALUE
+                DEST := REG
+            }
+            : main
+            store-pair v1 7 v2
+            store-pair v3 0x20 v4
+            loop again
+        ");
+        assert_eq!(chip8.cpu.registery()[1..5],[7,7,0x20,0x20]);
+    }
+
+    #[test]
+    fn calc_evaluates_right_to_left() {
+        let chip8 = run("
+            :const D 7
+            :calc A { 2 * 3 + 4 }
+            :calc B { 10 - 4 - 3 }
+            :calc C { ( 2 * 3 ) + 4 }
+            :calc E { D * 2 - 1 }
+            : main
+            v0 := A
+            v1 := B
+            v2 := C
+            v3 := E
+            loop again
+        ");
+        assert_eq!(chip8.cpu.registery()[..4],[14,9,10,7]);
+    }
+
+    #[test]
+    fn unpack_next_and_forward_labels() {
+        let program = Octo::new().compile("test.8o","
+            : main
+            jump later
+            :unpack 0xA data
+            : later
+            i := data
+            :next target
+            v2 := 5
+            : data
+            0xAB
+        ").unwrap();
+        assert_eq!(program.bytes,[0x12,0x06,0x60,0xA2,0x61,0x0A,0xA2,0x0A,0x62,0x05,0xAB]);
+        assert_eq!(program.labels["later"],0x206);
+        assert_eq!(program.labels["target"],0x209);
+        assert_eq!(program.labels["data"],0x20A);
+    }
+
+    #[test]
+    fn reports_unsupported_and_unbalanced_constructs() {
+        for directive in [":string",":stringmode",":call",":include",":segment"] {
+            let error = compile_error(&format!(": main\n{} data",directive));
+            assert_eq!((error.line,error.message),(2,format!("'{}' is not supported",directive)));
+        }
+        let cases = [
+            (": main\nif v0 == 1 begin\nv1 := 2\n",2,"'begin' without 'end'"),
+            (": main\nloop\nv0 += 1\n",2,"'loop' without 'again'"),
+            (": main\nv0 := 1\nelse\n",3,"'else' without 'if ... begin'"),
+            (": main\nagain\n",2,"'again' without 'loop'"),
+            (": main\nwhile v0 == 1\n",2,"'while' outside of a loop"),
+            (": main\n:bogus\n",2,"unknown directive ':bogus'"),
+            ("v0 := 1\n",1,"program has no ': main' label"),
+        ];
+        for (source,line,message) in cases {
+            let error = compile_error(source);
+            assert_eq!((error.line,error.message.as_str()),(line,message),"{}",source);
+        }
+        let error = Octo::new().with_target(InstructionSet::Chip8).compile("test.8o",": main\nhires\n").unwrap_err();
+        assert!(error.message.ends_with("needs the schip target"),"{}",error);
+    }
+}
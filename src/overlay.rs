@@ -0,0 +1,72 @@
+///Size of the RGBA panel drawn over the game, twice the lores display so it scales evenly
+pub const C8_OVERLAY_WIDTH:usize = 320;
+pub const C8_OVERLAY_HEIGHT:usize = 160;
+///Each glyph is 3x5 pixels with a pixel of spacing around it
+pub const C8_GLYPH_WIDTH:usize = 4;
+pub const C8_GLYPH_HEIGHT:usize = 6;
+pub const C8_OVERLAY_COLUMNS:usize = (C8_OVERLAY_WIDTH-C8_GLYPH_WIDTH)/C8_GLYPH_WIDTH;
+
+///3x5 glyphs for ' ' to '_', 15 bits each with the top left pixel highest
+pub const C8_OVERLAY_FONT:[u16;64] = [
+    0x0000,0x2482,0x5A00,0x5F7D,0x3C9E,0x52A5,0x2AAB,0x2400,
+    0x1491,0x4494,0x0AA8,0x05D0,0x0014,0x01C0,0x0002,0x12A4,
+    0x7B6F,0x2C97,0x73E7,0x72CF,0x5BC9,0x79CF,0x79EF,0x7292,
+    0x7BEF,0x7BCF,0x0410,0x0414,0x1511,0x0E38,0x4454,0x72C2,
+    0x2BE3,0x2BED,0x6BAE,0x3923,0x6B6E,0x79A7,0x79A4,0x396B,
+    0x5BED,0x7497,0x126A,0x5BAD,0x4927,0x5FED,0x6B6D,0x2B6A,
+    0x6BA4,0x2B73,0x6BAD,0x388E,0x7492,0x5B6F,0x5B6A,0x5BFD,
+    0x5AAD,0x5A92,0x72A7,0x3493,0x4889,0x6496,0x2A00,0x0007,
+];
+
+///The glyph for ch, lowercase is drawn as uppercase and anything else as '?'
+#[inline]
+pub fn glyph(ch:char)->u16 {
+    match ch.to_ascii_uppercase() {
+        ch @ ' '..='_'=>C8_OVERLAY_FONT[ch as usize-' ' as usize],
+        _=>C8_OVERLAY_FONT['?' as usize-' ' as usize]
+    }
+}
+
+///Breaks text into lines of at most columns characters, at spaces where possible
+pub fn wrap(text:&str,columns:usize)->Vec<String> {
+    let mut lines = vec![];
+    for line in text.lines() {
+        let mut rest = line;
+        while rest.chars().count() > columns {
+            let cut = rest.char_indices().nth(columns).map(|(n,_)|n).unwrap_or(rest.len());
+            let at = rest[..cut].rfind(' ').filter(|n|*n > 0).unwrap_or(cut);
+            lines.push(rest[..at].to_string());
+            rest = rest[at..].trim_start();
+        }
+        lines.push(rest.to_string());
+    }
+    lines
+}
+
+///Fills an RGBA buffer of C8_OVERLAY_WIDTH x C8_OVERLAY_HEIGHT with a translucent panel and text,
+///the first line is drawn in the highlight colour
+pub fn draw_panel(buffer:&mut [u8],pitch:usize,text:&str) {
+    for y in 0..C8_OVERLAY_HEIGHT {
+        for x in 0..C8_OVERLAY_WIDTH {
+            let offset = y*pitch+x*4;
+            buffer[offset..offset+4].copy_from_slice(&[0,0,0,210]);
+        }
+    }
+    let rows = C8_OVERLAY_HEIGHT/C8_GLYPH_HEIGHT;
+    for (row,line) in wrap(text,C8_OVERLAY_COLUMNS).iter().take(rows).enumerate() {
+        let color = if row == 0 {[255,85,85,255]} else {[255,255,255,255]};
+        for (col,ch) in line.chars().enumerate() {
+            let bits = glyph(ch);
+            for py in 0..5 {
+                for px in 0..3 {
+                    if (bits>>(14-py*3-px))&1 == 0 {
+                        continue;
+                    }
+                    let (x,y) = (C8_GLYPH_WIDTH+col*C8_GLYPH_WIDTH+px,1+row*C8_GLYPH_HEIGHT+py);
+                    let offset = y*pitch+x*4;
+                    buffer[offset..offset+4].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+}
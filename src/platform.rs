@@ -0,0 +1,145 @@
+use crate::chip8::InstructionSet;
+use crate::cpu::{CHIP8_MEM_SIZE,XOCHIP_MEM_SIZE,CHIP8_PROGRAM_START};
+use crate::fonts::{C8_CLASSIC_FONT,C8_VIP_FONT,C8_DREAM6800_FONT,C8_ETI660_FONT,C8_BIG_FONT};
+use crate::quirks::Quirks;
+
+pub const C8_DEFAULT_IPF:u32 = 16;
+
+///Machines and interpreters ROMs were commonly written for
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Platform {
+    Vip,
+    Chip48,
+    SChipLegacy,
+    SChipModern,
+    XoChip,
+    Eti660,
+    Dream6800,
+}
+
+///Cli name of every platform
+pub const C8_PLATFORMS:[(&str,Platform);7] = [
+    ("vip",Platform::Vip),
+    ("chip48",Platform::Chip48),
+    ("schip-legacy",Platform::SChipLegacy),
+    ("schip-modern",Platform::SChipModern),
+    ("xochip",Platform::XoChip),
+    ("eti660",Platform::Eti660),
+    ("dream6800",Platform::Dream6800),
+];
+
+impl Platform {
+    pub fn from_name(name:&str)->Option<Self> {
+        C8_PLATFORMS.iter().find(|(n,_)|*n == name).map(|(_,p)|*p)
+    }
+    pub fn name(&self)->&'static str {
+        C8_PLATFORMS.iter().find(|(_,p)|p == self).map(|(n,_)|*n).unwrap()
+    }
+    pub fn profile(&self)->Profile {
+        let base = Profile::default();
+        let schip_quirks = Quirks{
+            vf_reset:false,
+            shift_vy:false,
+            jump_vx:true,
+            index_overflow:false,
+            memory_increment:false,
+            sprite_wrap:false,
+            display_wait:false,
+            key_press:false,
+            stack_depth:16,
+        };
+        match self {
+            Platform::Vip=>Profile{
+                quirks:Quirks::cosmic(),
+                font:C8_VIP_FONT,
+                instructions_per_frame:15,
+                ..base
+            },
+            Platform::Chip48=>Profile{
+                quirks:schip_quirks,
+                instructions_per_frame:30,
+                ..base
+            },
+            Platform::SChipLegacy=>Profile{
+                quirks:Quirks{display_wait:true,..schip_quirks},
+                set:InstructionSet::SuperChip,
+                instructions_per_frame:30,
+                ..base
+            },
+            Platform::SChipModern=>Profile{
+                quirks:schip_quirks,
+                set:InstructionSet::SuperChip,
+                instructions_per_frame:30,
+                ..base
+            },
+            Platform::XoChip=>Profile{
+                quirks:Quirks{
+                    vf_reset:false,
+                    shift_vy:true,
+                    jump_vx:false,
+                    index_overflow:false,
+                    memory_increment:true,
+                    sprite_wrap:true,
+                    display_wait:false,
+                    key_press:false,
+                    stack_depth:16,
+                },
+                set:InstructionSet::XoChip,
+                memory_size:XOCHIP_MEM_SIZE,
+                instructions_per_frame:1000,
+                ..base
+            },
+            Platform::Eti660=>Profile{
+                quirks:Quirks::cosmic(),
+                font:C8_ETI660_FONT,
+                program_start:0x600,
+                instructions_per_frame:15,
+                ..base
+            },
+            Platform::Dream6800=>Profile{
+                quirks:Quirks::cosmic(),
+                font:C8_DREAM6800_FONT,
+                instructions_per_frame:15,
+                ..base
+            },
+        }
+    }
+}
+
+///Everything Chip8::create needs to know about the machine it emulates
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub struct Profile {
+    pub quirks:Quirks,
+    pub set:InstructionSet,
+    pub program_start:u16,
+    ///Bytes of memory, a power of two
+    pub memory_size:usize,
+    pub instructions_per_frame:u32,
+    pub font:&'static str,
+    pub big_font:&'static str,
+    ///Where both fonts are loaded, the big one right after the small one
+    pub font_base:u16,
+}
+
+impl Default for Profile {
+    #[inline]
+    fn default()->Self {
+        Self{
+            quirks:Quirks::default(),
+            set:InstructionSet::Chip8,
+            program_start:CHIP8_PROGRAM_START,
+            memory_size:CHIP8_MEM_SIZE,
+            instructions_per_frame:C8_DEFAULT_IPF,
+            font:C8_CLASSIC_FONT,
+            big_font:C8_BIG_FONT,
+            font_base:0x0,
+        }
+    }
+}
+
+impl From<Platform> for Profile {
+    #[inline]
+    fn from(platform:Platform)->Self {
+        platform.profile()
+    }
+}
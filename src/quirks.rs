@@ -0,0 +1,96 @@
+use crate::savestate::*;
+///Name and description of every boolean quirk, in the order the cli lists them
+pub const C8_QUIRK_FLAGS:[(&str,&str);8] = [
+    ("vf-reset","8XY1/8XY2/8XY3 reset VF to 0"),
+    ("shift-vy","8XY6/8XYE shift VY into VX instead of shifting VX in place"),
+    ("jump-vx","BXNN jumps to XNN+VX instead of NNN+V0"),
+    ("index-overflow","FX1E sets VF when I runs past 0xFFF"),
+    ("memory-increment","FX55/FX65 leave I pointing past the last register"),
+    ("sprite-wrap","sprites wrap around the screen edges instead of being clipped"),
+    ("display-wait","DXYN waits for the next 60hz vblank"),
+    ("key-press","FX0A takes a key as soon as it goes down instead of on its release"),
+];
+
+///Behaviours that differ between the interpreters ROMs were written for
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub struct Quirks {
+    pub vf_reset:bool,
+    pub shift_vy:bool,
+    pub jump_vx:bool,
+    pub index_overflow:bool,
+    pub memory_increment:bool,
+    pub sprite_wrap:bool,
+    pub display_wait:bool,
+    pub key_press:bool,
+    ///Maximum number of nested calls, 0 for no limit besides CHIP8_MAX_STACK_DEPTH
+    pub stack_depth:usize,
+}
+
+impl Default for Quirks {
+    #[inline]
+    fn default()->Self {
+        Self{
+            vf_reset:false,
+            shift_vy:false,
+            jump_vx:true,
+            index_overflow:true,
+            memory_increment:false,
+            sprite_wrap:false,
+            display_wait:false,
+            key_press:false,
+            stack_depth:0,
+        }
+    }
+}
+
+impl Quirks {
+    ///The original COSMAC VIP interpreter
+    #[inline]
+    pub fn cosmic()->Self {
+        Self{
+            vf_reset:true,
+            shift_vy:true,
+            jump_vx:false,
+            index_overflow:false,
+            memory_increment:true,
+            sprite_wrap:false,
+            display_wait:true,
+            key_press:false,
+            stack_depth:12,
+        }
+    }
+    ///Sets a quirk by its name in C8_QUIRK_FLAGS, false if there is no such quirk
+    pub fn set(&mut self,name:&str,on:bool)->bool {
+        let flag = match name {
+            "vf-reset"=>&mut self.vf_reset,
+            "shift-vy"=>&mut self.shift_vy,
+            "jump-vx"=>&mut self.jump_vx,
+            "index-overflow"=>&mut self.index_overflow,
+            "memory-increment"=>&mut self.memory_increment,
+            "sprite-wrap"=>&mut self.sprite_wrap,
+            "display-wait"=>&mut self.display_wait,
+            "key-press"=>&mut self.key_press,
+            _=>return false
+        };
+        *flag = on;
+        true
+    }
+}
+
+impl Snapshot for Quirks {
+    fn save(&self,out:&mut Vec<u8>) {
+        for on in [self.vf_reset,self.shift_vy,self.jump_vx,self.index_overflow,
+                   self.memory_increment,self.sprite_wrap,self.display_wait,self.key_press] {
+            put_bool(out,on);
+        }
+        put_u32(out,self.stack_depth as u32);
+    }
+    fn load(&mut self,input:&mut &[u8])->std::io::Result<()> {
+        for on in [&mut self.vf_reset,&mut self.shift_vy,&mut self.jump_vx,&mut self.index_overflow,
+                   &mut self.memory_increment,&mut self.sprite_wrap,&mut self.display_wait,&mut self.key_press] {
+            *on = get_bool(input)?;
+        }
+        self.stack_depth = get_u32(input)? as usize;
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+use crate::chip8::Chip8;
+
+///Frames kept by default, 30 seconds at 60hz
+pub const C8_REWIND_FRAMES:usize = 60*30;
+///A full snapshot is kept every this many frames, the rest are deltas against it
+pub const C8_REWIND_KEYFRAME_INTERVAL:usize = 60;
+///Unchanged bytes between two changes that still get merged into one run
+const C8_REWIND_RUN_GAP:usize = 8;
+
+///A save state stored as the bytes that differ from its keyframe
+struct Frame {
+    keyframe:Rc<Vec<u8>>,
+    len:usize,
+    runs:Vec<(usize,Vec<u8>)>,
+}
+
+impl Frame {
+    fn key(state:Vec<u8>)->Self {
+        let len = state.len();
+        Self{keyframe:Rc::new(state),len,runs:vec![]}
+    }
+    fn delta(keyframe:&Rc<Vec<u8>>,state:&[u8])->Self {
+        let mut runs:Vec<(usize,Vec<u8>)> = vec![];
+        for (n,byte) in state.iter().enumerate() {
+            if keyframe.get(n) == Some(byte) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((start,bytes)) if n-(*start+bytes.len()) <= C8_REWIND_RUN_GAP=>{
+                    let end = *start+bytes.len();
+                    bytes.extend_from_slice(&state[end..=n]);
+                }
+                _=>runs.push((n,vec![*byte]))
+            }
+        }
+        Self{keyframe:keyframe.clone(),len:state.len(),runs}
+    }
+    fn state(&self)->Vec<u8> {
+        let mut state = self.keyframe.as_ref().clone();
+        state.resize(self.len, 0);
+        for (start,bytes) in &self.runs {
+            state[*start..*start+bytes.len()].copy_from_slice(bytes);
+        }
+        state
+    }
+}
+
+///Ring of recent save states for stepping a game backwards frame by frame
+pub struct Rewind {
+    frames:VecDeque<Frame>,
+    capacity:usize,
+    keyframe_interval:usize,
+    since_keyframe:usize,
+}
+
+impl Default for Rewind {
+    #[inline]
+    fn default()->Self {
+        Self::new(C8_REWIND_FRAMES)
+    }
+}
+
+impl Rewind {
+    #[inline]
+    pub fn new(capacity:usize)->Self {
+        Self{
+            frames:VecDeque::with_capacity(capacity),
+            capacity:capacity.max(1),
+            keyframe_interval:C8_REWIND_KEYFRAME_INTERVAL,
+            since_keyframe:0,
+        }
+    }
+    #[inline]
+    pub fn with_keyframe_interval(mut self,n:usize)->Self {
+        self.keyframe_interval = n.max(1);
+        self
+    }
+    #[inline]
+    pub fn len(&self)->usize {
+        self.frames.len()
+    }
+    #[inline]
+    pub fn is_empty(&self)->bool {
+        self.frames.is_empty()
+    }
+    #[inline]
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.since_keyframe = 0;
+    }
+    ///Remembers a save state, dropping the oldest once full
+    pub fn push(&mut self,state:Vec<u8>) {
+        let keyframe = match self.frames.back() {
+            Some(last) if self.since_keyframe < self.keyframe_interval=>Some(last.keyframe.clone()),
+            _=>None
+        };
+        let frame = match keyframe {
+            Some(keyframe)=>{
+                self.since_keyframe+=1;
+                Frame::delta(&keyframe,&state)
+            }
+            None=>{
+                self.since_keyframe = 1;
+                Frame::key(state)
+            }
+        };
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+    ///Takes back the latest save state
+    pub fn pop(&mut self)->Option<Vec<u8>> {
+        let frame = self.frames.pop_back()?;
+        //the next push starts a new keyframe if this was one
+        self.since_keyframe = match self.frames.back() {
+            Some(last) if Rc::ptr_eq(&last.keyframe,&frame.keyframe)=>self.since_keyframe.saturating_sub(1),
+            _=>self.keyframe_interval
+        };
+        Some(frame.state())
+    }
+    ///Remembers the machine as it is now, call once per frame
+    #[inline]
+    pub fn record(&mut self,chip8:&Chip8) {
+        self.push(chip8.save_state())
+    }
+    ///Puts the machine back one recorded frame, false once there is nothing left
+    pub fn step_back(&mut self,chip8:&mut Chip8)->bool {
+        match self.pop() {
+            Some(state)=>chip8.load_state(&state).is_ok(),
+            None=>false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Profile;
+
+    fn state(n:u8,len:usize)->Vec<u8> {
+        (0..len).map(|i|if i%7 == 0 {n} else {i as u8}).collect()
+    }
+
+    #[test]
+    fn pops_come_back_newest_first() {
+        let mut rewind = Rewind::new(100).with_keyframe_interval(4);
+        for n in 0..10 {
+            //states of different lengths exercise the resize against the keyframe
+            rewind.push(state(n,64+n as usize));
+        }
+        for n in (0..10).rev() {
+            assert_eq!(rewind.pop(),Some(state(n,64+n as usize)));
+        }
+        assert_eq!(rewind.pop(),None);
+    }
+
+    #[test]
+    fn the_oldest_frames_are_dropped() {
+        let mut rewind = Rewind::new(5).with_keyframe_interval(3);
+        for n in 0..12 {
+            rewind.push(state(n,32));
+        }
+        assert_eq!(rewind.len(),5);
+        for n in (7..12).rev() {
+            assert_eq!(rewind.pop(),Some(state(n,32)));
+        }
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn pushing_after_popping_a_keyframe_starts_a_new_one() {
+        let mut rewind = Rewind::new(100).with_keyframe_interval(2);
+        for n in 0..3 {
+            rewind.push(state(n,16));
+        }
+        rewind.pop();
+        rewind.pop();
+        rewind.push(state(9,16));
+        rewind.push(state(10,16));
+        assert_eq!(rewind.pop(),Some(state(10,16)));
+        assert_eq!(rewind.pop(),Some(state(9,16)));
+        assert_eq!(rewind.pop(),Some(state(0,16)));
+    }
+
+    #[test]
+    fn stepping_back_restores_earlier_frames() {
+        //counts frames in V0
+        let mut chip8 = Chip8::create(Profile::default()).unwrap();
+        chip8.load_rom(&[0x70,0x01,0x12,0x00]).unwrap();
+        chip8.set_instructions_per_frame(2);
+        chip8.start();
+        let mut rewind = Rewind::default();
+        let mut states = vec![];
+        for _ in 0..90 {
+            chip8.run_frame().unwrap();
+            rewind.record(&chip8);
+            states.push(chip8.save_state());
+        }
+        states.pop();
+        rewind.step_back(&mut chip8);
+        for expected in states.iter().rev().take(70) {
+            assert!(rewind.step_back(&mut chip8));
+            assert!(chip8.save_state() == *expected);
+        }
+        assert_eq!(*chip8.cpu.register(0),20);
+    }
+}
@@ -0,0 +1,61 @@
+use std::io::Read;
+use byteorder::{BigEndian,ReadBytesExt,WriteBytesExt};
+
+pub const C8_SAVESTATE_MAGIC:&[u8;4] = b"C8SS";
+///Bumped whenever the layout changes, older states are rejected
+pub const C8_SAVESTATE_VERSION:u16 = 4;
+
+///A piece of the machine that can be written into, and read back from, a save state
+pub trait Snapshot {
+    fn save(&self,out:&mut Vec<u8>);
+    fn load(&mut self,input:&mut &[u8])->std::io::Result<()>;
+}
+
+#[inline]
+pub(crate) fn put_bool(out:&mut Vec<u8>,b:bool) {
+    out.push(b as u8)
+}
+#[inline]
+pub(crate) fn put_u16(out:&mut Vec<u8>,n:u16) {
+    let _ = out.write_u16::<BigEndian>(n);
+}
+#[inline]
+pub(crate) fn put_u32(out:&mut Vec<u8>,n:u32) {
+    let _ = out.write_u32::<BigEndian>(n);
+}
+#[inline]
+pub(crate) fn put_u64(out:&mut Vec<u8>,n:u64) {
+    let _ = out.write_u64::<BigEndian>(n);
+}
+#[inline]
+pub(crate) fn get_bool(input:&mut &[u8])->std::io::Result<bool> {
+    match input.read_u8()? {
+        0=>Ok(false),
+        1=>Ok(true),
+        _=>Err(invalid("bad boolean"))
+    }
+}
+#[inline]
+pub(crate) fn get_u8(input:&mut &[u8])->std::io::Result<u8> {
+    input.read_u8()
+}
+#[inline]
+pub(crate) fn get_u16(input:&mut &[u8])->std::io::Result<u16> {
+    input.read_u16::<BigEndian>()
+}
+#[inline]
+pub(crate) fn get_u32(input:&mut &[u8])->std::io::Result<u32> {
+    input.read_u32::<BigEndian>()
+}
+#[inline]
+pub(crate) fn get_u64(input:&mut &[u8])->std::io::Result<u64> {
+    input.read_u64::<BigEndian>()
+}
+#[inline]
+pub(crate) fn get_bytes(input:&mut &[u8],buffer:&mut [u8])->std::io::Result<()> {
+    input.read_exact(buffer)
+}
+#[inline]
+pub(crate) fn invalid(message:&str)->std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
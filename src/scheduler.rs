@@ -0,0 +1,162 @@
+use std::time::{Duration,Instant};
+use crate::chip8::C8_TIMER_RATE;
+
+///Frames run back to back before the scheduler gives up catching up
+pub const C8_MAX_FRAME_LAG:u32 = 4;
+
+///Where the scheduler gets the time from, the time is measured from any fixed point
+pub trait Clock {
+    fn now(&mut self)->Duration;
+}
+
+///Wall clock time
+pub struct SystemClock {
+    start:Instant,
+}
+
+impl SystemClock {
+    #[inline]
+    pub fn new()->Self {
+        Self{start:Instant::now()}
+    }
+}
+
+impl Default for SystemClock {
+    #[inline]
+    fn default()->Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&mut self)->Duration {
+        self.start.elapsed()
+    }
+}
+
+///Time that only moves when told to, for tests and headless runs
+#[derive(Clone,Copy,Default,Debug)]
+pub struct ManualClock {
+    now:Duration,
+}
+
+impl ManualClock {
+    #[inline]
+    pub fn new()->Self {
+        Self::default()
+    }
+    #[inline]
+    pub fn advance(&mut self,by:Duration) {
+        self.now+=by
+    }
+}
+
+impl Clock for ManualClock {
+    #[inline]
+    fn now(&mut self)->Duration {
+        self.now
+    }
+}
+
+///Decides how many 60hz frames are due, so the emulator itself never reads the time
+pub struct Scheduler<C:Clock> {
+    clock:C,
+    frame:Duration,
+    next:Duration,
+}
+
+impl<C:Clock> Scheduler<C> {
+    pub fn new(mut clock:C)->Self {
+        let next = clock.now();
+        Self{clock,frame:C8_TIMER_RATE,next}
+    }
+    #[inline]
+    pub fn with_frame_rate(mut self,frame:Duration)->Self {
+        self.frame = frame;
+        self
+    }
+    #[inline]
+    pub fn clock(&self)->&C {
+        &self.clock
+    }
+    #[inline]
+    pub fn clock_mut(&mut self)->&mut C {
+        &mut self.clock
+    }
+    ///Frames that should run now, after a long stall the missed frames are dropped
+    pub fn due_frames(&mut self)->u32 {
+        let now = self.clock.now();
+        let mut frames = 0;
+        while self.next <= now {
+            self.next+=self.frame;
+            frames+=1;
+            if frames == C8_MAX_FRAME_LAG {
+                if self.next <= now {
+                    self.next = now+self.frame;
+                }
+                break;
+            }
+        }
+        frames
+    }
+    ///Time left before the next frame is due
+    #[inline]
+    pub fn until_next(&mut self)->Duration {
+        let now = self.clock.now();
+        self.next.saturating_sub(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::keyboard::{Key,KeyEvent,KeyEventKind};
+    use crate::platform::Profile;
+
+    ///Draws random sprites, counting in V3 while key 5 is held
+    const ROM:[u8;27] = [
+        0x6A,0x3C, 0xFA,0x15, 0xC0,0x3F, 0xC1,0x1F, 0xA2,0x16, 0xD0,0x15, 0x62,0x05, 0xE2,0x9E,
+        0x12,0x04, 0x73,0x01, 0x12,0x04, 0xF0,0x90,0x90,0x90,0xF0,
+    ];
+
+    fn run()->(Vec<u8>,u64) {
+        let mut chip8 = Chip8::create(Profile::default()).unwrap();
+        chip8.load_rom(&ROM).unwrap();
+        chip8.start();
+        let five = Key::from_byte(0x5).unwrap();
+        chip8.queue_input_at(20,KeyEvent::new(five,KeyEventKind::KeyPress));
+        chip8.queue_input_at(25,KeyEvent::new(five,KeyEventKind::KeyRelease));
+        let mut scheduler = Scheduler::new(ManualClock::new());
+        for n in 0..200u64 {
+            //uneven steps, some long enough that frames are dropped
+            scheduler.clock_mut().advance(Duration::from_millis(n*37%90));
+            for _ in 0..scheduler.due_frames() {
+                chip8.run_frame().unwrap();
+            }
+        }
+        assert!(*chip8.cpu.register(3) > 0);
+        (chip8.save_state(),chip8.frame())
+    }
+
+    #[test]
+    fn manual_clock_runs_are_identical() {
+        let (first,frames) = run();
+        let (second,_) = run();
+        assert!(frames > 25);
+        assert!(first == second);
+    }
+
+    #[test]
+    fn long_stalls_drop_frames() {
+        let mut scheduler = Scheduler::new(ManualClock::new());
+        assert_eq!(scheduler.due_frames(),1);
+        scheduler.clock_mut().advance(C8_TIMER_RATE*2);
+        assert_eq!(scheduler.due_frames(),2);
+        scheduler.clock_mut().advance(C8_TIMER_RATE*100);
+        assert_eq!(scheduler.due_frames(),C8_MAX_FRAME_LAG);
+        assert_eq!(scheduler.due_frames(),0);
+        assert_eq!(scheduler.until_next(),C8_TIMER_RATE);
+    }
+}
@@ -0,0 +1,190 @@
+use sdl2::{EventPump,Sdl};
+use sdl2::audio::{AudioCallback,AudioDevice,AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode,Mod};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode,Texture,TextureCreator,WindowCanvas};
+use sdl2::video::WindowContext;
+use crate::audio::{AudioSettings,NullSink,Synth};
+use crate::frontend::{AudioSink,Display,Input,InputSource,color};
+use crate::graphics::{PixMap,C8_HIRES_WIDTH,C8_HIRES_HEIGHT};
+use crate::keyboard::{KeyEvent,KeyEventKind};
+use crate::keymap::Keymap;
+use crate::overlay::{draw_panel,C8_OVERLAY_WIDTH,C8_OVERLAY_HEIGHT};
+use crate::sound::Sound;
+
+///Window pixels per chip8 pixel when the window opens
+pub const C8_WINDOW_SCALE:u32 = 10;
+
+///A resizable window, the screen, input and audio are all taken from it
+pub struct SdlWindow {
+    context:Sdl,
+    canvas:WindowCanvas,
+    creator:TextureCreator<WindowContext>,
+}
+
+impl SdlWindow {
+    ///Opens a window C8_WINDOW_SCALE times the size of a width x height display
+    pub fn open(title:&str,(width,height):(usize,usize))->Result<Self,String> {
+        let context = sdl2::init()?;
+        let window = context.video()?
+            .window(title,width as u32*C8_WINDOW_SCALE,height as u32*C8_WINDOW_SCALE)
+            .resizable()
+            .position_centered()
+            .opengl()
+            .build()
+            .map_err(|e|e.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|e|e.to_string())?;
+        let creator = canvas.texture_creator();
+        Ok(Self{context,canvas,creator})
+    }
+    ///SDL only hands out one event pump at a time
+    pub fn input(&self,keymap:Keymap)->Result<SdlInput,String> {
+        Ok(SdlInput{events:self.context.event_pump()?,keymap})
+    }
+    ///Opens the default output device, running silently if there isn't one
+    pub fn audio(&self,settings:AudioSettings)->Box<dyn AudioSink> {
+        let spec = AudioSpecDesired{freq:Some(44_100),channels:Some(1),samples:Some(512)};
+        let device = self.context.audio().and_then(|audio|{
+            audio.open_playback(None,&spec,|spec|Beeper(Synth::new(spec.freq as u32,settings)))
+        });
+        match device {
+            Ok(device)=>{
+                device.resume();
+                Box::new(SdlAudio(device))
+            }
+            Err(e)=>{
+                println!("No sound: {}",e);
+                Box::new(NullSink)
+            }
+        }
+    }
+    pub fn screen(&mut self)->Result<SdlScreen<'_>,String> {
+        let texture = self.creator
+            .create_texture_streaming(PixelFormatEnum::RGB24,C8_HIRES_WIDTH as u32,C8_HIRES_HEIGHT as u32)
+            .map_err(|e|e.to_string())?;
+        let mut overlay = self.creator
+            .create_texture_streaming(PixelFormatEnum::RGBA32,C8_OVERLAY_WIDTH as u32,C8_OVERLAY_HEIGHT as u32)
+            .map_err(|e|e.to_string())?;
+        overlay.set_blend_mode(BlendMode::Blend);
+        Ok(SdlScreen{canvas:&mut self.canvas,texture,overlay,size:(0,0)})
+    }
+}
+
+///Draws the display stretched over the whole window
+pub struct SdlScreen<'a> {
+    canvas:&'a mut WindowCanvas,
+    texture:Texture<'a>,
+    overlay:Texture<'a>,
+    ///Window size at the last redraw, resizing forces another
+    size:(u32,u32),
+}
+
+impl Display for SdlScreen<'_> {
+    fn present(&mut self,pix:&mut PixMap,panel:Option<&str>)->Result<(),String> {
+        let size = self.canvas.output_size()?;
+        if pix.ready() || panel.is_some() || size != self.size {
+            self.size = size;
+            self.canvas.clear();
+            let (width,height) = (pix.width(),pix.height());
+            self.texture.with_lock(None,|buffer:&mut [u8],pitch:usize|{
+                for y in 0..height {
+                    for x in 0..width {
+                        let offset = y*pitch+x*3;
+                        let (r,g,b) = color(pix.get(x as u8,y as u8));
+                        buffer[offset] = r;
+                        buffer[offset+1] = g;
+                        buffer[offset+2] = b;
+                    }
+                }
+            })?;
+            self.canvas.copy(&self.texture,Some(Rect::new(0,0,width as u32,height as u32)),None)?;
+            pix.flush();
+            //the crash panel goes over the frozen game
+            if let Some(text) = panel {
+                self.overlay.with_lock(None,|buffer:&mut [u8],pitch:usize|draw_panel(buffer,pitch,text))?;
+                self.canvas.copy(&self.overlay,None,None)?;
+            }
+        }
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+///Keyboard and window events, keys are looked up by the name SDL gives them in the current layout
+pub struct SdlInput {
+    events:EventPump,
+    keymap:Keymap,
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self)->Vec<Input> {
+        let mut inputs = vec![];
+        let keymap = &self.keymap;
+        for event in self.events.poll_iter() {
+            let input = match event {
+                Event::Quit{..}|Event::KeyDown{keycode:Some(Keycode::Escape),..}=>Input::Quit,
+                Event::KeyDown{keycode:Some(Keycode::Return),repeat:false,..}=>Input::Confirm,
+                Event::KeyDown{keycode:Some(Keycode::Backspace),..}=>Input::Rewind(true),
+                Event::KeyUp{keycode:Some(Keycode::Backspace),..}=>Input::Rewind(false),
+                Event::KeyDown{keycode:Some(keycode),keymod,repeat:false,..} if state_slot(keycode).is_some()=>{
+                    let slot = state_slot(keycode).unwrap();
+                    if keymod.intersects(Mod::LSHIFTMOD|Mod::RSHIFTMOD) {
+                        Input::LoadState(slot)
+                    } else {
+                        Input::SaveState(slot)
+                    }
+                }
+                Event::KeyDown{keycode:Some(keycode),..}=>match key_event(keymap,keycode,KeyEventKind::KeyPress) {
+                    Some(key)=>Input::Key(key),
+                    None=>continue
+                },
+                Event::KeyUp{keycode:Some(keycode),..}=>match key_event(keymap,keycode,KeyEventKind::KeyRelease) {
+                    Some(key)=>Input::Key(key),
+                    None=>continue
+                },
+                _=>continue
+            };
+            inputs.push(input);
+        }
+        inputs
+    }
+}
+
+///Feeds SDL's audio thread from a Synth
+struct Beeper(Synth);
+
+impl AudioCallback for Beeper {
+    type Channel = f32;
+    fn callback(&mut self,out:&mut [f32]) {
+        self.0.fill(out)
+    }
+}
+
+struct SdlAudio(AudioDevice<Beeper>);
+
+impl AudioSink for SdlAudio {
+    fn update(&mut self,sound:&Sound) {
+        self.0.lock().0.update(sound)
+    }
+}
+
+///F1-F8 pick a save state slot
+fn state_slot(key:Keycode)->Option<u8> {
+    Some(match key {
+        Keycode::F1=>1,
+        Keycode::F2=>2,
+        Keycode::F3=>3,
+        Keycode::F4=>4,
+        Keycode::F5=>5,
+        Keycode::F6=>6,
+        Keycode::F7=>7,
+        Keycode::F8=>8,
+        _=>return None
+    })
+}
+#[inline]
+fn key_event(keymap:&Keymap,keycode:Keycode,kind:KeyEventKind)->Option<KeyEvent> {
+    Some(KeyEvent::new(keymap.key(&keycode.name())?,kind))
+}
@@ -1,12 +1,22 @@
+use crate::savestate::*;
+pub const C8_PATTERN_SIZE:usize = 16;
+pub const C8_DEFAULT_PITCH:u8 = 64;
+
+///Beeper state, XO-CHIP programs can swap the plain tone for a looping
+///128 bit sample played back at a rate picked by the pitch register
 #[derive(Clone,Copy)]
 pub struct Sound {
     is_running:bool,
+    pattern:Option<[u8;C8_PATTERN_SIZE]>,
+    pitch:u8,
 }
 
 impl Sound {
     pub fn pack()->Self{
         Self{
             is_running:false,
+            pattern:None,
+            pitch:C8_DEFAULT_PITCH,
         }
     }
     pub fn play(&mut self) {
@@ -29,5 +39,52 @@ impl Sound {
             self.pause()
         }
     }
+    ///XO-CHIP F002
+    #[inline]
+    pub fn load_pattern(&mut self,pattern:[u8;C8_PATTERN_SIZE]){
+        self.pattern = Some(pattern)
+    }
+    #[inline]
+    pub fn pattern(&self)->Option<&[u8;C8_PATTERN_SIZE]>{
+        self.pattern.as_ref()
+    }
+    ///XO-CHIP FX3A
+    #[inline]
+    pub fn set_pitch(&mut self,pitch:u8){
+        self.pitch = pitch
+    }
+    #[inline]
+    pub fn pitch(&self)->u8{
+        self.pitch
+    }
+    ///Pattern bits played per second, 4000*2^((pitch-64)/48)
+    #[inline]
+    pub fn playback_rate(&self)->f64{
+        4000.0*2f64.powf((self.pitch as f64-64.0)/48.0)
+    }
+    ///The pattern bit at position, wrapping around the 128 bit loop
+    #[inline]
+    pub fn sample(&self,position:usize)->Option<bool>{
+        let pattern = self.pattern.as_ref()?;
+        let bit = position%(C8_PATTERN_SIZE*8);
+        Some(pattern[bit/8]&(0x80>>(bit%8))>0)
+    }
 }
 
+impl Snapshot for Sound {
+    fn save(&self,out:&mut Vec<u8>) {
+        put_bool(out,self.is_running);
+        out.push(self.pitch);
+        put_bool(out,self.pattern.is_some());
+        out.extend_from_slice(&self.pattern.unwrap_or([0;C8_PATTERN_SIZE]));
+    }
+    fn load(&mut self,input:&mut &[u8])->std::io::Result<()> {
+        self.is_running = get_bool(input)?;
+        self.pitch = get_u8(input)?;
+        let has_pattern = get_bool(input)?;
+        let mut pattern = [0u8;C8_PATTERN_SIZE];
+        get_bytes(input,&mut pattern)?;
+        self.pattern = if has_pattern {Some(pattern)} else {None};
+        Ok(())
+    }
+}
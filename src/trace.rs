@@ -0,0 +1,222 @@
+use std::fmt;
+use std::io::{self,Write};
+use crate::chip8::Chip8;
+use crate::errors::Result;
+use crate::instruction::Instruction;
+
+///The machine just before an instruction runs
+struct Before {
+    pc:u16,
+    opcode:u16,
+    instruction:Option<Instruction>,
+    registers:[u8;16],
+}
+
+///Writes one tab separated line per executed instruction:
+///cycle, PC, opcode, disassembly, the registers it changed and I afterwards
+pub struct Tracer<W:Write> {
+    out:W,
+    cycle:u64,
+    before:Option<Before>,
+    error:Option<io::Error>,
+}
+
+impl<W:Write> Tracer<W> {
+    #[inline]
+    pub fn new(out:W)->Self {
+        Self{out,cycle:0,before:None,error:None}
+    }
+    ///Instructions logged so far
+    #[inline]
+    pub fn cycle(&self)->u64 {
+        self.cycle
+    }
+    ///Remembers the machine before the next instruction runs
+    pub fn before(&mut self,chip8:&Chip8) {
+        let mut registers = [0;16];
+        registers.copy_from_slice(&chip8.cpu.registery()[..16]);
+        let pc = chip8.cpu.program_counter();
+        self.before = Some(Before{pc,opcode:chip8.cpu.instruction(),instruction:chip8.decode_at(pc).ok(),registers});
+    }
+    ///Logs the instruction that ran since before
+    pub fn after(&mut self,chip8:&Chip8) {
+        let before = match self.before.take() {
+            Some(before)=>before,
+            None=>return
+        };
+        let text = match before.instruction {
+            Some(instruction)=>instruction.to_string(),
+            None=>format!("db #{:02X}, #{:02X}",before.opcode>>8,before.opcode&0xFF)
+        };
+        let changes:Vec<String> = chip8.cpu.registery().iter().zip(before.registers.iter()).enumerate()
+            .filter(|(_,(now,was))|now != was)
+            .map(|(n,(now,_))|format!("V{:X}={:02X}",n,now))
+            .collect();
+        if self.error.is_none() {
+            let line = writeln!(self.out,"{}\t{:04X}\t{:04X}\t{}\t{}\tI={:04X}",
+                                self.cycle,before.pc,before.opcode,text,changes.join(" "),chip8.cpu.register_i());
+            self.error = line.err();
+        }
+        self.cycle+=1;
+    }
+    ///Chip8::run_frame, logging every instruction
+    pub fn run_frame(&mut self,chip8:&mut Chip8)->Result<()> {
+        if !chip8.cpu_state().is_running() {
+            return chip8.run_frame();
+        }
+        loop {
+            self.before(chip8);
+            let step = chip8.frame_step()?;
+            if step.ran {
+                self.after(chip8);
+            } else {
+                self.before = None;
+            }
+            if step.ended {
+                return Ok(());
+            }
+        }
+    }
+    #[inline]
+    pub fn into_inner(self)->W {
+        self.out
+    }
+    ///Flushes the log, reporting the first write that failed
+    pub fn flush(&mut self)->io::Result<()> {
+        match self.error.take() {
+            Some(e)=>Err(e),
+            None=>self.out.flush()
+        }
+    }
+}
+
+///One parsed line of a trace
+#[derive(Clone,PartialEq,Debug)]
+pub struct TraceLine {
+    pub cycle:u64,
+    pub pc:u16,
+    pub opcode:u16,
+    pub text:String,
+    pub changes:String,
+    pub i:u16,
+}
+
+impl TraceLine {
+    pub fn parse(line:&str)->Option<Self> {
+        let fields:Vec<&str> = line.split('\t').collect();
+        if fields.len() != 6 {
+            return None;
+        }
+        Some(Self{
+            cycle:fields[0].trim().parse().ok()?,
+            pc:u16::from_str_radix(fields[1].trim(),16).ok()?,
+            opcode:u16::from_str_radix(fields[2].trim(),16).ok()?,
+            text:fields[3].to_string(),
+            changes:fields[4].trim().to_string(),
+            i:u16::from_str_radix(fields[5].trim().strip_prefix("I=")?,16).ok()?,
+        })
+    }
+    ///The fields that differ, the cycle number and disassembly aren't compared
+    ///so traces from other emulators line up
+    pub fn differences(&self,other:&TraceLine)->Vec<&'static str> {
+        let mut fields = vec![];
+        if self.pc != other.pc {
+            fields.push("PC");
+        }
+        if self.opcode != other.opcode {
+            fields.push("opcode");
+        }
+        if self.changes != other.changes {
+            fields.push("registers");
+        }
+        if self.i != other.i {
+            fields.push("I");
+        }
+        fields
+    }
+}
+
+impl fmt::Display for TraceLine {
+    fn fmt(&self,f:&mut fmt::Formatter)->fmt::Result {
+        write!(f,"{}\t{:04X}\t{:04X}\t{}\t{}\tI={:04X}",self.cycle,self.pc,self.opcode,self.text,self.changes,self.i)
+    }
+}
+
+///Where two traces first disagree
+#[derive(Clone,PartialEq,Debug)]
+pub struct Divergence {
+    ///Instructions both traces agree on
+    pub matched:usize,
+    ///Up to C8_TRACE_CONTEXT agreeing lines just before the divergence
+    pub context:Vec<TraceLine>,
+    ///None where a trace has already ended
+    pub a:Option<TraceLine>,
+    pub b:Option<TraceLine>,
+}
+
+///Agreeing lines shown before a divergence
+pub const C8_TRACE_CONTEXT:usize = 4;
+
+impl fmt::Display for Divergence {
+    fn fmt(&self,f:&mut fmt::Formatter)->fmt::Result {
+        let fields = match (&self.a,&self.b) {
+            (Some(a),Some(b))=>a.differences(b).join(", "),
+            _=>"length".to_string()
+        };
+        writeln!(f,"traces diverge after {} matching instructions ({} differs)",self.matched,fields)?;
+        for line in &self.context {
+            writeln!(f,"  {}",line)?;
+        }
+        for (name,line) in [("a",&self.a),("b",&self.b)] {
+            match line {
+                Some(line)=>writeln!(f,"{} {}",name,line)?,
+                None=>writeln!(f,"{} <end of trace>",name)?
+            }
+        }
+        Ok(())
+    }
+}
+
+///Compares two traces line by line, lines that aren't trace lines are skipped
+pub fn diff(a:&str,b:&str)->Option<Divergence> {
+    let mut a = a.lines().filter_map(TraceLine::parse);
+    let mut b = b.lines().filter_map(TraceLine::parse);
+    let mut context = vec![];
+    let mut matched = 0;
+    loop {
+        let (x,y) = (a.next(),b.next());
+        let same = match (&x,&y) {
+            (Some(x),Some(y))=>x.differences(y).is_empty(),
+            (None,None)=>return None,
+            _=>false
+        };
+        if !same {
+            return Some(Divergence{matched,context,a:x,b:y});
+        }
+        if context.len() == C8_TRACE_CONTEXT {
+            context.remove(0);
+        }
+        context.extend(x);
+        matched+=1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocked_fx0a_is_logged_once_with_its_key() {
+        let mut chip8 = Chip8::waiting_for_key();
+        let mut tracer = Tracer::new(vec![]);
+        for _ in 0..7 {
+            tracer.run_frame(&mut chip8).unwrap();
+        }
+        let text = String::from_utf8(tracer.into_inner()).unwrap();
+        let lines:Vec<TraceLine> = text.lines().map(|line|TraceLine::parse(line).unwrap()).collect();
+        assert_eq!(lines[0].opcode,0xA300);
+        assert_eq!((lines[1].pc,lines[1].opcode,lines[1].changes.as_str()),(0x202,0xF00A,"V0=05"));
+        assert_eq!((lines[2].pc,lines[2].opcode),(0x204,0xF055));
+        assert_eq!(lines.iter().filter(|line|line.opcode == 0xF00A).count(),1);
+    }
+}
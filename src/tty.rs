@@ -0,0 +1,417 @@
+use std::io::{self,Read,Write};
+use std::time::{Duration,Instant};
+use crate::frontend::{Display,Input,InputSource,Status,color};
+use crate::graphics::PixMap;
+use crate::keyboard::{Key,KeyEvent,KeyEventKind};
+use crate::keymap::Keymap;
+use crate::overlay::wrap;
+
+///Terminals only send key presses, so a key is held until it stops repeating for this long
+pub const C8_TTY_KEY_HOLD:Duration = Duration::from_millis(150);
+///Bit of each dot in a braille cell, by row then column
+pub const C8_BRAILLE_DOTS:[[u32;2];4] = [[0x01,0x08],[0x02,0x10],[0x04,0x20],[0x40,0x80]];
+
+///Raw, unechoed input on the alternate screen with the cursor hidden, put back when dropped
+pub struct Terminal {
+    original:libc::termios,
+}
+
+impl Terminal {
+    pub fn open()->io::Result<Self> {
+        let mut termios = unsafe{std::mem::zeroed::<libc::termios>()};
+        if unsafe{libc::tcgetattr(libc::STDIN_FILENO,&mut termios)} != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let original = termios;
+        termios.c_lflag &= !(libc::ICANON|libc::ECHO|libc::ISIG|libc::IEXTEN);
+        termios.c_iflag &= !(libc::IXON|libc::ICRNL);
+        //reads return straight away with whatever has arrived
+        termios.c_cc[libc::VMIN] = 0;
+        termios.c_cc[libc::VTIME] = 0;
+        if unsafe{libc::tcsetattr(libc::STDIN_FILENO,libc::TCSANOW,&termios)} != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut out = io::stdout();
+        //the alternate screen, no cursor and the keypad sending its own sequences
+        write!(out,"\x1b[?1049h\x1b[?25l\x1b=\x1b[2J")?;
+        out.flush()?;
+        Ok(Self{original})
+    }
+    ///Columns and rows
+    pub fn size()->Option<(usize,usize)> {
+        let mut size = unsafe{std::mem::zeroed::<libc::winsize>()};
+        if unsafe{libc::ioctl(libc::STDOUT_FILENO,libc::TIOCGWINSZ,&mut size)} != 0 || size.ws_col == 0 {
+            return None;
+        }
+        Some((size.ws_col as usize,size.ws_row as usize))
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        let _ = write!(out,"\x1b[0m\x1b[?25h\x1b>\x1b[?1049l");
+        let _ = out.flush();
+        unsafe{libc::tcsetattr(libc::STDIN_FILENO,libc::TCSANOW,&self.original)};
+    }
+}
+
+///How pixels are packed into characters
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Cells {
+    ///▀ coloured with the top pixel and backed with the bottom one, 1x2 pixels a cell
+    HalfBlock,
+    ///Braille dots in a single colour, 2x4 pixels a cell
+    Braille,
+}
+
+impl Cells {
+    ///Half blocks when a width x height display fits in columns x rows, braille otherwise
+    pub fn fit(width:usize,height:usize,columns:usize,rows:usize)->Self {
+        let (w,h) = Cells::HalfBlock.size(width,height);
+        if w <= columns && h <= rows {Cells::HalfBlock} else {Cells::Braille}
+    }
+    ///Columns and rows taken by a width x height display
+    pub fn size(&self,width:usize,height:usize)->(usize,usize) {
+        match self {
+            Cells::HalfBlock=>(width,height.div_ceil(2)),
+            Cells::Braille=>(width.div_ceil(2),height.div_ceil(4)),
+        }
+    }
+    ///The display as lines of ANSI coloured text, colours are only set where they change
+    pub fn render(&self,pix:&PixMap)->Vec<String> {
+        self.render_pixels(pix.width(),pix.height(),|x,y|pix.get(x as u8,y as u8))
+    }
+    ///Renders width x height pixels read from get, which is only asked for pixels inside them
+    pub fn render_pixels(&self,width:usize,height:usize,get:impl Fn(usize,usize)->u8)->Vec<String> {
+        let get = |x:usize,y:usize|if x < width && y < height {get(x,y)&3} else {0};
+        let (columns,rows) = self.size(width,height);
+        let mut lines = Vec::with_capacity(rows);
+        for row in 0..rows {
+            let mut line = String::new();
+            let mut last = None;
+            for col in 0..columns {
+                let (fg,bg,ch) = match self {
+                    Cells::HalfBlock=>(get(col,row*2),get(col,row*2+1),'▀'),
+                    Cells::Braille=>{
+                        let (mut dots,mut shade) = (0,0);
+                        for (dy,bits) in C8_BRAILLE_DOTS.iter().enumerate() {
+                            for (dx,bit) in bits.iter().enumerate() {
+                                let pixel = get(col*2+dx,row*4+dy);
+                                if pixel != 0 {
+                                    dots|=bit;
+                                    shade = if shade == 0 {pixel} else {shade};
+                                }
+                            }
+                        }
+                        (shade,0,char::from_u32(0x2800+dots).unwrap_or(' '))
+                    }
+                };
+                if last != Some((fg,bg)) {
+                    let ((r,g,b),(br,bgg,bb)) = (color(fg),color(bg));
+                    line.push_str(&format!("\x1b[38;2;{};{};{};48;2;{};{};{}m",r,g,b,br,bgg,bb));
+                    last = Some((fg,bg));
+                }
+                line.push(ch);
+            }
+            line.push_str("\x1b[0m");
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+///Draws the display with a status line under it, only rewriting the rows that changed
+pub struct TtyScreen {
+    ///Rows on the terminal right now
+    lines:Vec<String>,
+    size:Option<(usize,usize)>,
+    status:String,
+    drawn_status:String,
+    drawn_panel:Option<String>,
+}
+
+impl TtyScreen {
+    #[inline]
+    pub fn new()->Self {
+        Self{lines:vec![],size:None,status:String::new(),drawn_status:String::new(),drawn_panel:None}
+    }
+}
+
+impl Default for TtyScreen {
+    #[inline]
+    fn default()->Self {
+        Self::new()
+    }
+}
+
+impl Display for TtyScreen {
+    fn status(&mut self,status:&Status) {
+        self.status = format!("PC {:04X}  IPS {:<6}  sound {}{}  esc: quit",
+                              status.pc,status.ips,if status.beeping {"on "} else {"off"},if status.paused {"  paused"} else {""});
+    }
+    fn present(&mut self,pix:&mut PixMap,panel:Option<&str>)->Result<(),String> {
+        let size = Terminal::size().unwrap_or((80,24));
+        let mut text = String::new();
+        let cells = Cells::fit(pix.width(),pix.height(),size.0,size.1.saturating_sub(1));
+        let lines = if pix.ready() || Some(size) != self.size || self.lines.is_empty() {
+            pix.flush();
+            Some(cells.render(pix))
+        } else {
+            None
+        };
+        if let Some(lines) = lines {
+            //resizing or switching resolution leaves stale rows around, start over
+            if Some(size) != self.size || lines.len() != self.lines.len() {
+                text.push_str("\x1b[0m\x1b[2J");
+                self.size = Some(size);
+                self.lines = vec![String::new();lines.len()];
+                self.drawn_status.clear();
+                self.drawn_panel = None;
+            }
+            for (row,line) in lines.into_iter().enumerate() {
+                if self.lines[row] != line {
+                    text.push_str(&format!("\x1b[{};1H{}\x1b[K",row+1,line));
+                    self.lines[row] = line;
+                }
+            }
+        }
+        if self.status != self.drawn_status {
+            text.push_str(&format!("\x1b[{};1H\x1b[0m{}\x1b[K",self.lines.len()+1,self.status));
+            self.drawn_status = self.status.clone();
+        }
+        //the crash panel goes over the frozen game
+        if panel != self.drawn_panel.as_deref() {
+            if let Some(panel) = panel {
+                for (row,line) in wrap(panel,size.0.max(1)).iter().enumerate() {
+                    let color = if row == 0 {"\x1b[1;31m"} else {""};
+                    text.push_str(&format!("\x1b[{};1H\x1b[0m{}{}\x1b[0m\x1b[K",row+1,color,line));
+                    if let Some(line) = self.lines.get_mut(row) {
+                        line.clear();
+                    }
+                }
+            }
+            self.drawn_panel = panel.map(String::from);
+        }
+        if !text.is_empty() {
+            let mut out = io::stdout();
+            out.write_all(text.as_bytes()).and_then(|_|out.flush()).map_err(|e|e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+///Something the terminal can only press, released once it stops repeating
+#[derive(Copy,Clone,PartialEq)]
+enum Held {
+    Key(Key),
+    Rewind,
+}
+
+impl Held {
+    fn input(&self,down:bool)->Input {
+        match self {
+            Held::Key(key)=>Input::Key(KeyEvent::new(*key,if down {KeyEventKind::KeyPress} else {KeyEventKind::KeyRelease})),
+            Held::Rewind=>Input::Rewind(down),
+        }
+    }
+}
+
+///Keys read from the raw terminal, Backspace rewinds, F1-F8 save and Shift+F1-F8 load
+pub struct TtyInput {
+    keymap:Keymap,
+    held:Vec<(Held,Instant)>,
+}
+
+impl TtyInput {
+    #[inline]
+    pub fn new(keymap:Keymap)->Self {
+        Self{keymap,held:vec![]}
+    }
+    fn hold(&mut self,held:Held,inputs:&mut Vec<Input>) {
+        let now = Instant::now();
+        match self.held.iter_mut().find(|(h,_)|*h == held) {
+            Some((_,at))=>*at = now,
+            None=>{
+                self.held.push((held,now));
+                inputs.push(held.input(true));
+            }
+        }
+    }
+}
+
+impl InputSource for TtyInput {
+    fn poll(&mut self)->Vec<Input> {
+        let mut inputs = vec![];
+        let mut buffer = [0;64];
+        let n = io::stdin().read(&mut buffer).unwrap_or(0);
+        for key in parse_keys(&buffer[..n]) {
+            match key {
+                TermKey::Quit=>inputs.push(Input::Quit),
+                TermKey::Confirm=>inputs.push(Input::Confirm),
+                TermKey::Rewind=>self.hold(Held::Rewind,&mut inputs),
+                TermKey::Function(slot,true)=>inputs.push(Input::LoadState(slot)),
+                TermKey::Function(slot,false)=>inputs.push(Input::SaveState(slot)),
+                TermKey::Key(name)=>if let Some(key) = self.keymap.key(&name) {
+                    self.hold(Held::Key(key),&mut inputs)
+                },
+            }
+        }
+        let now = Instant::now();
+        self.held.retain(|(held,at)|{
+            let expired = now.duration_since(*at) >= C8_TTY_KEY_HOLD;
+            if expired {
+                inputs.push(held.input(false));
+            }
+            !expired
+        });
+        inputs
+    }
+}
+
+///A key as the terminal sent it, before the keymap turns it into a keypad key
+#[derive(Clone,PartialEq,Debug)]
+pub enum TermKey {
+    ///Escape or Ctrl+C
+    Quit,
+    ///Enter
+    Confirm,
+    ///Backspace
+    Rewind,
+    ///F1-F8 and whether shift was held
+    Function(u8,bool),
+    ///A key named like the keymap names it
+    Key(String),
+}
+
+///Splits what one read returned into keys, escape sequences and characters like é arrive as several bytes but are one key
+pub fn parse_keys(bytes:&[u8])->Vec<TermKey> {
+    let mut keys = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            0x1B if i+1 < bytes.len() && (bytes[i+1] == b'[' || bytes[i+1] == b'O')=>{
+                //escape sequences end with a byte from @ to ~
+                let mut end = i+2;
+                while end < bytes.len() && !(0x40..=0x7E).contains(&bytes[end]) {
+                    end+=1;
+                }
+                keys.extend(escape(&bytes[i+1..(end+1).min(bytes.len())]));
+                i = end;
+            }
+            0x1B|0x03=>keys.push(TermKey::Quit),
+            b'\r'|b'\n'=>keys.push(TermKey::Confirm),
+            0x7F|0x08=>keys.push(TermKey::Rewind),
+            byte=>{
+                let len = match byte {
+                    0xC0..=0xDF=>2,
+                    0xE0..=0xEF=>3,
+                    0xF0..=0xF7=>4,
+                    _=>1
+                };
+                let end = (i+len).min(bytes.len());
+                if let Ok(name) = std::str::from_utf8(&bytes[i..end]) {
+                    keys.push(TermKey::Key(name.to_string()));
+                }
+                i = end-1;
+            }
+        }
+        i+=1;
+    }
+    keys
+}
+
+///Reads a sequence from after the escape, like `OP`, `[15~`, `[1;2P`, `[A` or, for the keypad, `Op`
+fn escape(sequence:&[u8])->Option<TermKey> {
+    let (last,body) = sequence.get(1..)?.split_last()?;
+    let params:Vec<u32> = std::str::from_utf8(body).ok()?.split(';').filter_map(|p|p.parse().ok()).collect();
+    let shift = params.get(1) == Some(&2);
+    let key = |name:&str|TermKey::Key(name.to_string());
+    Some(match last {
+        b'P'..=b'S'=>TermKey::Function(last-b'P'+1,shift),
+        b'~' if sequence[0] == b'['=>match params.first()? {
+            n @ 11..=15=>TermKey::Function((n-10) as u8,shift),
+            n @ 17..=19=>TermKey::Function((n-11) as u8,shift),
+            _=>return None
+        },
+        b'A'=>key("up"),
+        b'B'=>key("down"),
+        b'C'=>key("right"),
+        b'D'=>key("left"),
+        //the keypad in application mode
+        b'p'..=b'y' if sequence[0] == b'O'=>TermKey::Key(format!("kp{}",last-b'p')),
+        b'M' if sequence[0] == b'O'=>key("kpenter"),
+        b'k' if sequence[0] == b'O'=>key("kp+"),
+        b'm' if sequence[0] == b'O'=>key("kp-"),
+        b'j' if sequence[0] == b'O'=>key("kp*"),
+        b'o' if sequence[0] == b'O'=>key("kp/"),
+        b'n' if sequence[0] == b'O'=>key("kp."),
+        _=>return None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name:&str)->TermKey {
+        TermKey::Key(name.to_string())
+    }
+
+    fn paint(fg:u8,bg:u8)->String {
+        let ((r,g,b),(br,bgg,bb)) = (color(fg),color(bg));
+        format!("\x1b[38;2;{};{};{};48;2;{};{};{}m",r,g,b,br,bgg,bb)
+    }
+
+    #[test]
+    fn plain_bytes_are_keys_and_controls() {
+        assert_eq!(parse_keys(b"q1\r\x7f\x03"),[key("q"),key("1"),TermKey::Confirm,TermKey::Rewind,TermKey::Quit]);
+        //a lone escape is the escape key
+        assert_eq!(parse_keys(b"\x1b"),[TermKey::Quit]);
+        assert_eq!(parse_keys("é&".as_bytes()),[key("é"),key("&")]);
+        //a character cut off by the end of the read is dropped
+        assert_eq!(parse_keys(&"é".as_bytes()[..1]),[]);
+    }
+
+    #[test]
+    fn escape_sequences_are_one_key() {
+        assert_eq!(parse_keys(b"\x1b[A\x1b[Dx"),[key("up"),key("left"),key("x")]);
+        assert_eq!(parse_keys(b"\x1bOp\x1bOy\x1bOM\x1bOk"),[key("kp0"),key("kp9"),key("kpenter"),key("kp+")]);
+        //home isn't anything
+        assert_eq!(parse_keys(b"\x1b[Hq"),[key("q")]);
+    }
+
+    #[test]
+    fn function_keys_save_and_shifted_load() {
+        assert_eq!(parse_keys(b"\x1bOP\x1bOS"),[TermKey::Function(1,false),TermKey::Function(4,false)]);
+        assert_eq!(parse_keys(b"\x1b[15~\x1b[17~\x1b[19~"),[TermKey::Function(5,false),TermKey::Function(6,false),TermKey::Function(8,false)]);
+        assert_eq!(parse_keys(b"\x1b[1;2P\x1b[15;2~"),[TermKey::Function(1,true),TermKey::Function(5,true)]);
+        //F12 isn't a slot
+        assert_eq!(parse_keys(b"\x1b[24~"),[]);
+    }
+
+    #[test]
+    fn cells_fit_the_terminal() {
+        assert_eq!(Cells::HalfBlock.size(64,33),(64,17));
+        assert_eq!(Cells::Braille.size(128,64),(64,16));
+        assert_eq!(Cells::fit(64,32,80,23),Cells::HalfBlock);
+        assert_eq!(Cells::fit(128,64,80,23),Cells::Braille);
+    }
+
+    #[test]
+    fn half_blocks_pair_rows_and_only_change_colour_when_needed() {
+        //a 3x3 display, the last row gets a black bottom half
+        let pixels = [[1,1,0],[1,1,1],[2,0,0]];
+        let lines = Cells::HalfBlock.render_pixels(3,3,|x,y|pixels[y][x]);
+        assert_eq!(lines,[
+            format!("{}▀▀{}▀\x1b[0m",paint(1,1),paint(0,1)),
+            format!("{}▀{}▀▀\x1b[0m",paint(2,0),paint(0,0)),
+        ]);
+    }
+
+    #[test]
+    fn braille_sets_a_dot_per_pixel() {
+        let lines = Cells::Braille.render_pixels(2,4,|x,y|if (x,y) == (0,0) || (x,y) == (1,3) {3} else {0});
+        assert_eq!(lines,[format!("{}\u{2881}\x1b[0m",paint(3,0))]);
+    }
+}
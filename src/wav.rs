@@ -0,0 +1,143 @@
+use std::io::{self,Seek,SeekFrom,Write};
+use byteorder::{LittleEndian,WriteBytesExt};
+use crate::audio::{AudioSettings,AudioSink,Synth};
+use crate::chip8::C8_TIMER_RATE;
+use crate::sound::Sound;
+
+pub const C8_WAV_SAMPLE_RATE:u32 = 44_100;
+///Bytes before the first sample
+pub const C8_WAV_HEADER_SIZE:u32 = 44;
+
+///Writes mono 16 bit PCM, the sizes in the header are filled in by finish
+pub struct WavWriter<W:Write+Seek> {
+    out:W,
+    sample_rate:u32,
+    samples:u32,
+}
+
+impl<W:Write+Seek> WavWriter<W> {
+    pub fn new(out:W,sample_rate:u32)->io::Result<Self> {
+        let mut wav = Self{out,sample_rate,samples:0};
+        wav.header()?;
+        Ok(wav)
+    }
+    fn header(&mut self)->io::Result<()> {
+        let data = self.samples*2;
+        self.out.write_all(b"RIFF")?;
+        self.out.write_u32::<LittleEndian>(C8_WAV_HEADER_SIZE-8+data)?;
+        self.out.write_all(b"WAVEfmt ")?;
+        self.out.write_u32::<LittleEndian>(16)?;
+        //PCM, one channel
+        self.out.write_u16::<LittleEndian>(1)?;
+        self.out.write_u16::<LittleEndian>(1)?;
+        self.out.write_u32::<LittleEndian>(self.sample_rate)?;
+        self.out.write_u32::<LittleEndian>(self.sample_rate*2)?;
+        self.out.write_u16::<LittleEndian>(2)?;
+        self.out.write_u16::<LittleEndian>(16)?;
+        self.out.write_all(b"data")?;
+        self.out.write_u32::<LittleEndian>(data)
+    }
+    ///Appends samples from -1 to 1
+    pub fn write(&mut self,samples:&[f32])->io::Result<()> {
+        for sample in samples {
+            self.out.write_i16::<LittleEndian>((sample.clamp(-1.0,1.0)*i16::MAX as f32) as i16)?;
+        }
+        self.samples+=samples.len() as u32;
+        Ok(())
+    }
+    #[inline]
+    pub fn samples(&self)->u32 {
+        self.samples
+    }
+    ///Fills in the header's sizes
+    pub fn finish(mut self)->io::Result<W> {
+        self.out.seek(SeekFrom::Start(0))?;
+        self.header()?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+///Records the beeper to a WAV file, every update renders one emulated frame of audio
+///so the recording follows the game rather than the wall clock
+pub struct WavRecorder<W:Write+Seek> {
+    synth:Synth,
+    wav:WavWriter<W>,
+    frames:u64,
+    buffer:Vec<f32>,
+    error:Option<io::Error>,
+}
+
+impl<W:Write+Seek> WavRecorder<W> {
+    pub fn new(out:W,settings:AudioSettings)->io::Result<Self> {
+        Ok(Self{
+            synth:Synth::new(C8_WAV_SAMPLE_RATE,settings),
+            wav:WavWriter::new(out,C8_WAV_SAMPLE_RATE)?,
+            frames:0,
+            buffer:vec![],
+            error:None,
+        })
+    }
+    ///Frames recorded so far
+    #[inline]
+    pub fn frames(&self)->u64 {
+        self.frames
+    }
+    ///Samples due by the end of frame, counted from the start so rounding never drifts
+    fn samples_until(frame:u64)->u64 {
+        (frame as u128*C8_WAV_SAMPLE_RATE as u128*C8_TIMER_RATE.as_nanos()/1_000_000_000) as u64
+    }
+    ///Finishes the file, reporting the first write that failed
+    pub fn finish(mut self)->io::Result<W> {
+        match self.error.take() {
+            Some(e)=>Err(e),
+            None=>self.wav.finish()
+        }
+    }
+}
+
+impl<W:Write+Seek> AudioSink for WavRecorder<W> {
+    fn update(&mut self,sound:&Sound) {
+        self.synth.update(sound);
+        let n = Self::samples_until(self.frames+1)-Self::samples_until(self.frames);
+        self.buffer.resize(n as usize,0.0);
+        self.synth.fill(&mut self.buffer);
+        if self.error.is_none() {
+            self.error = self.wav.write(&self.buffer).err();
+        }
+        self.frames+=1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u32_at(bytes:&[u8],offset:usize)->u32 {
+        u32::from_le_bytes([bytes[offset],bytes[offset+1],bytes[offset+2],bytes[offset+3]])
+    }
+
+    #[test]
+    fn finish_fills_in_the_header_sizes() {
+        let mut recorder = WavRecorder::new(Cursor::new(vec![]),AudioSettings::default()).unwrap();
+        let mut beeping = Sound::pack();
+        beeping.play();
+        recorder.update(&Sound::pack());
+        recorder.update(&beeping);
+        recorder.silence();
+        assert_eq!(recorder.frames(),3);
+        let bytes = recorder.finish().unwrap().into_inner();
+        //3 frames of 1/60s at 44.1khz, 16 bits each
+        let data = 2205*2;
+        assert_eq!(bytes.len(),C8_WAV_HEADER_SIZE as usize+data);
+        assert_eq!((&bytes[..4],u32_at(&bytes,4)),(&b"RIFF"[..],C8_WAV_HEADER_SIZE-8+data as u32));
+        assert_eq!(&bytes[8..16],b"WAVEfmt ");
+        assert_eq!(u32_at(&bytes,24),C8_WAV_SAMPLE_RATE);
+        assert_eq!((&bytes[36..40],u32_at(&bytes,40)),(&b"data"[..],data as u32));
+        let samples = &bytes[C8_WAV_HEADER_SIZE as usize..];
+        assert!(samples[..735*2].iter().all(|b|*b == 0));
+        assert!(samples[735*2..1470*2].iter().any(|b|*b != 0));
+    }
+}